@@ -62,4 +62,54 @@ pub enum PerpetualsError {
     PermissionlessOracleSignerMismatch,
     #[msg("Signed message does not match instruction params")]
     PermissionlessOracleMessageMismatch,
+    #[msg("Custody is halted by the price circuit breaker")]
+    CircuitBreakerHalted,
+    #[msg("Open interest limit exceeded")]
+    MaxOpenInterest,
+    #[msg("Open interest skew limit exceeded")]
+    MaxSkew,
+    #[msg("Custody has enough liquidity, auto-deleveraging is not allowed")]
+    AutoDeleverageNotAllowed,
+    #[msg("A position with a higher auto-deleveraging score must be reduced first")]
+    AutoDeleverageRank,
+    #[msg("Invalid referral account")]
+    InvalidReferral,
+    #[msg("Withdrawal cooldown has not passed")]
+    WithdrawalCooldown,
+    #[msg("Pool withdrawal limit for the current epoch exceeded")]
+    WithdrawalLimit,
+    #[msg("Primary and secondary oracle prices diverge")]
+    OraclePriceDivergence,
+    #[msg("Oracle price was not published after the order request")]
+    OrderPriceNotUpdated,
+    #[msg("Order request has expired")]
+    OrderExpired,
+    #[msg("Order request has not expired yet")]
+    OrderNotExpired,
+    #[msg("Order fee exceeds the escrowed fee budget")]
+    OrderFeeBudgetExceeded,
+    #[msg("Signer is not the owner or an authorized delegate")]
+    InvalidDelegation,
+    #[msg("Position index must match the owner position counter")]
+    InvalidPositionIndex,
+    #[msg("Queued multisig instruction can't be executed before the timelock has passed")]
+    MultisigTimelockNotExpired,
+    #[msg("Another multisig instruction is already queued")]
+    MultisigInstructionQueued,
+    #[msg("Multisig proposal has expired")]
+    ProposalExpired,
+    #[msg("Multisig proposal is bound to a different instruction")]
+    ProposalInstructionMismatch,
+    #[msg("Multisig proposal is still pending")]
+    InvalidProposalState,
+    #[msg("Multisig signers have changed since the proposal was signed")]
+    ProposalSignersChanged,
+    #[msg("Multisig account has already been upgraded")]
+    InvalidMultisigState,
+    #[msg("Account is not authorized as guardian")]
+    GuardianAccountNotAuthorized,
+    #[msg("Staked LP tokens are still locked")]
+    LpStakeLocked,
+    #[msg("Oracle price was published after the order fill window")]
+    OrderFillWindowMissed,
 }
\ No newline at end of file
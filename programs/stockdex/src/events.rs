@@ -0,0 +1,474 @@
+use {
+    crate::state::{
+        order_request::OrderType,
+        perps::Permissions,
+        pool::{FeeTier, WithdrawalParams},
+        position::Side,
+    },
+    anchor_lang::prelude::*,
+};
+
+#[event]
+pub struct AutoDeleverageEvent {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub collateral_custody: Pubkey,
+    pub position: Pubkey,
+    pub side: Side,
+    pub size_usd: u64,
+    pub collateral_usd: u64,
+    pub entry_price: u64,
+    pub exit_price: u64,
+    pub profit_usd: u64,
+    pub loss_usd: u64,
+    pub score: u128,
+    pub amount_out: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct ReportAdlCandidateEvent {
+    pub reporter: Pubkey,
+    pub collateral_custody: Pubkey,
+    pub position: Pubkey,
+    pub score: u128,
+    pub time: i64,
+}
+
+#[event]
+pub struct OpenPositionEvent {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub collateral_custody: Pubkey,
+    pub position: Pubkey,
+    pub side: Side,
+    pub entry_price: u64,
+    pub size_usd: u64,
+    pub collateral_usd: u64,
+    pub collateral_amount: u64,
+    pub fee_amount: u64,
+    pub fee_usd: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct ClosePositionEvent {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub collateral_custody: Pubkey,
+    pub position: Pubkey,
+    pub side: Side,
+    pub entry_price: u64,
+    pub exit_price: u64,
+    pub size_usd: u64,
+    pub collateral_usd: u64,
+    pub profit_usd: u64,
+    pub loss_usd: u64,
+    pub fee_amount: u64,
+    pub fee_usd: u64,
+    pub amount_out: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct LiquidateEvent {
+    pub owner: Pubkey,
+    pub liquidator: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub collateral_custody: Pubkey,
+    pub position: Pubkey,
+    pub side: Side,
+    pub entry_price: u64,
+    pub size_usd: u64,
+    pub collateral_usd: u64,
+    pub profit_usd: u64,
+    pub loss_usd: u64,
+    pub fee_amount: u64,
+    pub fee_usd: u64,
+    pub amount_out: u64,
+    pub reward: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct AddCollateralEvent {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub collateral_custody: Pubkey,
+    pub position: Pubkey,
+    pub side: Side,
+    pub amount_in: u64,
+    pub amount_in_usd: u64,
+    pub collateral_usd: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct RemoveCollateralEvent {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub collateral_custody: Pubkey,
+    pub position: Pubkey,
+    pub side: Side,
+    pub amount_out: u64,
+    pub amount_out_usd: u64,
+    pub collateral_usd: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct TransferPositionEvent {
+    pub owner: Pubkey,
+    pub new_owner: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub position: Pubkey,
+    pub new_position: Pubkey,
+    pub side: Side,
+    pub size_usd: u64,
+    pub collateral_usd: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct AddLiquidityEvent {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub amount_in: u64,
+    pub fee_amount: u64,
+    pub lp_amount_out: u64,
+    pub aum_usd: u128,
+    pub time: i64,
+}
+
+#[event]
+pub struct RemoveLiquidityEvent {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub lp_amount_in: u64,
+    pub fee_amount: u64,
+    pub amount_out: u64,
+    pub amount_out_usd: u64,
+    pub aum_usd: u128,
+    pub time: i64,
+}
+
+#[event]
+pub struct RequestOrderEvent {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub collateral_custody: Pubkey,
+    pub position: Pubkey,
+    pub order_type: OrderType,
+    pub side: Side,
+    pub price: u64,
+    pub collateral: u64,
+    pub size: u64,
+    pub escrow_amount: u64,
+    pub expiry_time: i64,
+    pub time: i64,
+}
+
+#[event]
+pub struct ExecuteOrderEvent {
+    pub owner: Pubkey,
+    pub keeper: Pubkey,
+    pub position: Pubkey,
+    pub order_type: OrderType,
+    // false if the circuit breaker tripped and the escrow was refunded
+    pub filled: bool,
+    pub refund_amount: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct CancelOrderEvent {
+    pub owner: Pubkey,
+    pub position: Pubkey,
+    pub order_type: OrderType,
+    pub refund_amount: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct RequestRemoveLiquidityEvent {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub lp_amount: u64,
+    pub min_amount_out: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct CancelRemoveLiquidityEvent {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub lp_amount: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct StakeLpEvent {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub total_staked: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct UnstakeLpEvent {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub total_staked: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct ClaimLpStakingRewardsEvent {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct DistributeLpStakingRewardsEvent {
+    pub pool: Pubkey,
+    pub reward_custody: Pubkey,
+    pub amount: u64,
+    pub total_staked: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct InitLpStakingEvent {
+    pub pool: Pubkey,
+    pub reward_custody: Pubkey,
+    pub time: i64,
+}
+
+#[event]
+pub struct SetReferrerEvent {
+    pub referrer: Pubkey,
+    pub is_approved: bool,
+    pub time: i64,
+}
+
+#[event]
+pub struct CreateReferralEvent {
+    pub trader: Pubkey,
+    pub referrer: Pubkey,
+    pub time: i64,
+}
+
+#[event]
+pub struct ClaimReferralRewardsEvent {
+    pub referrer: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub amount: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct InitReferralRewardsEvent {
+    pub referrer: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub time: i64,
+}
+
+#[event]
+pub struct SetDelegationEvent {
+    pub owner: Pubkey,
+    pub delegate: Pubkey,
+    pub can_open: bool,
+    pub can_close: bool,
+    pub can_adjust_collateral: bool,
+    pub max_size_usd: u64,
+    pub expiry_time: i64,
+    pub time: i64,
+}
+
+#[event]
+pub struct RevokeDelegationEvent {
+    pub owner: Pubkey,
+    pub delegate: Pubkey,
+    pub time: i64,
+}
+
+#[event]
+pub struct RecordDividendEvent {
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub dividend_per_share: u64,
+    pub ex_date: i64,
+    pub time: i64,
+}
+
+#[event]
+pub struct CircuitBreakerTripEvent {
+    pub pool: Pubkey,
+    pub mint: Pubkey,
+    pub price: u64,
+    pub reference_price: u64,
+    pub price_move: u128,
+    pub halted_until: i64,
+    pub time: i64,
+}
+
+#[event]
+pub struct ClearCircuitBreakerEvent {
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub time: i64,
+}
+
+#[event]
+pub struct InitOracleHistoryEvent {
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub time: i64,
+}
+
+#[event]
+pub struct UpdateOracleHistoryEvent {
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub price: u64,
+    pub exponent: i32,
+    pub twap_price: u64,
+    pub twap_exponent: i32,
+    pub time: i64,
+}
+
+#[event]
+pub struct SetAdminSignersEvent {
+    pub signers: Vec<Pubkey>,
+    pub weights: Vec<u8>,
+    pub min_weight: u8,
+    pub time: i64,
+}
+
+#[event]
+pub struct UpgradeMultisigEvent {
+    pub payer: Pubkey,
+    pub time: i64,
+}
+
+#[event]
+pub struct SetTimelockEvent {
+    pub timelock_sec: u32,
+    pub time: i64,
+}
+
+#[event]
+pub struct QueueAdminInstructionEvent {
+    pub instruction_hash: [u8; 32],
+    // None if the instruction is queued in the multisig itself
+    pub proposal_id: Option<u64>,
+    pub queued_eta: i64,
+    pub time: i64,
+}
+
+#[event]
+pub struct CancelQueuedInstructionEvent {
+    // None if the instruction queued in the multisig itself was cancelled
+    pub proposal_id: Option<u64>,
+    pub time: i64,
+}
+
+#[event]
+pub struct CreateProposalEvent {
+    pub id: u64,
+    pub proposer: Pubkey,
+    pub expiry_time: i64,
+    pub time: i64,
+}
+
+#[event]
+pub struct CloseProposalEvent {
+    pub id: u64,
+    pub proposer: Pubkey,
+    pub executed: bool,
+    pub time: i64,
+}
+
+#[event]
+pub struct SetPermissionsEvent {
+    // None for the global permissions
+    pub custody: Option<Pubkey>,
+    pub permissions: Permissions,
+    pub time: i64,
+}
+
+#[event]
+pub struct SetGuardianEvent {
+    pub guardian: Pubkey,
+    pub time: i64,
+}
+
+#[event]
+pub struct GuardianPauseEvent {
+    pub guardian: Pubkey,
+    // None for the global permissions
+    pub custody: Option<Pubkey>,
+    pub pause_open_position: bool,
+    pub pause_swap: bool,
+    pub pause_add_liquidity: bool,
+    pub time: i64,
+}
+
+#[event]
+pub struct AddPoolEvent {
+    pub pool: Pubkey,
+    pub name: String,
+    pub lp_token_mint: Pubkey,
+    pub time: i64,
+}
+
+#[event]
+pub struct SetFeeTiersEvent {
+    pub pool: Pubkey,
+    pub fee_tiers: Vec<FeeTier>,
+    pub time: i64,
+}
+
+#[event]
+pub struct SetWithdrawalParamsEvent {
+    pub pool: Pubkey,
+    pub withdrawal: WithdrawalParams,
+    pub time: i64,
+}
+
+#[event]
+pub struct AddInsuranceFundEvent {
+    pub funder: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub amount: u64,
+    pub swept_amount: u64,
+    pub time: i64,
+}
+
+#[event]
+pub struct WithdrawInsuranceFundEvent {
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub receiving_account: Pubkey,
+    pub amount: u64,
+    pub time: i64,
+}
@@ -0,0 +1,608 @@
+//! ReportAdlCandidate and AutoDeleverage instruction handlers
+
+use {
+    crate::{
+        error::PerpetualsError,
+        events::{AutoDeleverageEvent, ReportAdlCandidateEvent},
+        math,
+        state::{
+            custody::{AdlState, Custody},
+            oracle::OraclePrice,
+            perps::Perpetuals,
+            pool::Pool,
+            position::{Position, Side},
+            user_stats::UserStats,
+        },
+    },
+    anchor_lang::prelude::*,
+    anchor_spl::token::{Token, TokenAccount},
+};
+
+/**
+ * Permissionless instruction that records a profitable position as the auto-deleveraging
+ * candidate of its collateral custody if it outranks the currently reported one.
+ */
+#[derive(Accounts)]
+pub struct ReportAdlCandidate<'info> {
+    pub signer: Signer<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"position",
+                 position.owner.as_ref(),
+                 pool.key().as_ref(),
+                 custody.key().as_ref(),
+                 &[position.side as u8],
+                 Position::get_index_seed(position.index).as_slice()],
+        bump = position.bump
+    )]
+    pub position: Box<Account<'info, Position>>,
+
+    #[account(
+        constraint = position.custody == custody.key()
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the position token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = position.collateral_custody == collateral_custody.key()
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the collateral token
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+
+    /// CHECK: secondary oracle account for the position token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    /// CHECK: secondary oracle account for the collateral token, required when configured
+    pub collateral_custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct ReportAdlCandidateParams {}
+
+pub fn report_adl_candidate(
+    ctx: Context<ReportAdlCandidate>,
+    _params: &ReportAdlCandidateParams,
+) -> Result<()> {
+    let custody = ctx.accounts.custody.as_ref();
+    let collateral_custody = ctx.accounts.collateral_custody.as_mut();
+    require!(
+        collateral_custody.is_adl_allowed()?,
+        PerpetualsError::AutoDeleverageNotAllowed
+    );
+
+    let position = ctx.accounts.position.as_ref();
+    let curtime = ctx.accounts.perpetuals.get_time()?;
+
+    let token_price = OraclePrice::new_from_oracle(
+        &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &custody.oracle,
+        curtime,
+        false,
+    )?;
+
+    let token_ema_price = OraclePrice::new_from_oracle(
+        &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &custody.oracle,
+        curtime,
+        custody.pricing.use_ema,
+    )?;
+
+    let collateral_token_price = OraclePrice::new_from_oracle(
+        &ctx.accounts
+            .collateral_custody_oracle_account
+            .to_account_info(),
+        ctx.accounts
+            .collateral_custody_secondary_oracle_account
+            .as_ref(),
+        &collateral_custody.oracle,
+        curtime,
+        false,
+    )?;
+
+    let collateral_token_ema_price = OraclePrice::new_from_oracle(
+        &ctx.accounts
+            .collateral_custody_oracle_account
+            .to_account_info(),
+        ctx.accounts
+            .collateral_custody_secondary_oracle_account
+            .as_ref(),
+        &collateral_custody.oracle,
+        curtime,
+        collateral_custody.pricing.use_ema,
+    )?;
+
+    // scored at the same price the position would be deleveraged at
+    let exit_price = token_price
+        .scale_to_exponent(-(Perpetuals::PRICE_DECIMALS as i32))?
+        .price;
+
+    let (profit_usd, _, _) = ctx.accounts.pool.get_pnl_usd_at_price(
+        position,
+        exit_price,
+        0,
+        &token_ema_price,
+        custody,
+        &collateral_token_price,
+        &collateral_token_ema_price,
+        collateral_custody,
+        curtime,
+    )?;
+    require!(profit_usd > 0, PerpetualsError::InvalidPositionState);
+
+    let score = Pool::get_adl_score(position, profit_usd)?;
+    msg!("Score: {}", score);
+
+    collateral_custody.report_adl_candidate(position.key(), score, curtime)?;
+
+    emit!(ReportAdlCandidateEvent {
+        reporter: ctx.accounts.signer.key(),
+        collateral_custody: collateral_custody.key(),
+        position: position.key(),
+        score,
+        time: curtime,
+    });
+
+    Ok(())
+}
+
+/**
+ * Permissionless instruction that reduces a profitable position at the oracle price
+ * once the collateral custody runs low on unlocked liquidity. Only the position
+ * reported with the highest score can be deleveraged, and only by as much as needed
+ * to bring unlocked liquidity back to the threshold.
+ */
+#[derive(Accounts)]
+pub struct AutoDeleverage<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    /// CHECK: position owner, receives the position account rent
+    #[account(
+        mut,
+        constraint = owner.key() == position.owner
+    )]
+    pub owner: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = receiving_account.mint == collateral_custody.mint,
+        constraint = receiving_account.owner == position.owner
+    )]
+    pub receiving_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        mut,
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"position",
+                 position.owner.as_ref(),
+                 pool.key().as_ref(),
+                 custody.key().as_ref(),
+                 &[position.side as u8],
+                 Position::get_index_seed(position.index).as_slice()],
+        bump = position.bump
+    )]
+    pub position: Box<Account<'info, Position>>,
+
+    #[account(
+        mut,
+        constraint = position.custody == custody.key()
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the position token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = position.collateral_custody == collateral_custody.key()
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the collateral token
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 collateral_custody.mint.as_ref()],
+        bump = collateral_custody.token_account_bump
+    )]
+    pub collateral_custody_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = UserStats::LEN,
+        seeds = [b"user_stats",
+                 position.owner.as_ref(),
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Box<Account<'info, UserStats>>,
+
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+
+    /// CHECK: secondary oracle account for the position token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    /// CHECK: secondary oracle account for the collateral token, required when configured
+    pub collateral_custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct AutoDeleverageParams {}
+
+pub fn auto_deleverage<'info>(
+    ctx: Context<'_, '_, 'info, 'info, AutoDeleverage<'info>>,
+    _params: &AutoDeleverageParams,
+) -> Result<()> {
+    // check permissions
+    msg!("Check permissions");
+    let perpetuals = ctx.accounts.perpetuals.as_mut();
+    let custody = ctx.accounts.custody.as_mut();
+    let collateral_custody = ctx.accounts.collateral_custody.as_mut();
+    require!(
+        perpetuals.permissions.allow_close_position && custody.permissions.allow_close_position,
+        PerpetualsError::InstructionNotAllowed
+    );
+    require!(
+        collateral_custody.is_adl_allowed()?,
+        PerpetualsError::AutoDeleverageNotAllowed
+    );
+
+    let position = ctx.accounts.position.as_mut();
+    let pool = ctx.accounts.pool.as_mut();
+
+    // compute exit price
+    let curtime = perpetuals.get_time()?;
+
+    let token_price = OraclePrice::new_from_oracle(
+        &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &custody.oracle,
+        curtime,
+        false,
+    )?;
+
+    let token_ema_price = OraclePrice::new_from_oracle(
+        &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &custody.oracle,
+        curtime,
+        custody.pricing.use_ema,
+    )?;
+
+    let collateral_token_price = OraclePrice::new_from_oracle(
+        &ctx.accounts
+            .collateral_custody_oracle_account
+            .to_account_info(),
+        ctx.accounts
+            .collateral_custody_secondary_oracle_account
+            .as_ref(),
+        &collateral_custody.oracle,
+        curtime,
+        false,
+    )?;
+
+    let collateral_token_ema_price = OraclePrice::new_from_oracle(
+        &ctx.accounts
+            .collateral_custody_oracle_account
+            .to_account_info(),
+        ctx.accounts
+            .collateral_custody_secondary_oracle_account
+            .as_ref(),
+        &collateral_custody.oracle,
+        curtime,
+        collateral_custody.pricing.use_ema,
+    )?;
+
+    // check price bands, deleveraging is allowed while halted
+    custody.update_circuit_breaker(&token_price, curtime)?;
+    if position.side == Side::Long && !custody.is_virtual {
+        collateral_custody.circuit_breaker_state = custody.circuit_breaker_state;
+    }
+
+    // forced closes are settled at the oracle price without spread and exit fee
+    let exit_price = token_price
+        .scale_to_exponent(-(Perpetuals::PRICE_DECIMALS as i32))?
+        .price;
+    msg!("Exit price: {}", exit_price);
+
+    let (profit_usd, loss_usd, _) = pool.get_pnl_usd_at_price(
+        position,
+        exit_price,
+        0,
+        &token_ema_price,
+        custody,
+        &collateral_token_price,
+        &collateral_token_ema_price,
+        collateral_custody,
+        curtime,
+    )?;
+    require!(profit_usd > 0, PerpetualsError::InvalidPositionState);
+
+    // check position rank
+    msg!("Check position rank");
+    let position_key = position.key();
+    collateral_custody.check_adl_candidate(position_key, curtime)?;
+    let score = Pool::get_adl_score(position, profit_usd)?;
+    msg!("Score: {}", score);
+
+    // only close as much as needed to restore unlocked liquidity
+    let (full_transfer_amount, _, _, _) = pool.get_close_amount_from_pnl(
+        position,
+        profit_usd,
+        loss_usd,
+        0,
+        &collateral_token_price,
+        &collateral_token_ema_price,
+        collateral_custody,
+    )?;
+    let close_ratio = collateral_custody.get_adl_close_ratio(
+        position.locked_amount,
+        full_transfer_amount.saturating_sub(position.collateral_amount),
+    )?;
+    msg!("Close ratio: {}", close_ratio);
+
+    let remaining_ratio = (Perpetuals::BPS_POWER as u64).saturating_sub(close_ratio);
+    let scale = |amount: u64| -> Result<u64> {
+        math::checked_as_u64(math::checked_div(
+            math::checked_mul(amount as u128, remaining_ratio as u128)?,
+            Perpetuals::BPS_POWER,
+        )?)
+    };
+
+    // accrued interest and dividends of the remaining part are carried as unrealized pnl
+    let mut remaining_position = Position::default();
+    if remaining_ratio > 0 {
+        let interest_usd = collateral_custody.get_interest_amount_usd(position, curtime)?;
+        let (dividend_profit_usd, dividend_loss_usd) =
+            Pool::get_dividend_pnl_usd(position, custody, curtime)?;
+
+        remaining_position = Position::clone(position);
+        remaining_position.update_time = curtime;
+        remaining_position.size_usd = scale(position.size_usd)?;
+        remaining_position.borrow_size_usd = scale(position.borrow_size_usd)?;
+        remaining_position.collateral_usd = scale(position.collateral_usd)?;
+        remaining_position.unrealized_profit_usd = scale(math::checked_add(
+            position.unrealized_profit_usd,
+            dividend_profit_usd,
+        )?)?;
+        remaining_position.unrealized_loss_usd = scale(math::checked_add(
+            position.unrealized_loss_usd,
+            math::checked_add(interest_usd, dividend_loss_usd)?,
+        )?)?;
+        remaining_position.cumulative_interest_snapshot =
+            collateral_custody.get_cumulative_interest(curtime)?;
+        remaining_position.cumulative_dividend_snapshot =
+            custody.get_cumulative_dividend(curtime)?;
+        remaining_position.locked_amount = scale(position.locked_amount)?;
+        remaining_position.collateral_amount = scale(position.collateral_amount)?;
+    }
+
+    let mut closed_position = Position::clone(position);
+    closed_position.size_usd = math::checked_sub(position.size_usd, remaining_position.size_usd)?;
+    closed_position.collateral_usd =
+        math::checked_sub(position.collateral_usd, remaining_position.collateral_usd)?;
+    closed_position.locked_amount =
+        math::checked_sub(position.locked_amount, remaining_position.locked_amount)?;
+    closed_position.collateral_amount = math::checked_sub(
+        position.collateral_amount,
+        remaining_position.collateral_amount,
+    )?;
+
+    msg!("Settle position");
+    let (transfer_amount, _, profit_usd, loss_usd) = pool.get_close_amount_from_pnl(
+        &closed_position,
+        math::checked_sub(profit_usd, scale(profit_usd)?)?,
+        math::checked_sub(loss_usd, scale(loss_usd)?)?,
+        0,
+        &collateral_token_price,
+        &collateral_token_ema_price,
+        collateral_custody,
+    )?;
+
+    msg!("Net profit: {}, loss: {}", profit_usd, loss_usd);
+    msg!("Amount out: {}", transfer_amount);
+
+    // unlock pool funds
+    collateral_custody.unlock_funds(closed_position.locked_amount)?;
+
+    // check pool constraints
+    msg!("Check pool constraints");
+    require!(
+        pool.check_available_amount(transfer_amount, collateral_custody)?,
+        PerpetualsError::CustodyAmountLimit
+    );
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    perpetuals.transfer_token(
+        ctx.accounts
+            .collateral_custody_token_account
+            .to_account_info(),
+        ctx.accounts.receiving_account.to_account_info(),
+        ctx.accounts.transfer_authority.to_account_info(),
+        transfer_amount,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    // update user stats
+    let user_stats = ctx.accounts.user_stats.as_mut();
+    if user_stats.owner == Pubkey::default() {
+        user_stats.owner = position.owner;
+        user_stats.pool = pool.key();
+        user_stats.bump = ctx.bumps.user_stats;
+    }
+    user_stats.record_close(closed_position.size_usd, 0, profit_usd, loss_usd, false);
+
+    let custody_key = custody.key();
+    let collateral_custody_key = collateral_custody.key();
+    emit!(AutoDeleverageEvent {
+        owner: position.owner,
+        pool: pool.key(),
+        custody: custody_key,
+        collateral_custody: collateral_custody_key,
+        position: position_key,
+        side: position.side,
+        size_usd: closed_position.size_usd,
+        collateral_usd: closed_position.collateral_usd,
+        entry_price: position.price,
+        exit_price,
+        profit_usd,
+        loss_usd,
+        score,
+        amount_out: transfer_amount,
+        time: curtime,
+    });
+
+    // update custody stats
+    msg!("Update custody stats");
+    collateral_custody.adl_state = AdlState::default();
+
+    if transfer_amount > closed_position.collateral_amount {
+        let amount_lost = transfer_amount.saturating_sub(closed_position.collateral_amount);
+        collateral_custody.assets.owned =
+            math::checked_sub(collateral_custody.assets.owned, amount_lost)?;
+    } else {
+        let amount_gained = closed_position
+            .collateral_amount
+            .saturating_sub(transfer_amount);
+        collateral_custody.assets.owned =
+            math::checked_add(collateral_custody.assets.owned, amount_gained)?;
+    }
+    collateral_custody.assets.collateral = math::checked_sub(
+        collateral_custody.assets.collateral,
+        closed_position.collateral_amount,
+    )?;
+
+    // if custody and collateral_custody accounts are the same, ensure that data is in sync
+    if position.side == Side::Long && !custody.is_virtual {
+        collateral_custody.volume_stats.close_position_usd = collateral_custody
+            .volume_stats
+            .close_position_usd
+            .wrapping_add(closed_position.size_usd);
+
+        collateral_custody.trade_stats.oi_long_usd = collateral_custody
+            .trade_stats
+            .oi_long_usd
+            .saturating_sub(closed_position.size_usd);
+
+        collateral_custody.trade_stats.profit_usd = collateral_custody
+            .trade_stats
+            .profit_usd
+            .wrapping_add(profit_usd);
+        collateral_custody.trade_stats.loss_usd = collateral_custody
+            .trade_stats
+            .loss_usd
+            .wrapping_add(loss_usd);
+
+        collateral_custody.remove_position(position, curtime, None)?;
+        if remaining_ratio > 0 {
+            collateral_custody.add_position(
+                &remaining_position,
+                &token_ema_price,
+                curtime,
+                None,
+            )?;
+        }
+        collateral_custody.update_borrow_rate(curtime)?;
+        *custody = collateral_custody.clone();
+    } else {
+        custody.volume_stats.close_position_usd = custody
+            .volume_stats
+            .close_position_usd
+            .wrapping_add(closed_position.size_usd);
+
+        if position.side == Side::Long {
+            custody.trade_stats.oi_long_usd = custody
+                .trade_stats
+                .oi_long_usd
+                .saturating_sub(closed_position.size_usd);
+        } else {
+            custody.trade_stats.oi_short_usd = custody
+                .trade_stats
+                .oi_short_usd
+                .saturating_sub(closed_position.size_usd);
+        }
+
+        custody.trade_stats.profit_usd = custody.trade_stats.profit_usd.wrapping_add(profit_usd);
+        custody.trade_stats.loss_usd = custody.trade_stats.loss_usd.wrapping_add(loss_usd);
+
+        custody.remove_position(position, curtime, Some(collateral_custody))?;
+        if remaining_ratio > 0 {
+            custody.add_position(
+                &remaining_position,
+                &token_ema_price,
+                curtime,
+                Some(collateral_custody),
+            )?;
+        }
+        collateral_custody.update_borrow_rate(curtime)?;
+    }
+
+    if remaining_ratio > 0 {
+        msg!("Remaining size: {}", remaining_position.size_usd);
+        position.set_inner(remaining_position);
+    } else {
+        ctx.accounts
+            .position
+            .close(ctx.accounts.owner.to_account_info())?;
+    }
+
+    Ok(())
+}
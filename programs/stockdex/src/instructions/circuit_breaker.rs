@@ -0,0 +1,153 @@
+//! UpdateCircuitBreaker and ClearCircuitBreaker instruction handlers
+
+use {
+    crate::{
+        events::ClearCircuitBreakerEvent,
+        state::{
+            custody::Custody,
+            multisig::{AdminInstruction, Multisig},
+            oracle::OraclePrice,
+            perps::Perpetuals,
+            pool::Pool,
+            proposal::Proposal,
+        },
+    },
+    anchor_lang::prelude::*,
+};
+
+/**
+ * Permissionless crank that persists the breaker state.
+ * Instructions that trip the breaker persist it too, this switches the custody to reduce-only
+ * without waiting for the next trade.
+ */
+#[derive(Accounts)]
+pub struct UpdateCircuitBreaker<'info> {
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the custody token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+
+    /// CHECK: secondary oracle account for the custody token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+}
+
+pub fn update_circuit_breaker(ctx: Context<UpdateCircuitBreaker>) -> Result<bool> {
+    let custody = ctx.accounts.custody.as_mut();
+    let curtime = ctx.accounts.perpetuals.get_time()?;
+
+    let token_price = OraclePrice::new_from_oracle(
+        &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &custody.oracle,
+        curtime,
+        false,
+    )?;
+
+    let halted = custody.update_circuit_breaker(&token_price, curtime)?;
+    if halted {
+        msg!(
+            "Custody is halted until {}",
+            custody.circuit_breaker_state.halted_until
+        );
+    }
+
+    Ok(halted)
+}
+
+/**
+ * Clear circuit breaker
+ */
+#[derive(Accounts)]
+pub struct ClearCircuitBreaker<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, Multisig>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Option<Account<'info, Proposal>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct ClearCircuitBreakerParams {}
+
+pub fn clear_circuit_breaker<'info>(
+    ctx: Context<'_, '_, '_, 'info, ClearCircuitBreaker<'info>>,
+    params: &ClearCircuitBreakerParams,
+) -> Result<u8> {
+    let mut multisig = ctx.accounts.multisig.load_mut()?;
+
+    let signatures_left = multisig.sign_multisig(
+        &ctx.accounts.admin,
+        &Multisig::get_account_infos(&ctx)[1..],
+        &Multisig::get_instruction_data(AdminInstruction::ClearCircuitBreaker, params)?,
+        ctx.accounts.proposal.as_deref_mut(),
+    )?;
+    if signatures_left > 0 {
+        msg!(
+            "Instruction has been signed but more signatures are required: {}",
+            signatures_left
+        );
+        return Ok(signatures_left);
+    }
+
+    msg!("Clear circuit breaker");
+    ctx.accounts.custody.clear_circuit_breaker();
+
+    emit!(ClearCircuitBreakerEvent {
+        pool: ctx.accounts.pool.key(),
+        custody: ctx.accounts.custody.key(),
+        time: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(0)
+}
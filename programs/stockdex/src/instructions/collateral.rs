@@ -1,6 +1,6 @@
 #![allow(dead_code)]
 
-use {crate::{error::PerpetualsError, math, state::{custody::Custody, oracle::OraclePrice, perps::Perpetuals, pool::Pool, position::{Position, Side}}},
+use {crate::{error::PerpetualsError, events::{AddCollateralEvent, RemoveCollateralEvent}, math, state::{custody::Custody, delegation::{DelegatedAction, Delegation}, oracle::OraclePrice, perps::Perpetuals, pool::Pool, position::{Position, Side}}},
     anchor_lang::prelude::*,
     anchor_spl::token::{Token, TokenAccount},
 };
@@ -17,12 +17,15 @@ pub struct CollateralAdditionParams {
 #[instruction(params: CollateralAdditionParams)]
 pub struct AddCollateral<'info>{
     #[account(mut)]
-    pub owner: Signer<'info>,
+    pub authority: Signer<'info>,
+
+    /// CHECK: position owner, either the authority or the account delegating to it
+    pub owner: AccountInfo<'info>,
 
     #[account(
         mut,
         constraint = funding_account.mint == custody.mint,
-        has_one = owner
+        constraint = funding_account.owner == authority.key()
     )]
     pub funding_account: Box<Account<'info, TokenAccount>>,
 
@@ -54,7 +57,8 @@ pub struct AddCollateral<'info>{
                  owner.key().as_ref(),
                  pool.key().as_ref(),
                  custody.key().as_ref(),
-                 &[position.side as u8]],
+                 &[position.side as u8],
+                 Position::get_index_seed(position.index).as_slice()],
         bump = position.bump
     )]
     pub position: Box<Account<'info, Position>>,
@@ -93,6 +97,20 @@ pub struct AddCollateral<'info>{
     pub collateral_custody_token_account: Box<Account<'info, TokenAccount>>,
 
     token_program: Program<'info, Token>,
+
+    /// CHECK: secondary oracle account for the position token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    /// CHECK: secondary oracle account for the collateral token, required when configured
+    pub collateral_custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    #[account(
+        seeds = [b"delegation",
+                 owner.key().as_ref(),
+                 authority.key().as_ref()],
+        bump = delegation.bump
+    )]
+    pub delegation: Option<Account<'info, Delegation>>,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize)]
@@ -101,6 +119,15 @@ pub struct AddCollateralParams {
 }
 
 pub fn add_collateral(ctx: Context<AddCollateral>, params: &AddCollateralParams) -> Result<()> {
+    // check authority
+    Delegation::check_authority(
+        &ctx.accounts.authority.key(),
+        &ctx.accounts.owner.key(),
+        ctx.accounts.delegation.as_deref(),
+        DelegatedAction::AdjustCollateral,
+        ctx.accounts.perpetuals.get_time()?,
+    )?;
+
     msg!("Validate inputs");
     if params.collateral == 0 {
         return Err(ProgramError::InvalidArgument.into());
@@ -115,6 +142,7 @@ pub fn add_collateral(ctx: Context<AddCollateral>, params: &AddCollateralParams)
 
     let token_price = OraclePrice::new_from_oracle(
         &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
         &custody.oracle,
         curtime,
         false,
@@ -122,6 +150,7 @@ pub fn add_collateral(ctx: Context<AddCollateral>, params: &AddCollateralParams)
 
     let token_ema_price = OraclePrice::new_from_oracle(
         &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
         &custody.oracle,
         curtime,
         custody.pricing.use_ema,
@@ -131,6 +160,7 @@ pub fn add_collateral(ctx: Context<AddCollateral>, params: &AddCollateralParams)
         &ctx.accounts
             .collateral_custody_oracle_account
             .to_account_info(),
+        ctx.accounts.collateral_custody_secondary_oracle_account.as_ref(),
         &collateral_custody.oracle,
         curtime,
         false,
@@ -140,11 +170,18 @@ pub fn add_collateral(ctx: Context<AddCollateral>, params: &AddCollateralParams)
         &ctx.accounts
             .collateral_custody_oracle_account
             .to_account_info(),
+        ctx.accounts.collateral_custody_secondary_oracle_account.as_ref(),
         &collateral_custody.oracle,
         curtime,
         collateral_custody.pricing.use_ema,
     )?;
 
+    // check price bands, adding collateral is allowed while halted
+    custody.update_circuit_breaker(&token_price, curtime)?;
+    if position.side == Side::Long && !custody.is_virtual {
+        collateral_custody.circuit_breaker_state = custody.circuit_breaker_state;
+    }
+
     let min_collateral_price = collateral_token_price
         .get_min_price(&collateral_token_ema_price, collateral_custody.is_stable)?;
 
@@ -180,7 +217,7 @@ pub fn add_collateral(ctx: Context<AddCollateral>, params: &AddCollateralParams)
         ctx.accounts
             .collateral_custody_token_account
             .to_account_info(),
-        ctx.accounts.owner.to_account_info(),
+        ctx.accounts.authority.to_account_info(),
         params.collateral,
         ctx.accounts.token_program.to_account_info(),
     )?;
@@ -189,6 +226,19 @@ pub fn add_collateral(ctx: Context<AddCollateral>, params: &AddCollateralParams)
     collateral_custody.assets.collateral =
         math::checked_add(collateral_custody.assets.collateral, params.collateral)?;
 
+    emit!(AddCollateralEvent {
+        owner: position.owner,
+        pool: pool.key(),
+        custody: custody.key(),
+        collateral_custody: collateral_custody.key(),
+        position: position.key(),
+        side: position.side,
+        amount_in: params.collateral,
+        amount_in_usd: collateral_usd,
+        collateral_usd: position.collateral_usd,
+        time: curtime,
+    });
+
     if position.side == Side::Long && !custody.is_virtual {
         *custody = collateral_custody.clone();
     }
@@ -204,7 +254,10 @@ pub fn add_collateral(ctx: Context<AddCollateral>, params: &AddCollateralParams)
 #[instruction(params: RemoveCollateralParams)]
 pub struct RemoveCollateral<'info> {
     #[account(mut)]
-    pub owner: Signer<'info>,
+    pub authority: Signer<'info>,
+
+    /// CHECK: position owner, either the authority or the account delegating to it
+    pub owner: AccountInfo<'info>,
 
     #[account(
         mut,
@@ -241,7 +294,8 @@ pub struct RemoveCollateral<'info> {
                  owner.key().as_ref(),
                  pool.key().as_ref(),
                  custody.key().as_ref(),
-                 &[position.side as u8]],
+                 &[position.side as u8],
+                 Position::get_index_seed(position.index).as_slice()],
         bump = position.bump
     )]
     pub position: Box<Account<'info, Position>>,
@@ -280,6 +334,20 @@ pub struct RemoveCollateral<'info> {
     pub collateral_custody_token_account: Box<Account<'info, TokenAccount>>,
 
     token_program: Program<'info, Token>,
+
+    /// CHECK: secondary oracle account for the position token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    /// CHECK: secondary oracle account for the collateral token, required when configured
+    pub collateral_custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    #[account(
+        seeds = [b"delegation",
+                 owner.key().as_ref(),
+                 authority.key().as_ref()],
+        bump = delegation.bump
+    )]
+    pub delegation: Option<Account<'info, Delegation>>,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize)]
@@ -291,6 +359,15 @@ pub fn remove_collateral(
     ctx: Context<RemoveCollateral>,
     params: &RemoveCollateralParams,
 ) -> Result<()> {
+    // check authority
+    Delegation::check_authority(
+        &ctx.accounts.authority.key(),
+        &ctx.accounts.owner.key(),
+        ctx.accounts.delegation.as_deref(),
+        DelegatedAction::AdjustCollateral,
+        ctx.accounts.perpetuals.get_time()?,
+    )?;
+
     msg!("Check permissions");
     let perpetuals = ctx.accounts.perpetuals.as_mut();
     let custody = ctx.accounts.custody.as_mut();
@@ -312,6 +389,7 @@ pub fn remove_collateral(
 
     let token_price = OraclePrice::new_from_oracle(
         &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
         &custody.oracle,
         curtime,
         false,
@@ -319,6 +397,7 @@ pub fn remove_collateral(
 
     let token_ema_price = OraclePrice::new_from_oracle(
         &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
         &custody.oracle,
         curtime,
         custody.pricing.use_ema,
@@ -328,6 +407,7 @@ pub fn remove_collateral(
         &ctx.accounts
             .collateral_custody_oracle_account
             .to_account_info(),
+        ctx.accounts.collateral_custody_secondary_oracle_account.as_ref(),
         &collateral_custody.oracle,
         curtime,
         false,
@@ -337,11 +417,22 @@ pub fn remove_collateral(
         &ctx.accounts
             .collateral_custody_oracle_account
             .to_account_info(),
+        ctx.accounts.collateral_custody_secondary_oracle_account.as_ref(),
         &collateral_custody.oracle,
         curtime,
         collateral_custody.pricing.use_ema,
     )?;
 
+    // check price bands, custody only allows reducing risk while halted
+    let tripped = custody.check_circuit_breaker(&token_price, curtime)?;
+    if position.side == Side::Long && !custody.is_virtual {
+        collateral_custody.circuit_breaker_state = custody.circuit_breaker_state;
+    }
+    if tripped {
+        msg!("Circuit breaker tripped, collateral was not removed");
+        return Ok(());
+    }
+
     let max_collateral_price = if collateral_token_price > collateral_token_ema_price {
         collateral_token_price
     } else {
@@ -391,6 +482,19 @@ pub fn remove_collateral(
     collateral_custody.assets.collateral =
         math::checked_sub(collateral_custody.assets.collateral, collateral)?;
 
+    emit!(RemoveCollateralEvent {
+        owner: position.owner,
+        pool: pool.key(),
+        custody: custody.key(),
+        collateral_custody: collateral_custody.key(),
+        position: position.key(),
+        side: position.side,
+        amount_out: collateral,
+        amount_out_usd: params.collateral_usd,
+        collateral_usd: position.collateral_usd,
+        time: curtime,
+    });
+
     // if custody and collateral_custody accounts are the same, ensure that data is in sync
     if position.side == Side::Long && !custody.is_virtual {
         *custody = collateral_custody.clone();
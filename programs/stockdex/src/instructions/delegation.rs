@@ -0,0 +1,112 @@
+//! SetDelegation and RevokeDelegation instruction handlers
+
+use {
+    crate::{
+        events::{RevokeDelegationEvent, SetDelegationEvent},
+        state::{delegation::Delegation, perps::Perpetuals},
+    },
+    anchor_lang::prelude::*,
+};
+
+/**
+ * set delegation: grants or updates trading rights of a delegate over the owner's positions
+ */
+#[derive(Accounts)]
+pub struct SetDelegation<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    /// CHECK: key allowed to trade on behalf of the owner
+    pub delegate: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = Delegation::LEN,
+        seeds = [b"delegation",
+                 owner.key().as_ref(),
+                 delegate.key().as_ref()],
+        bump
+    )]
+    pub delegation: Box<Account<'info, Delegation>>,
+
+    system_program: Program<'info, System>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct SetDelegationParams {
+    pub can_open: bool,
+    pub can_close: bool,
+    pub can_adjust_collateral: bool,
+    pub max_size_usd: u64,
+    pub expiry_time: i64,
+}
+
+pub fn set_delegation(ctx: Context<SetDelegation>, params: &SetDelegationParams) -> Result<()> {
+    // validate inputs
+    let owner = ctx.accounts.owner.key();
+    let delegate = ctx.accounts.delegate.key();
+    let curtime = ctx.accounts.perpetuals.get_time()?;
+    if delegate == owner || (params.expiry_time != 0 && params.expiry_time <= curtime) {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+
+    let delegation = ctx.accounts.delegation.as_mut();
+    delegation.owner = owner;
+    delegation.delegate = delegate;
+    delegation.can_open = params.can_open;
+    delegation.can_close = params.can_close;
+    delegation.can_adjust_collateral = params.can_adjust_collateral;
+    delegation.max_size_usd = params.max_size_usd;
+    delegation.expiry_time = params.expiry_time;
+    delegation.bump = ctx.bumps.delegation;
+
+    emit!(SetDelegationEvent {
+        owner,
+        delegate,
+        can_open: delegation.can_open,
+        can_close: delegation.can_close,
+        can_adjust_collateral: delegation.can_adjust_collateral,
+        max_size_usd: delegation.max_size_usd,
+        expiry_time: delegation.expiry_time,
+        time: curtime,
+    });
+
+    Ok(())
+}
+
+/**
+ * revoke delegation
+ */
+#[derive(Accounts)]
+pub struct RevokeDelegation<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [b"delegation",
+                 owner.key().as_ref(),
+                 delegation.delegate.as_ref()],
+        bump = delegation.bump,
+        close = owner
+    )]
+    pub delegation: Box<Account<'info, Delegation>>,
+}
+
+pub fn revoke_delegation(ctx: Context<RevokeDelegation>) -> Result<()> {
+    emit!(RevokeDelegationEvent {
+        owner: ctx.accounts.owner.key(),
+        delegate: ctx.accounts.delegation.delegate,
+        time: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,111 @@
+//! RecordDividend instruction handler
+
+use {
+    crate::{
+        events::RecordDividendEvent,
+        state::{
+            custody::Custody,
+            multisig::{AdminInstruction, Multisig},
+            perps::Perpetuals,
+            pool::Pool,
+            proposal::Proposal,
+        },
+    },
+    anchor_lang::prelude::*,
+};
+
+#[derive(Accounts)]
+#[instruction(params: RecordDividendParams)]
+pub struct RecordDividend<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, Multisig>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Option<Account<'info, Proposal>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct RecordDividendParams {
+    // cash dividend per one whole token with implied USD_DECIMALS decimals
+    pub dividend_per_share: u64,
+    pub ex_date: i64,
+}
+
+pub fn record_dividend<'info>(
+    ctx: Context<'_, '_, '_, 'info, RecordDividend<'info>>,
+    params: &RecordDividendParams,
+) -> Result<u8> {
+    if params.dividend_per_share == 0 {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+
+    let mut multisig = ctx.accounts.multisig.load_mut()?;
+
+    let signatures_left = multisig.sign_multisig(
+        &ctx.accounts.admin,
+        &Multisig::get_account_infos(&ctx)[1..],
+        &Multisig::get_instruction_data(AdminInstruction::RecordDividend, params)?,
+        ctx.accounts.proposal.as_deref_mut(),
+    )?;
+    if signatures_left > 0 {
+        msg!(
+            "Instruction has been signed but more signatures are required: {}",
+            signatures_left
+        );
+        return Ok(signatures_left);
+    }
+
+    let curtime = ctx.accounts.perpetuals.get_time()?;
+    let custody = ctx.accounts.custody.as_mut();
+
+    msg!(
+        "Record dividend: {} per share, ex-date {}",
+        params.dividend_per_share,
+        params.ex_date
+    );
+    custody.record_dividend(params.dividend_per_share, params.ex_date, curtime)?;
+
+    emit!(RecordDividendEvent {
+        pool: ctx.accounts.pool.key(),
+        custody: custody.key(),
+        dividend_per_share: params.dividend_per_share,
+        ex_date: params.ex_date,
+        time: curtime,
+    });
+
+    Ok(0)
+}
@@ -64,6 +64,7 @@ pub struct InitParams {
     pub allow_pnl_withdrawal: bool,
     pub allow_collateral_withdrawal: bool,
     pub allow_size_change: bool,
+    pub allow_transfer_position: bool,
 }
 
 pub fn init(ctx: Context<Init>, params: &InitParams) -> Result<()> {
@@ -76,7 +77,11 @@ pub fn init(ctx: Context<Init>, params: &InitParams) -> Result<()> {
     // initialize multisig, this will fail if account is already initialized
     let mut multisig = ctx.accounts.multisig.load_init()?;
 
-    multisig.set_signers(ctx.remaining_accounts, params.min_signatures)?;
+    multisig.set_signers(
+        ctx.remaining_accounts,
+        &vec![1; ctx.remaining_accounts.len()],
+        params.min_signatures,
+    )?;
 
     // record multisig PDA bump
     multisig.bump = ctx.bumps.multisig;
@@ -92,6 +97,7 @@ pub fn init(ctx: Context<Init>, params: &InitParams) -> Result<()> {
     perpetuals.permissions.allow_pnl_withdrawal = params.allow_pnl_withdrawal;
     perpetuals.permissions.allow_collateral_withdrawal = params.allow_collateral_withdrawal;
     perpetuals.permissions.allow_size_change = params.allow_size_change;
+    perpetuals.permissions.allow_transfer_position = params.allow_transfer_position;
     perpetuals.transfer_authority_bump = ctx.bumps.transfer_authority;
     perpetuals.perpetuals_bump = ctx.bumps.perpetuals;
     perpetuals.inception_time = perpetuals.get_time()?;
@@ -0,0 +1,262 @@
+//! AddInsuranceFund and WithdrawInsuranceFund instruction handlers
+
+use {
+    crate::{
+        events::{AddInsuranceFundEvent, WithdrawInsuranceFundEvent},
+        state::{
+            custody::Custody,
+            multisig::{AdminInstruction, Multisig},
+            perps::Perpetuals,
+            pool::Pool,
+            proposal::Proposal,
+        },
+    },
+    anchor_lang::prelude::*,
+    anchor_spl::token::{Mint, Token, TokenAccount},
+};
+
+/**
+ * Add insurance fund: permissionless deposit to the pool insurance fund vault of a custody token.
+ * Also sweeps the insurance fees reserved in the custody to the vault.
+ * The vault is required to close or liquidate positions with this collateral, so it must be
+ * created once per collateral custody, with a zero amount if there is nothing to deposit.
+ */
+#[derive(Accounts)]
+pub struct AddInsuranceFund<'info> {
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = funding_account.mint == custody.mint,
+        constraint = funding_account.owner == funder.key()
+    )]
+    pub funding_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        constraint = custody_mint.key() == custody.mint
+    )]
+    pub custody_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.token_account_bump
+    )]
+    pub custody_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = funder,
+        token::mint = custody_mint,
+        token::authority = transfer_authority,
+        seeds = [b"insurance_fund_token_account",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump
+    )]
+    pub insurance_fund_token_account: Box<Account<'info, TokenAccount>>,
+
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+    rent: Sysvar<'info, Rent>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct AddInsuranceFundParams {
+    // 0 only sweeps the reserved insurance fees and creates the vault if needed
+    pub amount: u64,
+}
+
+pub fn add_insurance_fund(
+    ctx: Context<AddInsuranceFund>,
+    params: &AddInsuranceFundParams,
+) -> Result<()> {
+    let custody = ctx.accounts.custody.as_mut();
+    let reserved_amount = custody.assets.insurance_fund;
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    if params.amount > 0 {
+        ctx.accounts.perpetuals.transfer_token_from_user(
+            ctx.accounts.funding_account.to_account_info(),
+            ctx.accounts.insurance_fund_token_account.to_account_info(),
+            ctx.accounts.funder.to_account_info(),
+            params.amount,
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+    }
+
+    if reserved_amount > 0 {
+        msg!("Sweep reserved insurance fees: {}", reserved_amount);
+        ctx.accounts.perpetuals.transfer_token(
+            ctx.accounts.custody_token_account.to_account_info(),
+            ctx.accounts.insurance_fund_token_account.to_account_info(),
+            ctx.accounts.transfer_authority.to_account_info(),
+            reserved_amount,
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+        custody.assets.insurance_fund = 0;
+    }
+
+    emit!(AddInsuranceFundEvent {
+        funder: ctx.accounts.funder.key(),
+        pool: ctx.accounts.pool.key(),
+        custody: custody.key(),
+        amount: params.amount,
+        swept_amount: reserved_amount,
+        time: ctx.accounts.perpetuals.get_time()?,
+    });
+
+    Ok(())
+}
+
+/**
+ * Withdraw insurance fund
+ */
+#[derive(Accounts)]
+pub struct WithdrawInsuranceFund<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, Multisig>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        mut,
+        seeds = [b"insurance_fund_token_account",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump
+    )]
+    pub insurance_fund_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = receiving_account.mint == custody.mint
+    )]
+    pub receiving_account: Box<Account<'info, TokenAccount>>,
+
+    token_program: Program<'info, Token>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Option<Account<'info, Proposal>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct WithdrawInsuranceFundParams {
+    pub amount: u64,
+}
+
+pub fn withdraw_insurance_fund<'info>(
+    ctx: Context<'_, '_, '_, 'info, WithdrawInsuranceFund<'info>>,
+    params: &WithdrawInsuranceFundParams,
+) -> Result<u8> {
+    // validate inputs
+    if params.amount == 0 || params.amount > ctx.accounts.insurance_fund_token_account.amount {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+
+    let mut multisig = ctx.accounts.multisig.load_mut()?;
+
+    let signatures_left = multisig.sign_multisig(
+        &ctx.accounts.admin,
+        &Multisig::get_account_infos(&ctx)[1..],
+        &Multisig::get_instruction_data(AdminInstruction::WithdrawInsuranceFund, params)?,
+        ctx.accounts.proposal.as_deref_mut(),
+    )?;
+    if signatures_left > 0 {
+        msg!(
+            "Instruction has been signed but more signatures are required: {}",
+            signatures_left
+        );
+        return Ok(signatures_left);
+    }
+
+    msg!("Withdraw insurance fund: {}", params.amount);
+    ctx.accounts.perpetuals.transfer_token(
+        ctx.accounts.insurance_fund_token_account.to_account_info(),
+        ctx.accounts.receiving_account.to_account_info(),
+        ctx.accounts.transfer_authority.to_account_info(),
+        params.amount,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    emit!(WithdrawInsuranceFundEvent {
+        pool: ctx.accounts.pool.key(),
+        custody: ctx.accounts.custody.key(),
+        receiving_account: ctx.accounts.receiving_account.key(),
+        amount: params.amount,
+        time: ctx.accounts.perpetuals.get_time()?,
+    });
+
+    Ok(0)
+}
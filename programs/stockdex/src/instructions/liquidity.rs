@@ -1,14 +1,22 @@
-//! AddLiquidity instruction handler
+//! AddLiquidity and RemoveLiquidity instruction handlers, including the two-step
+//! withdrawal flow used when the pool has a cooldown
 
 use {
     crate::{
         error::PerpetualsError,
+        events::{
+            AddLiquidityEvent, CancelRemoveLiquidityEvent, RemoveLiquidityEvent,
+            RequestRemoveLiquidityEvent,
+        },
         math,
         state::{
             custody::Custody,
             oracle::OraclePrice,
+            oracle_history::OracleHistory,
             perps::Perpetuals,
             pool::{AumCalcMode, Pool},
+            user_stats::UserStats,
+            withdrawal_request::WithdrawalRequest,
         },
     },
     anchor_lang::prelude::*,
@@ -89,10 +97,35 @@ pub struct AddLiquidity<'info> {
     )]
     pub lp_token_mint: Box<Account<'info, Mint>>,
 
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = UserStats::LEN,
+        seeds = [b"user_stats",
+                 owner.key().as_ref(),
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Box<Account<'info, UserStats>>,
+
+    system_program: Program<'info, System>,
     token_program: Program<'info, Token>,
+
+    /// CHECK: secondary oracle account for the custody token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    // records a TWAP sample when provided
+    #[account(
+        mut,
+        seeds = [b"oracle_history",
+                 custody.key().as_ref()],
+        bump = custody_oracle_history.bump
+    )]
+    pub custody_oracle_history: Option<Box<Account<'info, OracleHistory>>>,
     // remaining accounts:
     //   pool.tokens.len() custody accounts (read-only, unsigned)
     //   pool.tokens.len() custody oracles (read-only, unsigned)
+    //   pool.tokens.len() custody secondary oracles, if any custody has one (read-only, unsigned)
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize)]
@@ -126,10 +159,11 @@ pub fn add_liquidity(ctx: Context<AddLiquidity>, params: &AddLiquidityParams) ->
 
     // Refresh pool.aum_usm to adapt to token price change
     pool.aum_usd =
-        pool.get_assets_under_management_usd(AumCalcMode::EMA, ctx.remaining_accounts, curtime)?;
+        pool.get_assets_under_management_usd(AumCalcMode::Twap, ctx.remaining_accounts, curtime)?;
 
     let token_price = OraclePrice::new_from_oracle(
         &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
         &custody.oracle,
         curtime,
         false,
@@ -137,11 +171,23 @@ pub fn add_liquidity(ctx: Context<AddLiquidity>, params: &AddLiquidityParams) ->
 
     let token_ema_price = OraclePrice::new_from_oracle(
         &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
         &custody.oracle,
         curtime,
         custody.pricing.use_ema,
     )?;
 
+    // record a sample so the TWAP doesn't depend on the update_oracle_history crank
+    if let Some(oracle_history) = ctx.accounts.custody_oracle_history.as_deref_mut() {
+        oracle_history.update_custody_twap(custody, &token_price, curtime)?;
+    }
+
+    // check price bands, no deposits at prices outside of the band
+    if custody.check_circuit_breaker(&token_price, curtime)? {
+        msg!("Circuit breaker tripped, liquidity was not added");
+        return Ok(());
+    }
+
     let min_price = if token_price < token_ema_price {
         token_price
     } else {
@@ -155,7 +201,17 @@ pub fn add_liquidity(ctx: Context<AddLiquidity>, params: &AddLiquidityParams) ->
     // check pool constraints
     msg!("Check pool constraints");
     let protocol_fee = Pool::get_fee_amount(custody.fees.protocol_share, fee_amount)?;
-    let deposit_amount = math::checked_sub(params.amount_in, protocol_fee)?;
+    let insurance_fee = Pool::get_fee_amount(custody.fees.insurance_share, fee_amount)?;
+    // staking fees are only reserved in the LP staking reward custody
+    let staking_fee = if custody.is_lp_staking_reward {
+        Pool::get_fee_amount(custody.fees.staking_share, fee_amount)?
+    } else {
+        0
+    };
+    let deposit_amount = math::checked_sub(
+        params.amount_in,
+        math::checked_add(math::checked_add(protocol_fee, insurance_fee)?, staking_fee)?,
+    )?;
     require!(
         pool.check_token_ratio(token_id, deposit_amount, 0, custody, &token_ema_price)?,
         PerpetualsError::TokenRatioOutOfRange
@@ -226,16 +282,743 @@ pub fn add_liquidity(ctx: Context<AddLiquidity>, params: &AddLiquidityParams) ->
         .wrapping_add(token_ema_price.get_asset_amount_usd(params.amount_in, custody.decimals)?);
 
     custody.assets.protocol_fees = math::checked_add(custody.assets.protocol_fees, protocol_fee)?;
+    custody.assets.insurance_fund =
+        math::checked_add(custody.assets.insurance_fund, insurance_fee)?;
+    custody.assets.staking_rewards =
+        math::checked_add(custody.assets.staking_rewards, staking_fee)?;
 
     custody.assets.owned = math::checked_add(custody.assets.owned, deposit_amount)?;
 
     custody.update_borrow_rate(curtime)?;
 
+    // update user stats
+    let user_stats = ctx.accounts.user_stats.as_mut();
+    if user_stats.owner == Pubkey::default() {
+        user_stats.owner = ctx.accounts.owner.key();
+        user_stats.pool = pool.key();
+        user_stats.bump = ctx.bumps.user_stats;
+    }
+    user_stats.last_add_liquidity_time = curtime;
+
+    // update pool stats
+    msg!("Update pool stats");
+    custody.exit(&crate::ID)?;
+    pool.aum_usd =
+        pool.get_assets_under_management_usd(AumCalcMode::Twap, ctx.remaining_accounts, curtime)?;
+
+    emit!(AddLiquidityEvent {
+        owner: ctx.accounts.owner.key(),
+        pool: pool.key(),
+        custody: custody.key(),
+        amount_in: params.amount_in,
+        fee_amount,
+        lp_amount_out: lp_amount,
+        aum_usd: pool.aum_usd,
+        time: curtime,
+    });
+
+    Ok(())
+}
+#[derive(Accounts)]
+#[instruction(params: RemoveLiquidityParams)]
+pub struct RemoveLiquidity<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = receiving_account.mint == custody.mint,
+        has_one = owner
+    )]
+    pub receiving_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = lp_token_account.mint == lp_token_mint.key(),
+        has_one = owner
+    )]
+    pub lp_token_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        mut,
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the returned token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.token_account_bump
+    )]
+    pub custody_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"lp_token_mint",
+                 pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Box<Account<'info, Mint>>,
+
+    token_program: Program<'info, Token>,
+
+    /// CHECK: secondary oracle account for the custody token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    // records a TWAP sample when provided
+    #[account(
+        mut,
+        seeds = [b"oracle_history",
+                 custody.key().as_ref()],
+        bump = custody_oracle_history.bump
+    )]
+    pub custody_oracle_history: Option<Box<Account<'info, OracleHistory>>>,
+    // remaining accounts:
+    //   pool.tokens.len() custody accounts (read-only, unsigned)
+    //   pool.tokens.len() custody oracles (read-only, unsigned)
+    //   pool.tokens.len() custody secondary oracles, if any custody has one (read-only, unsigned)
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct RemoveLiquidityParams {
+    pub lp_amount_in: u64,
+    pub min_amount_out: u64,
+}
+
+pub fn remove_liquidity<'info>(
+    ctx: Context<'_, '_, 'info, 'info, RemoveLiquidity<'info>>,
+    params: &RemoveLiquidityParams,
+) -> Result<()> {
+    // pools with a cooldown only allow the request/execute flow
+    require!(
+        ctx.accounts.pool.withdrawal.cooldown_sec == 0,
+        PerpetualsError::InstructionNotAllowed
+    );
+
+    settle_remove_liquidity(
+        &ctx.accounts.perpetuals,
+        &mut ctx.accounts.pool,
+        &mut ctx.accounts.custody,
+        &ctx.accounts.custody_oracle_account,
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        ctx.accounts.custody_oracle_history.as_deref_mut(),
+        &ctx.accounts.custody_token_account,
+        &ctx.accounts.receiving_account,
+        &ctx.accounts.lp_token_mint,
+        &ctx.accounts.lp_token_account,
+        &ctx.accounts.owner,
+        &ctx.accounts.transfer_authority,
+        &ctx.accounts.token_program,
+        ctx.remaining_accounts,
+        params.lp_amount_in,
+        params.min_amount_out,
+    )
+}
+
+/**
+ * request remove liquidity: moves lp tokens to escrow until the pool cooldown has passed.
+ * The deposit cooldown is tracked per wallet, LP tokens moved to another wallet skip it
+ * but still wait the request cooldown, so no deposit leaves the pool within cooldown_sec.
+ */
+#[derive(Accounts)]
+#[instruction(params: RequestRemoveLiquidityParams)]
+pub struct RequestRemoveLiquidity<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = lp_token_account.mint == lp_token_mint.key(),
+        has_one = owner
+    )]
+    pub lp_token_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        seeds = [b"lp_token_mint",
+                 pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        token::mint = lp_token_mint,
+        token::authority = transfer_authority,
+        seeds = [b"withdrawal_escrow_token_account",
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub withdrawal_escrow_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = WithdrawalRequest::LEN,
+        seeds = [b"withdrawal_request",
+                 owner.key().as_ref(),
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub withdrawal_request: Box<Account<'info, WithdrawalRequest>>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = UserStats::LEN,
+        seeds = [b"user_stats",
+                 owner.key().as_ref(),
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Box<Account<'info, UserStats>>,
+
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+    rent: Sysvar<'info, Rent>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct RequestRemoveLiquidityParams {
+    pub lp_amount_in: u64,
+    pub min_amount_out: u64,
+}
+
+pub fn request_remove_liquidity(
+    ctx: Context<RequestRemoveLiquidity>,
+    params: &RequestRemoveLiquidityParams,
+) -> Result<()> {
+    // check permissions
+    msg!("Check permissions");
+    let perpetuals = ctx.accounts.perpetuals.as_ref();
+    let custody = ctx.accounts.custody.as_ref();
+    require!(
+        perpetuals.permissions.allow_remove_liquidity
+            && custody.permissions.allow_remove_liquidity
+            && !custody.is_virtual,
+        PerpetualsError::InstructionNotAllowed
+    );
+
+    // validate inputs
+    msg!("Validate inputs");
+    if params.lp_amount_in == 0 {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+
+    // check cooldown after the last deposit
+    let curtime = perpetuals.get_time()?;
+    let user_stats = ctx.accounts.user_stats.as_mut();
+    if user_stats.owner == Pubkey::default() {
+        user_stats.owner = ctx.accounts.owner.key();
+        user_stats.pool = ctx.accounts.pool.key();
+        user_stats.bump = ctx.bumps.user_stats;
+    }
+    let cooldown_end = math::checked_add(
+        user_stats.last_add_liquidity_time,
+        ctx.accounts.pool.withdrawal.cooldown_sec as i64,
+    )?;
+    require!(curtime >= cooldown_end, PerpetualsError::WithdrawalCooldown);
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    perpetuals.transfer_token_from_user(
+        ctx.accounts.lp_token_account.to_account_info(),
+        ctx.accounts.withdrawal_escrow_token_account.to_account_info(),
+        ctx.accounts.owner.to_account_info(),
+        params.lp_amount_in,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    // record request
+    msg!("Record withdrawal request");
+    let withdrawal_request = ctx.accounts.withdrawal_request.as_mut();
+    withdrawal_request.owner = ctx.accounts.owner.key();
+    withdrawal_request.pool = ctx.accounts.pool.key();
+    withdrawal_request.custody = custody.key();
+    withdrawal_request.lp_amount = params.lp_amount_in;
+    withdrawal_request.min_amount_out = params.min_amount_out;
+    withdrawal_request.request_time = curtime;
+    withdrawal_request.bump = ctx.bumps.withdrawal_request;
+
+    emit!(RequestRemoveLiquidityEvent {
+        owner: withdrawal_request.owner,
+        pool: withdrawal_request.pool,
+        custody: withdrawal_request.custody,
+        lp_amount: withdrawal_request.lp_amount,
+        min_amount_out: withdrawal_request.min_amount_out,
+        time: curtime,
+    });
+
+    Ok(())
+}
+
+/**
+ * execute remove liquidity: returns escrowed lp tokens to the owner and burns them
+ */
+#[derive(Accounts)]
+pub struct ExecuteRemoveLiquidity<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = receiving_account.mint == custody.mint,
+        has_one = owner
+    )]
+    pub receiving_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = lp_token_account.mint == lp_token_mint.key(),
+        has_one = owner
+    )]
+    pub lp_token_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        mut,
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        constraint = withdrawal_request.custody == custody.key()
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the returned token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.token_account_bump
+    )]
+    pub custody_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"lp_token_mint",
+                 pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [b"withdrawal_escrow_token_account",
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub withdrawal_escrow_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [b"withdrawal_request",
+                 owner.key().as_ref(),
+                 pool.key().as_ref()],
+        bump = withdrawal_request.bump,
+        close = owner
+    )]
+    pub withdrawal_request: Box<Account<'info, WithdrawalRequest>>,
+
+    token_program: Program<'info, Token>,
+
+    /// CHECK: secondary oracle account for the custody token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    // records a TWAP sample when provided
+    #[account(
+        mut,
+        seeds = [b"oracle_history",
+                 custody.key().as_ref()],
+        bump = custody_oracle_history.bump
+    )]
+    pub custody_oracle_history: Option<Box<Account<'info, OracleHistory>>>,
+    // remaining accounts:
+    //   pool.tokens.len() custody accounts (read-only, unsigned)
+    //   pool.tokens.len() custody oracles (read-only, unsigned)
+    //   pool.tokens.len() custody secondary oracles, if any custody has one (read-only, unsigned)
+}
+
+pub fn execute_remove_liquidity<'info>(
+    ctx: Context<'_, '_, 'info, 'info, ExecuteRemoveLiquidity<'info>>,
+) -> Result<()> {
+    // check cooldown after the request
+    let curtime = ctx.accounts.perpetuals.get_time()?;
+    let cooldown_end = math::checked_add(
+        ctx.accounts.withdrawal_request.request_time,
+        ctx.accounts.pool.withdrawal.cooldown_sec as i64,
+    )?;
+    require!(curtime >= cooldown_end, PerpetualsError::WithdrawalCooldown);
+
+    // release escrowed lp tokens
+    msg!("Release escrowed LP tokens");
+    let lp_amount_in = ctx.accounts.withdrawal_request.lp_amount;
+    ctx.accounts.perpetuals.transfer_token(
+        ctx.accounts.withdrawal_escrow_token_account.to_account_info(),
+        ctx.accounts.lp_token_account.to_account_info(),
+        ctx.accounts.transfer_authority.to_account_info(),
+        lp_amount_in,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+    ctx.accounts.lp_token_account.reload()?;
+
+    settle_remove_liquidity(
+        &ctx.accounts.perpetuals,
+        &mut ctx.accounts.pool,
+        &mut ctx.accounts.custody,
+        &ctx.accounts.custody_oracle_account,
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        ctx.accounts.custody_oracle_history.as_deref_mut(),
+        &ctx.accounts.custody_token_account,
+        &ctx.accounts.receiving_account,
+        &ctx.accounts.lp_token_mint,
+        &ctx.accounts.lp_token_account,
+        &ctx.accounts.owner,
+        &ctx.accounts.transfer_authority,
+        &ctx.accounts.token_program,
+        ctx.remaining_accounts,
+        lp_amount_in,
+        ctx.accounts.withdrawal_request.min_amount_out,
+    )
+}
+
+/**
+ * cancel remove liquidity: returns escrowed lp tokens and closes the request
+ */
+#[derive(Accounts)]
+pub struct CancelRemoveLiquidity<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = lp_token_account.mint == withdrawal_escrow_token_account.mint,
+        has_one = owner
+    )]
+    pub lp_token_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"withdrawal_escrow_token_account",
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub withdrawal_escrow_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [b"withdrawal_request",
+                 owner.key().as_ref(),
+                 pool.key().as_ref()],
+        bump = withdrawal_request.bump,
+        close = owner
+    )]
+    pub withdrawal_request: Box<Account<'info, WithdrawalRequest>>,
+
+    token_program: Program<'info, Token>,
+}
+
+pub fn cancel_remove_liquidity(ctx: Context<CancelRemoveLiquidity>) -> Result<()> {
+    let withdrawal_request = ctx.accounts.withdrawal_request.as_ref();
+
+    msg!("Return escrowed LP tokens");
+    ctx.accounts.perpetuals.transfer_token(
+        ctx.accounts.withdrawal_escrow_token_account.to_account_info(),
+        ctx.accounts.lp_token_account.to_account_info(),
+        ctx.accounts.transfer_authority.to_account_info(),
+        withdrawal_request.lp_amount,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    emit!(CancelRemoveLiquidityEvent {
+        owner: withdrawal_request.owner,
+        pool: withdrawal_request.pool,
+        custody: withdrawal_request.custody,
+        lp_amount: withdrawal_request.lp_amount,
+        time: ctx.accounts.perpetuals.get_time()?,
+    });
+
+    Ok(())
+}
+
+// burns lp tokens from lp_token_account and pays out the custody token, shared by the
+// single-step and the two-step withdrawal flows
+#[allow(clippy::too_many_arguments)]
+fn settle_remove_liquidity<'info>(
+    perpetuals: &Perpetuals,
+    pool: &mut Account<'info, Pool>,
+    custody: &mut Account<'info, Custody>,
+    custody_oracle_account: &AccountInfo<'info>,
+    custody_secondary_oracle_account: Option<&AccountInfo<'info>>,
+    custody_oracle_history: Option<&mut Account<'info, OracleHistory>>,
+    custody_token_account: &Account<'info, TokenAccount>,
+    receiving_account: &Account<'info, TokenAccount>,
+    lp_token_mint: &Account<'info, Mint>,
+    lp_token_account: &Account<'info, TokenAccount>,
+    owner: &Signer<'info>,
+    transfer_authority: &AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    remaining_accounts: &'info [AccountInfo<'info>],
+    lp_amount_in: u64,
+    min_amount_out: u64,
+) -> Result<()> {
+    // check permissions
+    msg!("Check permissions");
+    require!(
+        perpetuals.permissions.allow_remove_liquidity
+            && custody.permissions.allow_remove_liquidity
+            && !custody.is_virtual,
+        PerpetualsError::InstructionNotAllowed
+    );
+
+    // validate inputs
+    msg!("Validate inputs");
+    if lp_amount_in == 0 {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+    let token_id = pool.get_token_id(&custody.key())?;
+
+    // compute assets under management
+    msg!("Compute assets under management");
+    let curtime = perpetuals.get_time()?;
+    let pool_amount_usd =
+        pool.get_assets_under_management_usd(AumCalcMode::Min, remaining_accounts, curtime)?;
+
+    let token_price = OraclePrice::new_from_oracle(
+        &custody_oracle_account.to_account_info(),
+        custody_secondary_oracle_account,
+        &custody.oracle,
+        curtime,
+        false,
+    )?;
+
+    let token_ema_price = OraclePrice::new_from_oracle(
+        &custody_oracle_account.to_account_info(),
+        custody_secondary_oracle_account,
+        &custody.oracle,
+        curtime,
+        custody.pricing.use_ema,
+    )?;
+
+    // record a sample so the TWAP doesn't depend on the update_oracle_history crank
+    if let Some(oracle_history) = custody_oracle_history {
+        oracle_history.update_custody_twap(custody, &token_price, curtime)?;
+    }
+
+    // check price bands, no withdrawals at prices outside of the band, lp tokens stay with the
+    // owner (a pending withdrawal request is closed)
+    if custody.check_circuit_breaker(&token_price, curtime)? {
+        msg!("Circuit breaker tripped, liquidity was not removed");
+        return Ok(());
+    }
+
+    // compute amount of tokens to return
+    let (remove_amount, fee_amount, remove_amount_usd) = pool.get_remove_liquidity_amount(
+        token_id,
+        lp_amount_in,
+        lp_token_mint.supply,
+        pool_amount_usd,
+        custody,
+        &token_price,
+        &token_ema_price,
+    )?;
+    msg!("Collected fee: {}", fee_amount);
+
+    let transfer_amount = math::checked_sub(remove_amount, fee_amount)?;
+    msg!("Amount out: {}", transfer_amount);
+
+    require!(
+        transfer_amount >= min_amount_out,
+        PerpetualsError::MaxPriceSlippage
+    );
+
+    // check pool constraints
+    msg!("Check pool constraints");
+    let protocol_fee = Pool::get_fee_amount(custody.fees.protocol_share, fee_amount)?;
+    let insurance_fee = Pool::get_fee_amount(custody.fees.insurance_share, fee_amount)?;
+    // staking fees are only reserved in the LP staking reward custody
+    let staking_fee = if custody.is_lp_staking_reward {
+        Pool::get_fee_amount(custody.fees.staking_share, fee_amount)?
+    } else {
+        0
+    };
+    let withdrawal_amount = math::checked_add(
+        transfer_amount,
+        math::checked_add(math::checked_add(protocol_fee, insurance_fee)?, staking_fee)?,
+    )?;
+    require!(
+        pool.check_token_ratio(token_id, 0, withdrawal_amount, custody, &token_ema_price)?,
+        PerpetualsError::TokenRatioOutOfRange
+    );
+    require!(
+        math::checked_sub(custody.assets.owned, custody.assets.locked)? >= withdrawal_amount,
+        PerpetualsError::CustodyAmountLimit
+    );
+    pool.record_withdrawal(remove_amount_usd, pool_amount_usd, curtime)?;
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    perpetuals.transfer_token(
+        custody_token_account.to_account_info(),
+        receiving_account.to_account_info(),
+        transfer_authority.to_account_info(),
+        transfer_amount,
+        token_program.to_account_info(),
+    )?;
+
+    // burn lp tokens
+    msg!("Burn LP tokens");
+    perpetuals.burn_tokens(
+        lp_token_mint.to_account_info(),
+        lp_token_account.to_account_info(),
+        owner.to_account_info(),
+        token_program.to_account_info(),
+        lp_amount_in,
+    )?;
+
+    // update custody stats
+    msg!("Update custody stats");
+    custody.collected_fees.remove_liquidity_usd = custody
+        .collected_fees
+        .remove_liquidity_usd
+        .wrapping_add(token_ema_price.get_asset_amount_usd(fee_amount, custody.decimals)?);
+
+    custody.volume_stats.remove_liquidity_usd = custody
+        .volume_stats
+        .remove_liquidity_usd
+        .wrapping_add(remove_amount_usd);
+
+    custody.assets.protocol_fees = math::checked_add(custody.assets.protocol_fees, protocol_fee)?;
+    custody.assets.insurance_fund =
+        math::checked_add(custody.assets.insurance_fund, insurance_fee)?;
+    custody.assets.staking_rewards =
+        math::checked_add(custody.assets.staking_rewards, staking_fee)?;
+
+    custody.assets.owned = math::checked_sub(custody.assets.owned, withdrawal_amount)?;
+
+    custody.update_borrow_rate(curtime)?;
+
     // update pool stats
     msg!("Update pool stats");
     custody.exit(&crate::ID)?;
     pool.aum_usd =
-        pool.get_assets_under_management_usd(AumCalcMode::EMA, ctx.remaining_accounts, curtime)?;
+        pool.get_assets_under_management_usd(AumCalcMode::Twap, remaining_accounts, curtime)?;
+
+    emit!(RemoveLiquidityEvent {
+        owner: owner.key(),
+        pool: pool.key(),
+        custody: custody.key(),
+        lp_amount_in,
+        fee_amount,
+        amount_out: transfer_amount,
+        amount_out_usd: remove_amount_usd,
+        aum_usd: pool.aum_usd,
+        time: curtime,
+    });
 
     Ok(())
-}
\ No newline at end of file
+}
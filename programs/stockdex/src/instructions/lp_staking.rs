@@ -0,0 +1,596 @@
+//! LP staking instruction handlers: InitLpStaking, StakeLp, UnstakeLp, ClaimLpStakingRewards
+//! and the DistributeLpStakingRewards crank
+
+use {
+    crate::{
+        error::PerpetualsError,
+        events::{
+            ClaimLpStakingRewardsEvent, DistributeLpStakingRewardsEvent, InitLpStakingEvent,
+            StakeLpEvent, UnstakeLpEvent,
+        },
+        math,
+        state::{
+            custody::Custody,
+            lp_staking::{LpStake, LpStaking},
+            multisig::{AdminInstruction, Multisig},
+            perps::Perpetuals,
+            pool::Pool,
+            proposal::Proposal,
+        },
+    },
+    anchor_lang::prelude::*,
+    anchor_spl::token::{Mint, Token, TokenAccount},
+};
+
+/**
+ * Init LP staking
+ */
+#[derive(Accounts)]
+pub struct InitLpStaking<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, Multisig>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 reward_custody.mint.as_ref()],
+        bump = reward_custody.bump
+    )]
+    pub reward_custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        constraint = reward_mint.key() == reward_custody.mint
+    )]
+    pub reward_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        seeds = [b"lp_token_mint",
+                 pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Box<Account<'info, Mint>>,
+
+    // instruction can be called multiple times due to multisig use, hence init_if_needed
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = LpStaking::LEN,
+        seeds = [b"lp_staking",
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub lp_staking: Box<Account<'info, LpStaking>>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        token::mint = lp_token_mint,
+        token::authority = transfer_authority,
+        seeds = [b"lp_staking_stake_token_account",
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub stake_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        token::mint = reward_mint,
+        token::authority = transfer_authority,
+        seeds = [b"lp_staking_reward_token_account",
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub reward_token_account: Box<Account<'info, TokenAccount>>,
+
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+    rent: Sysvar<'info, Rent>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Option<Account<'info, Proposal>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct InitLpStakingParams {}
+
+pub fn init_lp_staking<'info>(
+    ctx: Context<'_, '_, '_, 'info, InitLpStaking<'info>>,
+    params: &InitLpStakingParams,
+) -> Result<u8> {
+    require!(
+        ctx.accounts.reward_custody.is_stable && !ctx.accounts.reward_custody.is_virtual,
+        PerpetualsError::InvalidCollateralCustody
+    );
+
+    let mut multisig = ctx.accounts.multisig.load_mut()?;
+
+    let signatures_left = multisig.sign_multisig(
+        &ctx.accounts.admin,
+        &Multisig::get_account_infos(&ctx)[1..],
+        &Multisig::get_instruction_data(AdminInstruction::InitLpStaking, params)?,
+        ctx.accounts.proposal.as_deref_mut(),
+    )?;
+    if signatures_left > 0 {
+        msg!(
+            "Instruction has been signed but more signatures are required: {}",
+            signatures_left
+        );
+        return Ok(signatures_left);
+    }
+
+    let lp_staking = ctx.accounts.lp_staking.as_mut();
+    if lp_staking.pool != Pubkey::default() {
+        return Err(ProgramError::AccountAlreadyInitialized.into());
+    }
+
+    lp_staking.pool = ctx.accounts.pool.key();
+    lp_staking.reward_custody = ctx.accounts.reward_custody.key();
+    lp_staking.bump = ctx.bumps.lp_staking;
+    lp_staking.stake_token_account_bump = ctx.bumps.stake_token_account;
+    lp_staking.reward_token_account_bump = ctx.bumps.reward_token_account;
+
+    // from now on staking fees are reserved in the reward custody
+    ctx.accounts.reward_custody.is_lp_staking_reward = true;
+
+    emit!(InitLpStakingEvent {
+        pool: lp_staking.pool,
+        reward_custody: lp_staking.reward_custody,
+        time: ctx.accounts.perpetuals.get_time()?,
+    });
+
+    Ok(0)
+}
+
+/**
+ * Stake LP tokens
+ */
+#[derive(Accounts)]
+pub struct StakeLp<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = lp_token_account.mint == lp_token_mint.key(),
+        has_one = owner
+    )]
+    pub lp_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"lp_token_mint",
+                 pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [b"lp_staking",
+                 pool.key().as_ref()],
+        bump = lp_staking.bump
+    )]
+    pub lp_staking: Box<Account<'info, LpStaking>>,
+
+    #[account(
+        mut,
+        seeds = [b"lp_staking_stake_token_account",
+                 pool.key().as_ref()],
+        bump = lp_staking.stake_token_account_bump
+    )]
+    pub stake_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = LpStake::LEN,
+        seeds = [b"lp_stake",
+                 owner.key().as_ref(),
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub lp_stake: Box<Account<'info, LpStake>>,
+
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct StakeLpParams {
+    pub amount: u64,
+}
+
+pub fn stake_lp(ctx: Context<StakeLp>, params: &StakeLpParams) -> Result<()> {
+    // validate inputs
+    msg!("Validate inputs");
+    if params.amount == 0 {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+
+    let lp_staking = ctx.accounts.lp_staking.as_mut();
+    let lp_stake = ctx.accounts.lp_stake.as_mut();
+    if lp_stake.owner == Pubkey::default() {
+        lp_stake.owner = ctx.accounts.owner.key();
+        lp_stake.pool = ctx.accounts.pool.key();
+        lp_stake.bump = ctx.bumps.lp_stake;
+    }
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    ctx.accounts.perpetuals.transfer_token_from_user(
+        ctx.accounts.lp_token_account.to_account_info(),
+        ctx.accounts.stake_token_account.to_account_info(),
+        ctx.accounts.owner.to_account_info(),
+        params.amount,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    // update stake
+    msg!("Update stake");
+    lp_stake.settle_rewards(lp_staking)?;
+    lp_stake.amount = math::checked_add(lp_stake.amount, params.amount)?;
+    lp_stake.stake_time = ctx.accounts.perpetuals.get_time()?;
+    lp_staking.total_staked = math::checked_add(lp_staking.total_staked, params.amount)?;
+
+    emit!(StakeLpEvent {
+        owner: lp_stake.owner,
+        pool: lp_stake.pool,
+        amount: params.amount,
+        total_staked: lp_staking.total_staked,
+        time: lp_stake.stake_time,
+    });
+
+    Ok(())
+}
+
+/**
+ * Unstake LP tokens
+ */
+#[derive(Accounts)]
+pub struct UnstakeLp<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = lp_token_account.mint == lp_token_mint.key(),
+        has_one = owner
+    )]
+    pub lp_token_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"lp_token_mint",
+                 pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [b"lp_staking",
+                 pool.key().as_ref()],
+        bump = lp_staking.bump
+    )]
+    pub lp_staking: Box<Account<'info, LpStaking>>,
+
+    #[account(
+        mut,
+        seeds = [b"lp_staking_stake_token_account",
+                 pool.key().as_ref()],
+        bump = lp_staking.stake_token_account_bump
+    )]
+    pub stake_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [b"lp_stake",
+                 owner.key().as_ref(),
+                 pool.key().as_ref()],
+        bump = lp_stake.bump
+    )]
+    pub lp_stake: Box<Account<'info, LpStake>>,
+
+    token_program: Program<'info, Token>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct UnstakeLpParams {
+    pub amount: u64,
+}
+
+pub fn unstake_lp(ctx: Context<UnstakeLp>, params: &UnstakeLpParams) -> Result<()> {
+    // validate inputs
+    msg!("Validate inputs");
+    let lp_staking = ctx.accounts.lp_staking.as_mut();
+    let lp_stake = ctx.accounts.lp_stake.as_mut();
+    if params.amount == 0 || params.amount > lp_stake.amount {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+
+    // stakes can't be timed around distributions
+    let curtime = ctx.accounts.perpetuals.get_time()?;
+    require!(
+        curtime >= math::checked_add(lp_stake.stake_time, LpStaking::MIN_STAKE_PERIOD_SEC)?,
+        PerpetualsError::LpStakeLocked
+    );
+
+    // update stake
+    msg!("Update stake");
+    lp_stake.settle_rewards(lp_staking)?;
+    lp_stake.amount = math::checked_sub(lp_stake.amount, params.amount)?;
+    lp_staking.total_staked = math::checked_sub(lp_staking.total_staked, params.amount)?;
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    ctx.accounts.perpetuals.transfer_token(
+        ctx.accounts.stake_token_account.to_account_info(),
+        ctx.accounts.lp_token_account.to_account_info(),
+        ctx.accounts.transfer_authority.to_account_info(),
+        params.amount,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    emit!(UnstakeLpEvent {
+        owner: lp_stake.owner,
+        pool: lp_stake.pool,
+        amount: params.amount,
+        total_staked: lp_staking.total_staked,
+        time: curtime,
+    });
+
+    Ok(())
+}
+
+/**
+ * Claim LP staking rewards
+ */
+#[derive(Accounts)]
+pub struct ClaimLpStakingRewards<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = receiving_account.mint == reward_token_account.mint,
+        has_one = owner
+    )]
+    pub receiving_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"lp_staking",
+                 pool.key().as_ref()],
+        bump = lp_staking.bump
+    )]
+    pub lp_staking: Box<Account<'info, LpStaking>>,
+
+    #[account(
+        mut,
+        seeds = [b"lp_staking_reward_token_account",
+                 pool.key().as_ref()],
+        bump = lp_staking.reward_token_account_bump
+    )]
+    pub reward_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [b"lp_stake",
+                 owner.key().as_ref(),
+                 pool.key().as_ref()],
+        bump = lp_stake.bump
+    )]
+    pub lp_stake: Box<Account<'info, LpStake>>,
+
+    token_program: Program<'info, Token>,
+}
+
+pub fn claim_lp_staking_rewards(ctx: Context<ClaimLpStakingRewards>) -> Result<()> {
+    let lp_stake = ctx.accounts.lp_stake.as_mut();
+    lp_stake.settle_rewards(&ctx.accounts.lp_staking)?;
+
+    let amount = lp_stake.pending_rewards;
+    require!(amount > 0, PerpetualsError::InsufficientAmountReturned);
+    msg!("Amount out: {}", amount);
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    ctx.accounts.perpetuals.transfer_token(
+        ctx.accounts.reward_token_account.to_account_info(),
+        ctx.accounts.receiving_account.to_account_info(),
+        ctx.accounts.transfer_authority.to_account_info(),
+        amount,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    lp_stake.pending_rewards = 0;
+
+    emit!(ClaimLpStakingRewardsEvent {
+        owner: lp_stake.owner,
+        pool: lp_stake.pool,
+        amount,
+        time: ctx.accounts.perpetuals.get_time()?,
+    });
+
+    Ok(())
+}
+
+/**
+ * Permissionless crank that moves the staking fee share of the reward custody to stakers
+ */
+#[derive(Accounts)]
+pub struct DistributeLpStakingRewards<'info> {
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"lp_staking",
+                 pool.key().as_ref()],
+        bump = lp_staking.bump
+    )]
+    pub lp_staking: Box<Account<'info, LpStaking>>,
+
+    #[account(
+        mut,
+        constraint = reward_custody.key() == lp_staking.reward_custody
+    )]
+    pub reward_custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 reward_custody.mint.as_ref()],
+        bump = reward_custody.token_account_bump
+    )]
+    pub reward_custody_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"lp_staking_reward_token_account",
+                 pool.key().as_ref()],
+        bump = lp_staking.reward_token_account_bump
+    )]
+    pub reward_token_account: Box<Account<'info, TokenAccount>>,
+
+    token_program: Program<'info, Token>,
+}
+
+pub fn distribute_lp_staking_rewards(ctx: Context<DistributeLpStakingRewards>) -> Result<()> {
+    let lp_staking = ctx.accounts.lp_staking.as_mut();
+    let reward_custody = ctx.accounts.reward_custody.as_mut();
+
+    // rewards stay reserved in the custody until there are stakers
+    let amount = reward_custody.assets.staking_rewards;
+    if amount == 0 || lp_staking.total_staked == 0 {
+        msg!("Nothing to distribute");
+        return Ok(());
+    }
+    msg!("Distribute rewards: {}", amount);
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    ctx.accounts.perpetuals.transfer_token(
+        ctx.accounts.reward_custody_token_account.to_account_info(),
+        ctx.accounts.reward_token_account.to_account_info(),
+        ctx.accounts.transfer_authority.to_account_info(),
+        amount,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    let curtime = ctx.accounts.perpetuals.get_time()?;
+    reward_custody.assets.staking_rewards = 0;
+    lp_staking.add_rewards(amount, curtime)?;
+
+    emit!(DistributeLpStakingRewardsEvent {
+        pool: lp_staking.pool,
+        reward_custody: lp_staking.reward_custody,
+        amount,
+        total_staked: lp_staking.total_staked,
+        time: curtime,
+    });
+
+    Ok(())
+}
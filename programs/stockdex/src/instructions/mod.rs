@@ -2,4 +2,17 @@ pub mod collateral;
 pub mod pools;
 pub mod position;
 pub mod liquidity;
-pub mod init;
\ No newline at end of file
+pub mod init;
+pub mod multisig;
+pub mod dividends;
+pub mod circuit_breaker;
+pub mod views;
+pub mod auto_deleverage;
+pub mod referral;
+pub mod lp_staking;
+pub mod oracle_history;
+pub mod orders;
+pub mod delegation;
+pub mod transfer_position;
+pub mod permissions;
+pub mod insurance_fund;
\ No newline at end of file
@@ -0,0 +1,401 @@
+//! SetAdminSigners, UpgradeMultisig, SetTimelock, CancelQueuedInstruction, CreateProposal and
+//! CloseProposal instruction handlers
+
+use {
+    crate::{
+        error::PerpetualsError,
+        events::{
+            CancelQueuedInstructionEvent, CloseProposalEvent, CreateProposalEvent,
+            SetAdminSignersEvent, SetTimelockEvent, UpgradeMultisigEvent,
+        },
+        math,
+        state::{
+            multisig::{AdminInstruction, DeprecatedMultisig, Multisig},
+            perps::Perpetuals,
+            proposal::Proposal,
+        },
+    },
+    anchor_lang::{prelude::*, Discriminator},
+};
+
+/**
+ * Set admin signers, new signers are passed as remaining accounts in the same order as weights
+ */
+#[derive(Accounts)]
+pub struct SetAdminSigners<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, Multisig>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Option<Account<'info, Proposal>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct SetAdminSignersParams {
+    pub min_weight: u8,
+    pub weights: Vec<u8>,
+}
+
+pub fn set_admin_signers<'info>(
+    ctx: Context<'_, '_, '_, 'info, SetAdminSigners<'info>>,
+    params: &SetAdminSignersParams,
+) -> Result<u8> {
+    let mut multisig = ctx.accounts.multisig.load_mut()?;
+
+    let signatures_left = multisig.sign_multisig(
+        &ctx.accounts.admin,
+        &Multisig::get_account_infos(&ctx)[1..],
+        &Multisig::get_instruction_data(AdminInstruction::SetAdminSigners, params)?,
+        ctx.accounts.proposal.as_deref_mut(),
+    )?;
+    if signatures_left > 0 {
+        msg!(
+            "Instruction has been signed but more signatures are required: {}",
+            signatures_left
+        );
+        return Ok(signatures_left);
+    }
+
+    msg!(
+        "Set admin signers: {}, min weight: {}",
+        ctx.remaining_accounts.len(),
+        params.min_weight
+    );
+    multisig.set_signers(ctx.remaining_accounts, &params.weights, params.min_weight)?;
+
+    emit!(SetAdminSignersEvent {
+        signers: ctx
+            .remaining_accounts
+            .iter()
+            .map(|account| account.key())
+            .collect(),
+        weights: params.weights.clone(),
+        min_weight: params.min_weight,
+        time: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(0)
+}
+
+/**
+ * Upgrade multisig: permissionless migration of the 6 signer layout to weighted signers,
+ * payer tops up the rent of the resized account
+ */
+#[derive(Accounts)]
+pub struct UpgradeMultisig<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: deprecated multisig account, validated in the handler
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump
+    )]
+    pub multisig: AccountInfo<'info>,
+
+    system_program: Program<'info, System>,
+}
+
+pub fn upgrade_multisig(ctx: Context<UpgradeMultisig>) -> Result<()> {
+    // validate inputs
+    let multisig_info = ctx.accounts.multisig.to_account_info();
+    require_keys_eq!(
+        *multisig_info.owner,
+        crate::ID,
+        ErrorCode::AccountOwnedByWrongProgram
+    );
+    let deprecated = {
+        let data = multisig_info.try_borrow_data()?;
+        if data.len() < 8 || data[..8] != Multisig::DISCRIMINATOR[..] {
+            return err!(ErrorCode::AccountDiscriminatorMismatch);
+        }
+        require_eq!(
+            data.len(),
+            8 + std::mem::size_of::<DeprecatedMultisig>(),
+            PerpetualsError::InvalidMultisigState
+        );
+        *bytemuck::from_bytes::<DeprecatedMultisig>(&data[8..])
+    };
+    let multisig = Multisig::from_deprecated(&deprecated);
+
+    // top up rent and resize
+    msg!("Upgrade multisig");
+    Perpetuals::realloc_account(
+        ctx.accounts.payer.to_account_info(),
+        multisig_info.clone(),
+        ctx.accounts.system_program.to_account_info(),
+        Multisig::LEN,
+        true,
+    )?;
+
+    // write the converted state after the discriminator
+    let mut data = multisig_info.try_borrow_mut_data()?;
+    data[8..].copy_from_slice(bytemuck::bytes_of(&multisig));
+
+    emit!(UpgradeMultisigEvent {
+        payer: ctx.accounts.payer.key(),
+        time: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+/**
+ * Set timelock
+ */
+#[derive(Accounts)]
+pub struct SetTimelock<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, Multisig>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Option<Account<'info, Proposal>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct SetTimelockParams {
+    pub timelock_sec: u32,
+}
+
+pub fn set_timelock<'info>(
+    ctx: Context<'_, '_, '_, 'info, SetTimelock<'info>>,
+    params: &SetTimelockParams,
+) -> Result<u8> {
+    // validate inputs
+    if params.timelock_sec > Multisig::MAX_TIMELOCK_SEC {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+
+    let mut multisig = ctx.accounts.multisig.load_mut()?;
+
+    let signatures_left = multisig.sign_multisig(
+        &ctx.accounts.admin,
+        &Multisig::get_account_infos(&ctx)[1..],
+        &Multisig::get_instruction_data(AdminInstruction::SetTimelock, params)?,
+        ctx.accounts.proposal.as_deref_mut(),
+    )?;
+    if signatures_left > 0 {
+        msg!(
+            "Instruction has been signed but more signatures are required: {}",
+            signatures_left
+        );
+        return Ok(signatures_left);
+    }
+
+    msg!("Set timelock: {}", params.timelock_sec);
+    multisig.timelock_sec = params.timelock_sec;
+
+    emit!(SetTimelockEvent {
+        timelock_sec: params.timelock_sec,
+        time: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(0)
+}
+
+/**
+ * Cancel queued instruction, executes on quorum without waiting for the timelock.
+ * Cancels queued_proposal if provided, otherwise the instruction queued in the multisig.
+ */
+#[derive(Accounts)]
+pub struct CancelQueuedInstruction<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, Multisig>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Option<Account<'info, Proposal>>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 queued_proposal.id.to_le_bytes().as_ref()],
+        bump = queued_proposal.bump
+    )]
+    pub queued_proposal: Option<Account<'info, Proposal>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct CancelQueuedInstructionParams {}
+
+pub fn cancel_queued_instruction<'info>(
+    ctx: Context<'_, '_, '_, 'info, CancelQueuedInstruction<'info>>,
+    params: &CancelQueuedInstructionParams,
+) -> Result<u8> {
+    let mut multisig = ctx.accounts.multisig.load_mut()?;
+
+    let signatures_left = multisig.sign_multisig_without_timelock(
+        &ctx.accounts.admin,
+        &Multisig::get_account_infos(&ctx)[1..],
+        &Multisig::get_instruction_data(AdminInstruction::CancelQueuedInstruction, params)?,
+        ctx.accounts.proposal.as_deref_mut(),
+    )?;
+    if signatures_left > 0 {
+        msg!(
+            "Instruction has been signed but more signatures are required: {}",
+            signatures_left
+        );
+        return Ok(signatures_left);
+    }
+
+    msg!("Cancel queued instruction");
+    let curtime = Clock::get()?.unix_timestamp;
+    let proposal_id = if let Some(queued_proposal) = ctx.accounts.queued_proposal.as_deref_mut() {
+        queued_proposal.cancel(curtime);
+        Some(queued_proposal.id)
+    } else {
+        multisig.clear_queue();
+        None
+    };
+
+    emit!(CancelQueuedInstructionEvent {
+        proposal_id,
+        time: curtime,
+    });
+
+    Ok(0)
+}
+
+/**
+ * Create proposal: empty proposal to collect signatures for one admin instruction,
+ * the id is assigned from the multisig proposal counter
+ */
+#[derive(Accounts)]
+pub struct CreateProposal<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, Multisig>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = Proposal::LEN,
+        seeds = [b"proposal",
+                 multisig.load()?.proposal_count.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub proposal: Box<Account<'info, Proposal>>,
+
+    system_program: Program<'info, System>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct CreateProposalParams {
+    pub expiry_sec: u32,
+}
+
+pub fn create_proposal(ctx: Context<CreateProposal>, params: &CreateProposalParams) -> Result<()> {
+    // validate inputs
+    let mut multisig = ctx.accounts.multisig.load_mut()?;
+    require!(
+        multisig.is_signer(&ctx.accounts.admin.key())?,
+        PerpetualsError::MultisigAccountNotAuthorized
+    );
+    // the proposal must outlive the timelock to be executable
+    if params.expiry_sec <= multisig.timelock_sec || params.expiry_sec > Proposal::MAX_EXPIRY_SEC {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+
+    let id = multisig.proposal_count;
+    msg!("Create proposal {}", id);
+    multisig.proposal_count = math::checked_add(id, 1)?;
+
+    let curtime = Clock::get()?.unix_timestamp;
+    let proposal = ctx.accounts.proposal.as_mut();
+    proposal.id = id;
+    proposal.proposer = ctx.accounts.admin.key();
+    proposal.creation_time = curtime;
+    proposal.expiry_time = math::checked_add(curtime, params.expiry_sec as i64)?;
+    proposal.bump = ctx.bumps.proposal;
+
+    emit!(CreateProposalEvent {
+        id: proposal.id,
+        proposer: proposal.proposer,
+        expiry_time: proposal.expiry_time,
+        time: curtime,
+    });
+
+    Ok(())
+}
+
+/**
+ * Close proposal: returns rent of an executed or expired proposal to the proposer
+ */
+#[derive(Accounts)]
+pub struct CloseProposal<'info> {
+    /// CHECK: proposer of the proposal, receives the rent
+    #[account(
+        mut,
+        constraint = proposer.key() == proposal.proposer
+    )]
+    pub proposer: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump,
+        close = proposer
+    )]
+    pub proposal: Box<Account<'info, Proposal>>,
+}
+
+pub fn close_proposal(ctx: Context<CloseProposal>) -> Result<()> {
+    let proposal = ctx.accounts.proposal.as_ref();
+    let curtime = Clock::get()?.unix_timestamp;
+    require!(
+        proposal.executed || curtime >= proposal.expiry_time,
+        PerpetualsError::InvalidProposalState
+    );
+
+    emit!(CloseProposalEvent {
+        id: proposal.id,
+        proposer: proposal.proposer,
+        executed: proposal.executed,
+        time: curtime,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,143 @@
+//! InitOracleHistory and UpdateOracleHistory instruction handlers
+
+use {
+    crate::{
+        events::{InitOracleHistoryEvent, UpdateOracleHistoryEvent},
+        state::{
+            custody::Custody, oracle::OraclePrice, oracle_history::OracleHistory,
+            perps::Perpetuals, pool::Pool,
+        },
+    },
+    anchor_lang::prelude::*,
+};
+
+/**
+ * init oracle history: permissionless, creates the price ring buffer for a custody
+ */
+#[derive(Accounts)]
+pub struct InitOracleHistory<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = OracleHistory::LEN,
+        seeds = [b"oracle_history",
+                 custody.key().as_ref()],
+        bump
+    )]
+    pub oracle_history: Box<Account<'info, OracleHistory>>,
+
+    system_program: Program<'info, System>,
+}
+
+pub fn init_oracle_history(ctx: Context<InitOracleHistory>) -> Result<()> {
+    let oracle_history = ctx.accounts.oracle_history.as_mut();
+    oracle_history.custody = ctx.accounts.custody.key();
+    oracle_history.bump = ctx.bumps.oracle_history;
+
+    emit!(InitOracleHistoryEvent {
+        pool: ctx.accounts.pool.key(),
+        custody: oracle_history.custody,
+        time: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+/**
+ * Permissionless crank that records the oracle price and refreshes the custody TWAP.
+ * Instructions that read the TWAP also record samples when given the oracle history, but
+ * pool AUM reads the TWAP of every custody, so the crank must keep each custody with
+ * use_twap fresh within its oracle max_price_age_sec.
+ */
+#[derive(Accounts)]
+pub struct UpdateOracleHistory<'info> {
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the custody token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"oracle_history",
+                 custody.key().as_ref()],
+        bump = oracle_history.bump
+    )]
+    pub oracle_history: Box<Account<'info, OracleHistory>>,
+
+    /// CHECK: secondary oracle account for the custody token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+}
+
+pub fn update_oracle_history(ctx: Context<UpdateOracleHistory>) -> Result<()> {
+    let custody = ctx.accounts.custody.as_mut();
+    let curtime = ctx.accounts.perpetuals.get_time()?;
+
+    let token_price = OraclePrice::new_from_oracle(
+        &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &custody.oracle,
+        curtime,
+        false,
+    )?;
+
+    // check price bands, recording prices is allowed while halted
+    custody.update_circuit_breaker(&token_price, curtime)?;
+
+    msg!("Record price sample");
+    ctx.accounts
+        .oracle_history
+        .update_custody_twap(custody, &token_price, curtime)?;
+
+    emit!(UpdateOracleHistoryEvent {
+        pool: ctx.accounts.pool.key(),
+        custody: custody.key(),
+        price: token_price.price,
+        exponent: token_price.exponent,
+        twap_price: custody.twap.price,
+        twap_exponent: custody.twap.exponent,
+        time: curtime,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,945 @@
+//! RequestOpenPosition, ExecuteOpenPosition, RequestClosePosition, ExecuteClosePosition and
+//! CancelOrder instruction handlers for custodies in delayed order mode
+
+use {
+    crate::{
+        error::PerpetualsError,
+        events::{CancelOrderEvent, ExecuteOrderEvent, RequestOrderEvent},
+        instructions::position::{
+            close_position_internal, open_position_internal, ClosePositionParams,
+            OpenPositionParams,
+        },
+        math,
+        state::{
+            custody::Custody,
+            oracle::OraclePrice,
+            order_request::{OrderRequest, OrderType},
+            perps::Perpetuals,
+            pool::Pool,
+            position::{Position, Side},
+            position_counter::PositionCounter,
+            referral::{Referral, ReferralRewards},
+            user_stats::UserStats,
+        },
+    },
+    anchor_lang::prelude::*,
+    anchor_spl::token::{Mint, Token, TokenAccount},
+};
+
+/**
+ * request open position: escrows collateral and fee budget until a keeper fills the order,
+ * the position rent is prepaid with the request
+ */
+#[derive(Accounts)]
+#[instruction(params: RequestOpenPositionParams)]
+pub struct RequestOpenPosition<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = funding_account.mint == collateral_custody.mint,
+        has_one = owner
+    )]
+    pub funding_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    /// CHECK: address of the position to be opened, must not exist yet
+    #[account(
+        seeds = [b"position",
+                 owner.key().as_ref(),
+                 pool.key().as_ref(),
+                 custody.key().as_ref(),
+                 &[params.side as u8],
+                 Position::get_index_seed(params.index).as_slice()],
+        bump
+    )]
+    pub position: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 collateral_custody.mint.as_ref()],
+        bump = collateral_custody.bump
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        constraint = collateral_custody_mint.key() == collateral_custody.mint
+    )]
+    pub collateral_custody_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        token::mint = collateral_custody_mint,
+        token::authority = transfer_authority,
+        seeds = [b"order_escrow_token_account",
+                 collateral_custody.key().as_ref()],
+        bump
+    )]
+    pub order_escrow_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = OrderRequest::LEN,
+        seeds = [b"order_request",
+                 position.key().as_ref()],
+        bump
+    )]
+    pub order_request: Box<Account<'info, OrderRequest>>,
+
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+    rent: Sysvar<'info, Rent>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct RequestOpenPositionParams {
+    pub price: u64,
+    pub collateral: u64,
+    pub size: u64,
+    pub side: Side,
+    // max fee in collateral tokens, unused budget is refunded on fill
+    pub fee_budget: u64,
+    // 0 for the legacy position, otherwise the next index of the owner position counter
+    pub index: u32,
+}
+
+pub fn request_open_position(
+    ctx: Context<RequestOpenPosition>,
+    params: &RequestOpenPositionParams,
+) -> Result<()> {
+    // check permissions
+    msg!("Check permissions");
+    let perpetuals = ctx.accounts.perpetuals.as_ref();
+    let custody = ctx.accounts.custody.as_ref();
+    require!(
+        perpetuals.permissions.allow_open_position
+            && custody.permissions.allow_open_position
+            && custody.pricing.use_delayed_orders,
+        PerpetualsError::InstructionNotAllowed
+    );
+
+    // validate inputs
+    msg!("Validate inputs");
+    if params.price == 0 || params.collateral == 0 || params.size == 0 || params.side == Side::None
+    {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+    require!(
+        Perpetuals::is_empty_account(&ctx.accounts.position)?,
+        PerpetualsError::InvalidPositionState
+    );
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    let escrow_amount = math::checked_add(params.collateral, params.fee_budget)?;
+    perpetuals.transfer_token_from_user(
+        ctx.accounts.funding_account.to_account_info(),
+        ctx.accounts.order_escrow_token_account.to_account_info(),
+        ctx.accounts.owner.to_account_info(),
+        escrow_amount,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    // the keeper pays for the position account on fill and is reimbursed from the request,
+    // the owner gets the rent back when the position is closed or the request is cancelled
+    msg!("Prepay position rent");
+    Perpetuals::transfer_sol(
+        ctx.accounts.owner.to_account_info(),
+        ctx.accounts.order_request.to_account_info(),
+        ctx.accounts.system_program.to_account_info(),
+        ctx.accounts.rent.minimum_balance(Position::LEN),
+    )?;
+
+    // record request
+    msg!("Record order request");
+    let curtime = perpetuals.get_time()?;
+    let order_request = ctx.accounts.order_request.as_mut();
+    order_request.owner = ctx.accounts.owner.key();
+    order_request.pool = ctx.accounts.pool.key();
+    order_request.custody = custody.key();
+    order_request.collateral_custody = ctx.accounts.collateral_custody.key();
+    order_request.position = ctx.accounts.position.key();
+    order_request.order_type = OrderType::Open;
+    order_request.side = params.side;
+    order_request.index = params.index;
+    order_request.price = params.price;
+    order_request.collateral = params.collateral;
+    order_request.size = params.size;
+    order_request.escrow_amount = escrow_amount;
+    order_request.request_time = curtime;
+    order_request.expiry_time =
+        math::checked_add(curtime, custody.pricing.order_expiry_sec as i64)?;
+    order_request.bump = ctx.bumps.order_request;
+
+    emit!(RequestOrderEvent {
+        owner: order_request.owner,
+        pool: order_request.pool,
+        custody: order_request.custody,
+        collateral_custody: order_request.collateral_custody,
+        position: order_request.position,
+        order_type: order_request.order_type,
+        side: order_request.side,
+        price: order_request.price,
+        collateral: order_request.collateral,
+        size: order_request.size,
+        escrow_amount: order_request.escrow_amount,
+        expiry_time: order_request.expiry_time,
+        time: curtime,
+    });
+
+    Ok(())
+}
+
+/**
+ * execute open position: keeper fills an open request with a fresh oracle price
+ */
+#[derive(Accounts)]
+pub struct ExecuteOpenPosition<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    /// CHECK: owner of the order request
+    #[account(
+        mut,
+        constraint = owner.key() == order_request.owner
+    )]
+    pub owner: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = funding_account.mint == collateral_custody.mint,
+        constraint = funding_account.owner == order_request.owner
+    )]
+    pub funding_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        mut,
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        init,
+        payer = keeper,
+        space = Position::LEN,
+        seeds = [b"position",
+                 order_request.owner.as_ref(),
+                 pool.key().as_ref(),
+                 custody.key().as_ref(),
+                 &[order_request.side as u8],
+                 Position::get_index_seed(order_request.index).as_slice()],
+        bump
+    )]
+    pub position: Box<Account<'info, Position>>,
+
+    #[account(
+        mut,
+        constraint = order_request.custody == custody.key()
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the position token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = order_request.collateral_custody == collateral_custody.key()
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the collateral token
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 collateral_custody.mint.as_ref()],
+        bump = collateral_custody.token_account_bump
+    )]
+    pub collateral_custody_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"order_escrow_token_account",
+                 collateral_custody.key().as_ref()],
+        bump
+    )]
+    pub order_escrow_token_account: Box<Account<'info, TokenAccount>>,
+
+    // holds the prepaid position rent, reimburses the keeper for the position account
+    #[account(
+        mut,
+        seeds = [b"order_request",
+                 position.key().as_ref()],
+        bump = order_request.bump,
+        close = keeper
+    )]
+    pub order_request: Box<Account<'info, OrderRequest>>,
+
+    #[account(
+        init_if_needed,
+        payer = keeper,
+        space = UserStats::LEN,
+        seeds = [b"user_stats",
+                 order_request.owner.as_ref(),
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Box<Account<'info, UserStats>>,
+
+    #[account(
+        init_if_needed,
+        payer = keeper,
+        space = PositionCounter::LEN,
+        seeds = [b"position_counter",
+                 order_request.owner.as_ref()],
+        bump
+    )]
+    pub position_counter: Box<Account<'info, PositionCounter>>,
+
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+
+    #[account(
+        seeds = [b"referral",
+                 order_request.owner.as_ref()],
+        bump = referral.bump
+    )]
+    pub referral: Option<Account<'info, Referral>>,
+
+    #[account(
+        mut,
+        seeds = [b"referral_rewards",
+                 referral_rewards.referrer.as_ref(),
+                 collateral_custody.key().as_ref()],
+        bump = referral_rewards.bump,
+        constraint = referral.as_ref().is_some_and(|referral| referral.referrer == referral_rewards.referrer)
+            @ PerpetualsError::InvalidReferral
+    )]
+    pub referral_rewards: Option<Account<'info, ReferralRewards>>,
+
+    /// CHECK: secondary oracle account for the position token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    /// CHECK: secondary oracle account for the collateral token, required when configured
+    pub collateral_custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+}
+
+pub fn execute_open_position(ctx: Context<ExecuteOpenPosition>) -> Result<()> {
+    let order_request = ctx.accounts.order_request.as_ref();
+    require!(
+        order_request.order_type == OrderType::Open,
+        PerpetualsError::InvalidPositionState
+    );
+    let curtime = ctx.accounts.perpetuals.get_time()?;
+    check_fillable(
+        order_request,
+        &ctx.accounts.custody_oracle_account,
+        &ctx.accounts.custody,
+        curtime,
+    )?;
+
+    let params = OpenPositionParams {
+        price: order_request.price,
+        collateral: order_request.collateral,
+        size: order_request.size,
+        side: order_request.side,
+        index: order_request.index,
+    };
+    let escrow_amount = order_request.escrow_amount;
+
+    let transfer_amount = open_position_internal(
+        &ctx.accounts.perpetuals,
+        &mut ctx.accounts.pool,
+        &mut ctx.accounts.position,
+        ctx.bumps.position,
+        ctx.accounts.owner.key(),
+        &mut ctx.accounts.custody,
+        &ctx.accounts.custody_oracle_account,
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &mut ctx.accounts.collateral_custody,
+        &ctx.accounts.collateral_custody_oracle_account,
+        ctx.accounts.collateral_custody_secondary_oracle_account.as_ref(),
+        &mut ctx.accounts.user_stats,
+        ctx.bumps.user_stats,
+        &mut ctx.accounts.position_counter,
+        ctx.bumps.position_counter,
+        ctx.accounts.referral.as_deref(),
+        ctx.accounts.referral_rewards.as_deref_mut(),
+        &params,
+    )?;
+
+    // the breaker has just tripped, refund the escrow instead of filling
+    let Some(transfer_amount) = transfer_amount else {
+        msg!("Circuit breaker tripped, refund escrow");
+        ctx.accounts.perpetuals.transfer_token(
+            ctx.accounts.order_escrow_token_account.to_account_info(),
+            ctx.accounts.funding_account.to_account_info(),
+            ctx.accounts.transfer_authority.to_account_info(),
+            escrow_amount,
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+
+        emit!(ExecuteOrderEvent {
+            owner: ctx.accounts.owner.key(),
+            keeper: ctx.accounts.keeper.key(),
+            position: ctx.accounts.position.key(),
+            order_type: OrderType::Open,
+            filled: false,
+            refund_amount: escrow_amount,
+            time: curtime,
+        });
+
+        // the keeper is reimbursed from the request, the prepaid rent goes back to the owner
+        return ctx
+            .accounts
+            .position
+            .close(ctx.accounts.owner.to_account_info());
+    };
+    require_gte!(
+        escrow_amount,
+        transfer_amount,
+        PerpetualsError::OrderFeeBudgetExceeded
+    );
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    ctx.accounts.perpetuals.transfer_token(
+        ctx.accounts.order_escrow_token_account.to_account_info(),
+        ctx.accounts
+            .collateral_custody_token_account
+            .to_account_info(),
+        ctx.accounts.transfer_authority.to_account_info(),
+        transfer_amount,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    // refund unused fee budget
+    let refund_amount = math::checked_sub(escrow_amount, transfer_amount)?;
+    if refund_amount > 0 {
+        ctx.accounts.perpetuals.transfer_token(
+            ctx.accounts.order_escrow_token_account.to_account_info(),
+            ctx.accounts.funding_account.to_account_info(),
+            ctx.accounts.transfer_authority.to_account_info(),
+            refund_amount,
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+    }
+
+    emit!(ExecuteOrderEvent {
+        owner: ctx.accounts.owner.key(),
+        keeper: ctx.accounts.keeper.key(),
+        position: ctx.accounts.position.key(),
+        order_type: OrderType::Open,
+        filled: true,
+        refund_amount,
+        time: curtime,
+    });
+
+    Ok(())
+}
+
+/**
+ * request close position: records a close order to be filled by a keeper
+ */
+#[derive(Accounts)]
+pub struct RequestClosePosition<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        has_one = owner,
+        seeds = [b"position",
+                 owner.key().as_ref(),
+                 pool.key().as_ref(),
+                 custody.key().as_ref(),
+                 &[position.side as u8],
+                 Position::get_index_seed(position.index).as_slice()],
+        bump = position.bump
+    )]
+    pub position: Box<Account<'info, Position>>,
+
+    #[account(
+        constraint = position.custody == custody.key()
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = OrderRequest::LEN,
+        seeds = [b"order_request",
+                 position.key().as_ref()],
+        bump
+    )]
+    pub order_request: Box<Account<'info, OrderRequest>>,
+
+    system_program: Program<'info, System>,
+}
+
+pub fn request_close_position(
+    ctx: Context<RequestClosePosition>,
+    params: &ClosePositionParams,
+) -> Result<()> {
+    // check permissions
+    msg!("Check permissions");
+    let perpetuals = ctx.accounts.perpetuals.as_ref();
+    let custody = ctx.accounts.custody.as_ref();
+    require!(
+        perpetuals.permissions.allow_close_position
+            && custody.permissions.allow_close_position
+            && custody.pricing.use_delayed_orders,
+        PerpetualsError::InstructionNotAllowed
+    );
+
+    // validate inputs
+    msg!("Validate inputs");
+    if params.price == 0 {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+
+    // record request
+    msg!("Record order request");
+    let curtime = perpetuals.get_time()?;
+    let position = ctx.accounts.position.as_ref();
+    let order_request = ctx.accounts.order_request.as_mut();
+    order_request.owner = ctx.accounts.owner.key();
+    order_request.pool = ctx.accounts.pool.key();
+    order_request.custody = custody.key();
+    order_request.collateral_custody = position.collateral_custody;
+    order_request.position = position.key();
+    order_request.order_type = OrderType::Close;
+    order_request.side = position.side;
+    order_request.price = params.price;
+    order_request.collateral = 0;
+    order_request.size = 0;
+    order_request.escrow_amount = 0;
+    order_request.request_time = curtime;
+    order_request.expiry_time =
+        math::checked_add(curtime, custody.pricing.order_expiry_sec as i64)?;
+    order_request.bump = ctx.bumps.order_request;
+
+    emit!(RequestOrderEvent {
+        owner: order_request.owner,
+        pool: order_request.pool,
+        custody: order_request.custody,
+        collateral_custody: order_request.collateral_custody,
+        position: order_request.position,
+        order_type: order_request.order_type,
+        side: order_request.side,
+        price: order_request.price,
+        collateral: order_request.collateral,
+        size: order_request.size,
+        escrow_amount: order_request.escrow_amount,
+        expiry_time: order_request.expiry_time,
+        time: curtime,
+    });
+
+    Ok(())
+}
+
+/**
+ * execute close position: keeper fills a close request with a fresh oracle price
+ */
+#[derive(Accounts)]
+pub struct ExecuteClosePosition<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    /// CHECK: owner of the position
+    #[account(
+        mut,
+        constraint = owner.key() == position.owner
+    )]
+    pub owner: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = receiving_account.mint == collateral_custody.mint,
+        constraint = receiving_account.owner == position.owner
+    )]
+    pub receiving_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        mut,
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"position",
+                 position.owner.as_ref(),
+                 pool.key().as_ref(),
+                 custody.key().as_ref(),
+                 &[position.side as u8],
+                 Position::get_index_seed(position.index).as_slice()],
+        bump = position.bump,
+        close = owner
+    )]
+    pub position: Box<Account<'info, Position>>,
+
+    #[account(
+        mut,
+        constraint = position.custody == custody.key()
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the position token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = position.collateral_custody == collateral_custody.key()
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the collateral token
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 collateral_custody.mint.as_ref()],
+        bump = collateral_custody.token_account_bump
+    )]
+    pub collateral_custody_token_account: Box<Account<'info, TokenAccount>>,
+
+    // underwater positions draw on the insurance fund first, see add_insurance_fund
+    #[account(
+        mut,
+        seeds = [b"insurance_fund_token_account",
+                 pool.key().as_ref(),
+                 collateral_custody.mint.as_ref()],
+        bump
+    )]
+    pub insurance_fund_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"order_request",
+                 position.key().as_ref()],
+        bump = order_request.bump,
+        close = keeper
+    )]
+    pub order_request: Box<Account<'info, OrderRequest>>,
+
+    #[account(
+        init_if_needed,
+        payer = keeper,
+        space = UserStats::LEN,
+        seeds = [b"user_stats",
+                 position.owner.as_ref(),
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Box<Account<'info, UserStats>>,
+
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+
+    #[account(
+        seeds = [b"referral",
+                 position.owner.as_ref()],
+        bump = referral.bump
+    )]
+    pub referral: Option<Account<'info, Referral>>,
+
+    #[account(
+        mut,
+        seeds = [b"referral_rewards",
+                 referral_rewards.referrer.as_ref(),
+                 collateral_custody.key().as_ref()],
+        bump = referral_rewards.bump,
+        constraint = referral.as_ref().is_some_and(|referral| referral.referrer == referral_rewards.referrer)
+            @ PerpetualsError::InvalidReferral
+    )]
+    pub referral_rewards: Option<Account<'info, ReferralRewards>>,
+
+    /// CHECK: secondary oracle account for the position token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    /// CHECK: secondary oracle account for the collateral token, required when configured
+    pub collateral_custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+}
+
+pub fn execute_close_position(ctx: Context<ExecuteClosePosition>) -> Result<()> {
+    let order_request = ctx.accounts.order_request.as_ref();
+    require!(
+        order_request.order_type == OrderType::Close,
+        PerpetualsError::InvalidPositionState
+    );
+    let curtime = ctx.accounts.perpetuals.get_time()?;
+    check_fillable(
+        order_request,
+        &ctx.accounts.custody_oracle_account,
+        &ctx.accounts.custody,
+        curtime,
+    )?;
+
+    let params = ClosePositionParams {
+        price: order_request.price,
+    };
+
+    let (transfer_amount, insurance_fund_amount) = close_position_internal(
+        &ctx.accounts.perpetuals,
+        &mut ctx.accounts.pool,
+        &mut ctx.accounts.position,
+        ctx.accounts.owner.key(),
+        &mut ctx.accounts.custody,
+        &ctx.accounts.custody_oracle_account,
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &mut ctx.accounts.collateral_custody,
+        &ctx.accounts.collateral_custody_oracle_account,
+        ctx.accounts.collateral_custody_secondary_oracle_account.as_ref(),
+        &mut ctx.accounts.user_stats,
+        ctx.bumps.user_stats,
+        ctx.accounts.referral.as_deref(),
+        ctx.accounts.referral_rewards.as_deref_mut(),
+        ctx.accounts.insurance_fund_token_account.amount,
+        &params,
+    )?;
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    if insurance_fund_amount > 0 {
+        ctx.accounts.perpetuals.transfer_token(
+            ctx.accounts.insurance_fund_token_account.to_account_info(),
+            ctx.accounts
+                .collateral_custody_token_account
+                .to_account_info(),
+            ctx.accounts.transfer_authority.to_account_info(),
+            insurance_fund_amount,
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+    }
+    ctx.accounts.perpetuals.transfer_token(
+        ctx.accounts
+            .collateral_custody_token_account
+            .to_account_info(),
+        ctx.accounts.receiving_account.to_account_info(),
+        ctx.accounts.transfer_authority.to_account_info(),
+        transfer_amount,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    emit!(ExecuteOrderEvent {
+        owner: ctx.accounts.owner.key(),
+        keeper: ctx.accounts.keeper.key(),
+        position: ctx.accounts.position.key(),
+        order_type: OrderType::Close,
+        filled: true,
+        refund_amount: 0,
+        time: curtime,
+    });
+
+    Ok(())
+}
+
+/**
+ * cancel order: permissionless once expired, refunds escrowed tokens and prepaid rent to the owner
+ */
+#[derive(Accounts)]
+pub struct CancelOrder<'info> {
+    pub signer: Signer<'info>,
+
+    /// CHECK: owner of the order request
+    #[account(
+        mut,
+        constraint = owner.key() == order_request.owner
+    )]
+    pub owner: AccountInfo<'info>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        mut,
+        seeds = [b"order_request",
+                 order_request.position.as_ref()],
+        bump = order_request.bump,
+        close = owner
+    )]
+    pub order_request: Box<Account<'info, OrderRequest>>,
+
+    token_program: Program<'info, Token>,
+
+    // escrow and refund accounts are only needed for open requests
+    #[account(
+        mut,
+        seeds = [b"order_escrow_token_account",
+                 order_request.collateral_custody.as_ref()],
+        bump
+    )]
+    pub order_escrow_token_account: Option<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = receiving_account.owner == order_request.owner
+    )]
+    pub receiving_account: Option<Account<'info, TokenAccount>>,
+}
+
+pub fn cancel_order(ctx: Context<CancelOrder>) -> Result<()> {
+    let order_request = ctx.accounts.order_request.as_ref();
+    let curtime = ctx.accounts.perpetuals.get_time()?;
+    require!(
+        curtime > order_request.expiry_time,
+        PerpetualsError::OrderNotExpired
+    );
+
+    if order_request.escrow_amount > 0 {
+        let (Some(order_escrow_token_account), Some(receiving_account)) = (
+            ctx.accounts.order_escrow_token_account.as_ref(),
+            ctx.accounts.receiving_account.as_ref(),
+        ) else {
+            return Err(ProgramError::NotEnoughAccountKeys.into());
+        };
+        require_keys_eq!(receiving_account.mint, order_escrow_token_account.mint);
+
+        msg!("Refund escrowed tokens");
+        ctx.accounts.perpetuals.transfer_token(
+            order_escrow_token_account.to_account_info(),
+            receiving_account.to_account_info(),
+            ctx.accounts.transfer_authority.to_account_info(),
+            order_request.escrow_amount,
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+    }
+
+    emit!(CancelOrderEvent {
+        owner: order_request.owner,
+        position: order_request.position,
+        order_type: order_request.order_type,
+        refund_amount: order_request.escrow_amount,
+        time: curtime,
+    });
+
+    Ok(())
+}
+
+// orders can only be filled before expiry with a price published shortly after the request,
+// so the keeper (possibly the owner) can't pick a favorable price over the whole expiry period
+fn check_fillable(
+    order_request: &OrderRequest,
+    custody_oracle_account: &AccountInfo,
+    custody: &Custody,
+    curtime: i64,
+) -> Result<()> {
+    require!(
+        curtime <= order_request.expiry_time,
+        PerpetualsError::OrderExpired
+    );
+    let publish_time = OraclePrice::get_publish_time(custody_oracle_account, &custody.oracle)?;
+    require!(
+        publish_time > order_request.request_time,
+        PerpetualsError::OrderPriceNotUpdated
+    );
+    let fill_window_end = math::checked_add(
+        order_request.request_time,
+        custody.pricing.order_fill_window_sec as i64,
+    )?;
+    require!(
+        publish_time <= fill_window_end,
+        PerpetualsError::OrderFillWindowMissed
+    );
+    Ok(())
+}
@@ -0,0 +1,243 @@
+//! SetPermissions, SetGuardian and GuardianPause instruction handlers
+
+use {
+    crate::{
+        error::PerpetualsError,
+        events::{GuardianPauseEvent, SetGuardianEvent, SetPermissionsEvent},
+        state::{
+            custody::Custody,
+            multisig::{AdminInstruction, Multisig},
+            perps::{Perpetuals, Permissions},
+            proposal::Proposal,
+        },
+    },
+    anchor_lang::prelude::*,
+};
+
+/**
+ * Set permissions, updates custody permissions if custody is provided, global ones otherwise
+ */
+#[derive(Accounts)]
+pub struct SetPermissions<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, Multisig>,
+
+    #[account(
+        mut,
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Option<Account<'info, Proposal>>,
+
+    #[account(
+        mut,
+        seeds = [b"custody",
+                 custody.pool.as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Option<Box<Account<'info, Custody>>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct SetPermissionsParams {
+    pub permissions: Permissions,
+}
+
+pub fn set_permissions<'info>(
+    ctx: Context<'_, '_, '_, 'info, SetPermissions<'info>>,
+    params: &SetPermissionsParams,
+) -> Result<u8> {
+    let mut multisig = ctx.accounts.multisig.load_mut()?;
+
+    let signatures_left = multisig.sign_multisig(
+        &ctx.accounts.admin,
+        &Multisig::get_account_infos(&ctx)[1..],
+        &Multisig::get_instruction_data(AdminInstruction::SetPermissions, params)?,
+        ctx.accounts.proposal.as_deref_mut(),
+    )?;
+    if signatures_left > 0 {
+        msg!(
+            "Instruction has been signed but more signatures are required: {}",
+            signatures_left
+        );
+        return Ok(signatures_left);
+    }
+
+    let custody = if let Some(custody) = ctx.accounts.custody.as_deref_mut() {
+        msg!("Set custody permissions: {}", custody.key());
+        custody.permissions = params.permissions;
+        Some(custody.key())
+    } else {
+        msg!("Set global permissions");
+        ctx.accounts.perpetuals.permissions = params.permissions;
+        None
+    };
+
+    emit!(SetPermissionsEvent {
+        custody,
+        permissions: params.permissions,
+        time: ctx.accounts.perpetuals.get_time()?,
+    });
+
+    Ok(0)
+}
+
+/**
+ * Set guardian, the key allowed to pause trading without the multisig
+ */
+#[derive(Accounts)]
+pub struct SetGuardian<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, Multisig>,
+
+    #[account(
+        mut,
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Option<Account<'info, Proposal>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct SetGuardianParams {
+    // Pubkey::default() removes the guardian
+    pub guardian: Pubkey,
+}
+
+pub fn set_guardian<'info>(
+    ctx: Context<'_, '_, '_, 'info, SetGuardian<'info>>,
+    params: &SetGuardianParams,
+) -> Result<u8> {
+    let mut multisig = ctx.accounts.multisig.load_mut()?;
+
+    let signatures_left = multisig.sign_multisig(
+        &ctx.accounts.admin,
+        &Multisig::get_account_infos(&ctx)[1..],
+        &Multisig::get_instruction_data(AdminInstruction::SetGuardian, params)?,
+        ctx.accounts.proposal.as_deref_mut(),
+    )?;
+    if signatures_left > 0 {
+        msg!(
+            "Instruction has been signed but more signatures are required: {}",
+            signatures_left
+        );
+        return Ok(signatures_left);
+    }
+
+    msg!("Set guardian: {}", params.guardian);
+    ctx.accounts.perpetuals.guardian = params.guardian;
+
+    emit!(SetGuardianEvent {
+        guardian: params.guardian,
+        time: ctx.accounts.perpetuals.get_time()?,
+    });
+
+    Ok(0)
+}
+
+/**
+ * Guardian pause: disables the selected permissions globally or for the provided custody.
+ * Can't re-enable anything, that requires set_permissions.
+ */
+#[derive(Accounts)]
+pub struct GuardianPause<'info> {
+    #[account(
+        constraint = guardian.key() == perpetuals.guardian
+            && perpetuals.guardian != Pubkey::default()
+            @ PerpetualsError::GuardianAccountNotAuthorized
+    )]
+    pub guardian: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        mut,
+        seeds = [b"custody",
+                 custody.pool.as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Option<Box<Account<'info, Custody>>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct GuardianPauseParams {
+    pub pause_open_position: bool,
+    pub pause_swap: bool,
+    pub pause_add_liquidity: bool,
+}
+
+pub fn guardian_pause(ctx: Context<GuardianPause>, params: &GuardianPauseParams) -> Result<()> {
+    // validate inputs
+    if !params.pause_open_position && !params.pause_swap && !params.pause_add_liquidity {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+
+    let curtime = ctx.accounts.perpetuals.get_time()?;
+    let custody = ctx.accounts.custody.as_ref().map(|custody| custody.key());
+    let permissions = if let Some(custody) = ctx.accounts.custody.as_deref_mut() {
+        msg!("Guardian pause custody: {}", custody.key());
+        &mut custody.permissions
+    } else {
+        msg!("Guardian pause global");
+        &mut ctx.accounts.perpetuals.permissions
+    };
+
+    // flags are only ever cleared here
+    if params.pause_open_position {
+        permissions.allow_open_position = false;
+    }
+    if params.pause_swap {
+        permissions.allow_swap = false;
+    }
+    if params.pause_add_liquidity {
+        permissions.allow_add_liquidity = false;
+    }
+
+    emit!(GuardianPauseEvent {
+        guardian: ctx.accounts.guardian.key(),
+        custody,
+        pause_open_position: params.pause_open_position,
+        pause_swap: params.pause_swap,
+        pause_add_liquidity: params.pause_add_liquidity,
+        time: curtime,
+    });
+
+    Ok(())
+}
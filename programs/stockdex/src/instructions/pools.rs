@@ -1,10 +1,12 @@
 use {crate::
     {
         error::PerpetualsError,
+        events::{AddPoolEvent, SetFeeTiersEvent, SetWithdrawalParamsEvent},
         state:: {
             perps::Perpetuals,
-            pool::Pool,
-            multisig::{AdminInstruction, Multisig}
+            pool::{FeeTier, Pool, TokenRatios, WithdrawalParams, WithdrawalState},
+            multisig::{AdminInstruction, Multisig},
+            proposal::Proposal
         },
     },
     anchor_lang::prelude::*,
@@ -82,6 +84,14 @@ pub struct AddPool<'info> {
     system_program: Program<'info, System>,
     token_program: Program<'info, Token>,
     rent: Sysvar<'info, Rent>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Option<Account<'info, Proposal>>,
 }
 
 pub fn add_pool<'info>(
@@ -98,6 +108,7 @@ pub fn add_pool<'info>(
         &ctx.accounts.admin,
         &Multisig::get_account_infos(&ctx)[1..],
         &Multisig::get_instruction_data(AdminInstruction::AddPool, params)?,
+        ctx.accounts.proposal.as_deref_mut(),
     )?;
     if signatures_left > 0 {
         msg!(
@@ -126,5 +137,171 @@ pub fn add_pool<'info>(
 
     perpetuals.pools.push(ctx.accounts.pool.key());
 
+    emit!(AddPoolEvent {
+        pool: ctx.accounts.pool.key(),
+        name: params.name.clone(),
+        lp_token_mint: ctx.accounts.lp_token_mint.key(),
+        time: ctx.accounts.pool.inception_time,
+    });
+
+    Ok(0)
+}
+/**
+ * Set fee tiers
+ */
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct SetFeeTiersParams {
+    pub fee_tiers: Vec<FeeTier>,
+}
+
+#[derive(Accounts)]
+#[instruction(params: SetFeeTiersParams)]
+pub struct SetFeeTiers<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, Multisig>,
+
+    #[account(
+        mut,
+        realloc = Pool::LEN
+            + pool.custodies.len() * std::mem::size_of::<Pubkey>()
+            + pool.ratios.len() * std::mem::size_of::<TokenRatios>()
+            + params.fee_tiers.len() * std::mem::size_of::<FeeTier>(),
+        realloc::payer = admin,
+        realloc::zero = false,
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    system_program: Program<'info, System>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Option<Account<'info, Proposal>>,
+}
+
+pub fn set_fee_tiers<'info>(
+    ctx: Context<'_, '_, '_, 'info, SetFeeTiers<'info>>,
+    params: &SetFeeTiersParams
+) -> Result<u8> {
+    if params.fee_tiers.len() > Pool::MAX_FEE_TIERS {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+
+    let mut multisig = ctx.accounts.multisig.load_mut()?;
+
+    let signatures_left = multisig.sign_multisig(
+        &ctx.accounts.admin,
+        &Multisig::get_account_infos(&ctx)[1..],
+        &Multisig::get_instruction_data(AdminInstruction::SetFeeTiers, params)?,
+        ctx.accounts.proposal.as_deref_mut(),
+    )?;
+    if signatures_left > 0 {
+        msg!(
+            "Instruction has been signed but more signatures are required: {}",
+            signatures_left
+        );
+        return Ok(signatures_left);
+    }
+
+    let pool = ctx.accounts.pool.as_mut();
+    pool.fee_tiers = params.fee_tiers.clone();
+
+    if !pool.validate() {
+        return err!(PerpetualsError::InvalidPoolConfig);
+    }
+
+    emit!(SetFeeTiersEvent {
+        pool: pool.key(),
+        fee_tiers: params.fee_tiers.clone(),
+        time: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(0)
+}
+
+/**
+ * Set withdrawal params
+ */
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct SetWithdrawalParamsParams {
+    pub withdrawal: WithdrawalParams,
+}
+
+#[derive(Accounts)]
+pub struct SetWithdrawalParams<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, Multisig>,
+
+    #[account(
+        mut,
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Option<Account<'info, Proposal>>,
+}
+
+pub fn set_withdrawal_params<'info>(
+    ctx: Context<'_, '_, '_, 'info, SetWithdrawalParams<'info>>,
+    params: &SetWithdrawalParamsParams
+) -> Result<u8> {
+    let mut multisig = ctx.accounts.multisig.load_mut()?;
+
+    let signatures_left = multisig.sign_multisig(
+        &ctx.accounts.admin,
+        &Multisig::get_account_infos(&ctx)[1..],
+        &Multisig::get_instruction_data(AdminInstruction::SetWithdrawalParams, params)?,
+        ctx.accounts.proposal.as_deref_mut(),
+    )?;
+    if signatures_left > 0 {
+        msg!(
+            "Instruction has been signed but more signatures are required: {}",
+            signatures_left
+        );
+        return Ok(signatures_left);
+    }
+
+    let pool = ctx.accounts.pool.as_mut();
+    pool.withdrawal = params.withdrawal;
+    pool.withdrawal_state = WithdrawalState::default();
+
+    if !pool.validate() {
+        return err!(PerpetualsError::InvalidPoolConfig);
+    }
+
+    emit!(SetWithdrawalParamsEvent {
+        pool: pool.key(),
+        withdrawal: params.withdrawal,
+        time: Clock::get()?.unix_timestamp,
+    });
+
     Ok(0)
-}
\ No newline at end of file
+}
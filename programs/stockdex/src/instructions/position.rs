@@ -1,5 +1,5 @@
 use {
-    crate::{error::PerpetualsError, math, state::{custody::Custody, pool::Pool, position::{Position, Side}, perps::Perpetuals, oracle::OraclePrice}},
+    crate::{error::PerpetualsError, events::{ClosePositionEvent, LiquidateEvent, OpenPositionEvent}, math, state::{custody::Custody, delegation::{DelegatedAction, Delegation}, pool::Pool, position::{Position, Side}, position_counter::PositionCounter, perps::Perpetuals, oracle::OraclePrice, oracle_history::OracleHistory, referral::{Referral, ReferralRewards}, user_stats::UserStats}},
     anchor_lang::prelude::*,
     anchor_spl::token::{Token, TokenAccount},
     solana_program::program_error::ProgramError,
@@ -11,18 +11,23 @@ pub struct OpenPositionParams {
     pub collateral: u64,
     pub size: u64,
     pub side: Side,
+    // 0 for the legacy position, otherwise the next index of the owner position counter
+    pub index: u32,
 }
 
 #[derive(Accounts)]
 #[instruction(params: OpenPositionParams)]
 pub struct OpenPosition<'info> {
     #[account(mut)]
-    pub owner: Signer<'info>,
+    pub authority: Signer<'info>,
+
+    /// CHECK: position owner, either the authority or the account delegating to it
+    pub owner: AccountInfo<'info>,
 
     #[account(
         mut,
         constraint = funding_account.mint == collateral_custody.mint,
-        has_one = owner
+        constraint = funding_account.owner == authority.key()
     )]
     pub funding_account: Box<Account<'info, TokenAccount>>,
 
@@ -49,13 +54,14 @@ pub struct OpenPosition<'info> {
 
     #[account(
         init,
-        payer = owner,
+        payer = authority,
         space = Position::LEN,
         seeds = [b"position",
                  owner.key().as_ref(),
                  pool.key().as_ref(),
                  custody.key().as_ref(),
-                 &[params.side as u8]],
+                 &[params.side as u8],
+                 Position::get_index_seed(params.index).as_slice()],
         bump
     )]
     pub position: Box<Account<'info, Position>>,
@@ -99,16 +105,155 @@ pub struct OpenPosition<'info> {
     )]
     pub collateral_custody_token_account: Box<Account<'info, TokenAccount>>,
 
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = UserStats::LEN,
+        seeds = [b"user_stats",
+                 owner.key().as_ref(),
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Box<Account<'info, UserStats>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = PositionCounter::LEN,
+        seeds = [b"position_counter",
+                 owner.key().as_ref()],
+        bump
+    )]
+    pub position_counter: Box<Account<'info, PositionCounter>>,
+
     system_program: Program<'info, System>,
     token_program: Program<'info, Token>,
+
+    #[account(
+        seeds = [b"referral",
+                 owner.key().as_ref()],
+        bump = referral.bump
+    )]
+    pub referral: Option<Account<'info, Referral>>,
+
+    #[account(
+        mut,
+        seeds = [b"referral_rewards",
+                 referral_rewards.referrer.as_ref(),
+                 collateral_custody.key().as_ref()],
+        bump = referral_rewards.bump,
+        constraint = referral.as_ref().is_some_and(|referral| referral.referrer == referral_rewards.referrer)
+            @ PerpetualsError::InvalidReferral
+    )]
+    pub referral_rewards: Option<Account<'info, ReferralRewards>>,
+
+    /// CHECK: secondary oracle account for the position token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    /// CHECK: secondary oracle account for the collateral token, required when configured
+    pub collateral_custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    #[account(
+        seeds = [b"delegation",
+                 owner.key().as_ref(),
+                 authority.key().as_ref()],
+        bump = delegation.bump
+    )]
+    pub delegation: Option<Account<'info, Delegation>>,
 }
 
 pub fn open_position(ctx: Context<OpenPosition>, params: &OpenPositionParams) -> Result<()> {
+    // check authority
+    Delegation::check_authority(
+        &ctx.accounts.authority.key(),
+        &ctx.accounts.owner.key(),
+        ctx.accounts.delegation.as_deref(),
+        DelegatedAction::OpenPosition,
+        ctx.accounts.perpetuals.get_time()?,
+    )?;
+
+    // custodies in delayed order mode only accept keeper-filled order requests
+    require!(
+        !ctx.accounts.custody.pricing.use_delayed_orders,
+        PerpetualsError::InstructionNotAllowed
+    );
+
+    let transfer_amount = open_position_internal(
+        &ctx.accounts.perpetuals,
+        &mut ctx.accounts.pool,
+        &mut ctx.accounts.position,
+        ctx.bumps.position,
+        ctx.accounts.owner.key(),
+        &mut ctx.accounts.custody,
+        &ctx.accounts.custody_oracle_account,
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &mut ctx.accounts.collateral_custody,
+        &ctx.accounts.collateral_custody_oracle_account,
+        ctx.accounts.collateral_custody_secondary_oracle_account.as_ref(),
+        &mut ctx.accounts.user_stats,
+        ctx.bumps.user_stats,
+        &mut ctx.accounts.position_counter,
+        ctx.bumps.position_counter,
+        ctx.accounts.referral.as_deref(),
+        ctx.accounts.referral_rewards.as_deref_mut(),
+        params,
+    )?;
+    let Some(transfer_amount) = transfer_amount else {
+        msg!("Circuit breaker tripped, position was not opened");
+        return ctx
+            .accounts
+            .position
+            .close(ctx.accounts.authority.to_account_info());
+    };
+
+    // delegates are limited in position size
+    if ctx.accounts.authority.key() != ctx.accounts.owner.key() {
+        if let Some(delegation) = ctx.accounts.delegation.as_deref() {
+            delegation.check_size(ctx.accounts.position.size_usd)?;
+        }
+    }
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    ctx.accounts.perpetuals.transfer_token_from_user(
+        ctx.accounts.funding_account.to_account_info(),
+        ctx.accounts
+            .collateral_custody_token_account
+            .to_account_info(),
+        ctx.accounts.authority.to_account_info(),
+        transfer_amount,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    Ok(())
+}
+
+// Settles a new position and updates custody and user stats, returns the amount of collateral
+// tokens (including fees) the caller has to transfer to the custody, or None if the circuit
+// breaker has just tripped and the position was not opened
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn open_position_internal<'info>(
+    perpetuals: &Perpetuals,
+    pool: &mut Account<'info, Pool>,
+    position: &mut Account<'info, Position>,
+    position_bump: u8,
+    owner: Pubkey,
+    custody: &mut Account<'info, Custody>,
+    custody_oracle_account: &AccountInfo<'info>,
+    custody_secondary_oracle_account: Option<&AccountInfo<'info>>,
+    collateral_custody: &mut Account<'info, Custody>,
+    collateral_custody_oracle_account: &AccountInfo<'info>,
+    collateral_custody_secondary_oracle_account: Option<&AccountInfo<'info>>,
+    user_stats: &mut Account<'info, UserStats>,
+    user_stats_bump: u8,
+    position_counter: &mut Account<'info, PositionCounter>,
+    position_counter_bump: u8,
+    referral: Option<&Referral>,
+    referral_rewards: Option<&mut ReferralRewards>,
+    params: &OpenPositionParams,
+) -> Result<Option<u64>> {
     // check permissions
     msg!("Check permissions");
-    let perpetuals = ctx.accounts.perpetuals.as_mut();
-    let custody = ctx.accounts.custody.as_mut();
-    let collateral_custody = ctx.accounts.collateral_custody.as_mut();
     require!(
         perpetuals.permissions.allow_open_position
             && custody.permissions.allow_open_position
@@ -132,49 +277,62 @@ pub fn open_position(ctx: Context<OpenPosition>, params: &OpenPositionParams) ->
     } else {
         require_keys_eq!(custody.key(), collateral_custody.key());
     };
-    let position = ctx.accounts.position.as_mut();
-    let pool = ctx.accounts.pool.as_mut();
 
     // compute position price
     let curtime = perpetuals.get_time()?;
 
     let token_price = OraclePrice::new_from_oracle(
-        &ctx.accounts.custody_oracle_account.to_account_info(),
+        &custody_oracle_account.to_account_info(),
+        custody_secondary_oracle_account,
         &custody.oracle,
         curtime,
         false,
     )?;
 
     let token_ema_price = OraclePrice::new_from_oracle(
-        &ctx.accounts.custody_oracle_account.to_account_info(),
+        &custody_oracle_account.to_account_info(),
+        custody_secondary_oracle_account,
         &custody.oracle,
         curtime,
         custody.pricing.use_ema,
     )?;
 
     let collateral_token_price = OraclePrice::new_from_oracle(
-        &ctx.accounts
-            .collateral_custody_oracle_account
-            .to_account_info(),
+        &collateral_custody_oracle_account.to_account_info(),
+        collateral_custody_secondary_oracle_account,
         &collateral_custody.oracle,
         curtime,
         false,
     )?;
 
     let collateral_token_ema_price = OraclePrice::new_from_oracle(
-        &ctx.accounts
-            .collateral_custody_oracle_account
-            .to_account_info(),
+        &collateral_custody_oracle_account.to_account_info(),
+        collateral_custody_secondary_oracle_account,
         &collateral_custody.oracle,
         curtime,
         collateral_custody.pricing.use_ema,
     )?;
 
+    // check price bands, custody only allows reducing risk while halted
+    let tripped = custody.check_circuit_breaker(&token_price, curtime)?;
+    if !use_collateral_custody {
+        collateral_custody.circuit_breaker_state = custody.circuit_breaker_state;
+    }
+    if tripped {
+        return Ok(None);
+    }
+
     let min_collateral_price = collateral_token_price
         .get_min_price(&collateral_token_ema_price, collateral_custody.is_stable)?;
 
-    let position_price =
-        pool.get_entry_price(&token_price, &token_ema_price, params.side, custody)?;
+    let trade_size_usd = token_price.get_asset_amount_usd(params.size, custody.decimals)?;
+    let position_price = pool.get_entry_price(
+        &token_price,
+        &token_ema_price,
+        params.side,
+        custody,
+        trade_size_usd,
+    )?;
     msg!("Entry price: {}", position_price);
 
     if params.side == Side::Long {
@@ -195,11 +353,15 @@ pub fn open_position(ctx: Context<OpenPosition>, params: &OpenPositionParams) ->
     let position_oracle_price = OraclePrice {
         price: position_price,
         exponent: -(Perpetuals::PRICE_DECIMALS as i32),
+        conf: 0,
     };
     let size_usd = position_oracle_price.get_asset_amount_usd(params.size, custody.decimals)?;
     let collateral_usd = min_collateral_price
         .get_asset_amount_usd(params.collateral, collateral_custody.decimals)?;
 
+    // check open interest limits
+    custody.check_open_interest(params.side, size_usd)?;
+
     let locked_amount = if use_collateral_custody {
         custody.get_locked_amount(
             min_collateral_price.get_token_amount(size_usd, collateral_custody.decimals)?,
@@ -225,26 +387,49 @@ pub fn open_position(ctx: Context<OpenPosition>, params: &OpenPositionParams) ->
     };
 
     // compute fee
+    let volume_usd = user_stats.get_volume_usd(curtime)?;
     let mut fee_amount = pool.get_entry_fee(
         custody.fees.open_position,
         params.size,
         locked_amount,
         collateral_custody,
+        volume_usd,
     )?;
-    let fee_amount_usd = token_ema_price.get_asset_amount_usd(fee_amount, custody.decimals)?;
+    let mut fee_amount_usd = token_ema_price.get_asset_amount_usd(fee_amount, custody.decimals)?;
     if use_collateral_custody {
         fee_amount = collateral_token_ema_price
             .get_token_amount(fee_amount_usd, collateral_custody.decimals)?;
     }
+
+    // referred traders get a discount, the referrer gets a share of the rest
+    let referral_kickback = if Referral::is_applied(
+        referral,
+        referral_rewards.as_deref(),
+        &collateral_custody.key(),
+    )? {
+        let (rebate, kickback) = Pool::get_referral_amounts(fee_amount, custody)?;
+        fee_amount = math::checked_sub(fee_amount, rebate)?;
+        fee_amount_usd = math::checked_sub(
+            fee_amount_usd,
+            Pool::get_fee_amount(custody.fees.referral_rebate, fee_amount_usd)?,
+        )?;
+        msg!("Referral rebate: {}", rebate);
+        kickback
+    } else {
+        0
+    };
     msg!("Collected fee: {}", fee_amount);
 
     // compute amount to transfer
     let transfer_amount = math::checked_add(params.collateral, fee_amount)?;
     msg!("Amount in: {}", transfer_amount);
 
+    // additional positions take the next index of the owner counter
+    position_counter.use_index(owner, params.index, position_counter_bump)?;
+
     // init new position
     msg!("Initialize new position");
-    position.owner = ctx.accounts.owner.key();
+    position.owner = owner;
     position.pool = pool.key();
     position.custody = custody.key();
     position.collateral_custody = collateral_custody.key();
@@ -260,7 +445,9 @@ pub fn open_position(ctx: Context<OpenPosition>, params: &OpenPositionParams) ->
     position.cumulative_interest_snapshot = collateral_custody.get_cumulative_interest(curtime)?;
     position.locked_amount = locked_amount;
     position.collateral_amount = params.collateral;
-    position.bump = ctx.bumps.position;
+    position.cumulative_dividend_snapshot = custody.get_cumulative_dividend(curtime)?;
+    position.index = params.index;
+    position.bump = position_bump;
 
     // check position risk
     msg!("Check position risks");
@@ -286,17 +473,14 @@ pub fn open_position(ctx: Context<OpenPosition>, params: &OpenPositionParams) ->
     // lock funds for potential profit payoff
     collateral_custody.lock_funds(position.locked_amount)?;
 
-    // transfer tokens
-    msg!("Transfer tokens");
-    perpetuals.transfer_token_from_user(
-        ctx.accounts.funding_account.to_account_info(),
-        ctx.accounts
-            .collateral_custody_token_account
-            .to_account_info(),
-        ctx.accounts.owner.to_account_info(),
-        transfer_amount,
-        ctx.accounts.token_program.to_account_info(),
-    )?;
+    // update user stats
+    if user_stats.owner == Pubkey::default() {
+        user_stats.owner = owner;
+        user_stats.pool = pool.key();
+        user_stats.bump = user_stats_bump;
+    }
+    user_stats.add_volume(size_usd, curtime)?;
+    user_stats.record_open(size_usd, fee_amount_usd);
 
     // update custody stats
     msg!("Update custody stats");
@@ -312,6 +496,27 @@ pub fn open_position(ctx: Context<OpenPosition>, params: &OpenPositionParams) ->
     collateral_custody.assets.protocol_fees =
         math::checked_add(collateral_custody.assets.protocol_fees, protocol_fee)?;
 
+    let insurance_fee = Pool::get_fee_amount(custody.fees.insurance_share, fee_amount)?;
+    collateral_custody.assets.insurance_fund =
+        math::checked_add(collateral_custody.assets.insurance_fund, insurance_fee)?;
+
+    // staking fees are only reserved in the LP staking reward custody
+    if collateral_custody.is_lp_staking_reward {
+        let staking_fee = Pool::get_fee_amount(custody.fees.staking_share, fee_amount)?;
+        collateral_custody.assets.staking_rewards =
+            math::checked_add(collateral_custody.assets.staking_rewards, staking_fee)?;
+    }
+
+    if let Some(referral_rewards) = referral_rewards {
+        collateral_custody.assets.referral_rewards =
+            math::checked_add(collateral_custody.assets.referral_rewards, referral_kickback)?;
+        referral_rewards.claimable_amount =
+            math::checked_add(referral_rewards.claimable_amount, referral_kickback)?;
+        referral_rewards.total_earned_amount = referral_rewards
+            .total_earned_amount
+            .wrapping_add(referral_kickback);
+    }
+
     // if custody and collateral_custody accounts are the same, ensure that data is in sync
     if position.side == Side::Long && !custody.is_virtual {
         collateral_custody.volume_stats.open_position_usd = collateral_custody
@@ -353,14 +558,34 @@ pub fn open_position(ctx: Context<OpenPosition>, params: &OpenPositionParams) ->
         collateral_custody.update_borrow_rate(curtime)?;
     }
 
-    Ok(())
+    emit!(OpenPositionEvent {
+        owner,
+        pool: pool.key(),
+        custody: custody.key(),
+        collateral_custody: collateral_custody.key(),
+        position: position.key(),
+        side: position.side,
+        entry_price: position.price,
+        size_usd,
+        collateral_usd,
+        collateral_amount: params.collateral,
+        fee_amount,
+        fee_usd: fee_amount_usd,
+        time: curtime,
+    });
+
+    Ok(Some(transfer_amount))
 }
 
 
 #[derive(Accounts)]
 pub struct ClosePosition<'info> {
     #[account(mut)]
-    pub owner: Signer<'info>,
+    pub authority: Signer<'info>,
+
+    /// CHECK: position owner, either the authority or the account delegating to it
+    #[account(mut)]
+    pub owner: AccountInfo<'info>,
 
     #[account(
         mut,
@@ -397,7 +622,8 @@ pub struct ClosePosition<'info> {
                  owner.key().as_ref(),
                  pool.key().as_ref(),
                  custody.key().as_ref(),
-                 &[position.side as u8]],
+                 &[position.side as u8],
+                 Position::get_index_seed(position.index).as_slice()],
         bump = position.bump,
         close = owner
     )]
@@ -436,7 +662,61 @@ pub struct ClosePosition<'info> {
     )]
     pub collateral_custody_token_account: Box<Account<'info, TokenAccount>>,
 
+    // underwater positions draw on the insurance fund first, see add_insurance_fund
+    #[account(
+        mut,
+        seeds = [b"insurance_fund_token_account",
+                 pool.key().as_ref(),
+                 collateral_custody.mint.as_ref()],
+        bump
+    )]
+    pub insurance_fund_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = UserStats::LEN,
+        seeds = [b"user_stats",
+                 owner.key().as_ref(),
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Box<Account<'info, UserStats>>,
+
+    system_program: Program<'info, System>,
     token_program: Program<'info, Token>,
+
+    #[account(
+        seeds = [b"referral",
+                 owner.key().as_ref()],
+        bump = referral.bump
+    )]
+    pub referral: Option<Account<'info, Referral>>,
+
+    #[account(
+        mut,
+        seeds = [b"referral_rewards",
+                 referral_rewards.referrer.as_ref(),
+                 collateral_custody.key().as_ref()],
+        bump = referral_rewards.bump,
+        constraint = referral.as_ref().is_some_and(|referral| referral.referrer == referral_rewards.referrer)
+            @ PerpetualsError::InvalidReferral
+    )]
+    pub referral_rewards: Option<Account<'info, ReferralRewards>>,
+
+    /// CHECK: secondary oracle account for the position token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    /// CHECK: secondary oracle account for the collateral token, required when configured
+    pub collateral_custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    #[account(
+        seeds = [b"delegation",
+                 owner.key().as_ref(),
+                 authority.key().as_ref()],
+        bump = delegation.bump
+    )]
+    pub delegation: Option<Account<'info, Delegation>>,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
@@ -445,11 +725,90 @@ pub struct ClosePositionParams {
 }
 
 pub fn close_position(ctx: Context<ClosePosition>, params: &ClosePositionParams) -> Result<()> {
+    // check authority
+    Delegation::check_authority(
+        &ctx.accounts.authority.key(),
+        &ctx.accounts.owner.key(),
+        ctx.accounts.delegation.as_deref(),
+        DelegatedAction::ClosePosition,
+        ctx.accounts.perpetuals.get_time()?,
+    )?;
+
+    // custodies in delayed order mode only accept keeper-filled order requests
+    require!(
+        !ctx.accounts.custody.pricing.use_delayed_orders,
+        PerpetualsError::InstructionNotAllowed
+    );
+
+    let (transfer_amount, insurance_fund_amount) = close_position_internal(
+        &ctx.accounts.perpetuals,
+        &mut ctx.accounts.pool,
+        &mut ctx.accounts.position,
+        ctx.accounts.owner.key(),
+        &mut ctx.accounts.custody,
+        &ctx.accounts.custody_oracle_account,
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &mut ctx.accounts.collateral_custody,
+        &ctx.accounts.collateral_custody_oracle_account,
+        ctx.accounts.collateral_custody_secondary_oracle_account.as_ref(),
+        &mut ctx.accounts.user_stats,
+        ctx.bumps.user_stats,
+        ctx.accounts.referral.as_deref(),
+        ctx.accounts.referral_rewards.as_deref_mut(),
+        ctx.accounts.insurance_fund_token_account.amount,
+        params,
+    )?;
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    if insurance_fund_amount > 0 {
+        ctx.accounts.perpetuals.transfer_token(
+            ctx.accounts.insurance_fund_token_account.to_account_info(),
+            ctx.accounts
+                .collateral_custody_token_account
+                .to_account_info(),
+            ctx.accounts.transfer_authority.to_account_info(),
+            insurance_fund_amount,
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+    }
+    ctx.accounts.perpetuals.transfer_token(
+        ctx.accounts
+            .collateral_custody_token_account
+            .to_account_info(),
+        ctx.accounts.receiving_account.to_account_info(),
+        ctx.accounts.transfer_authority.to_account_info(),
+        transfer_amount,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    Ok(())
+}
+
+// Settles a closed position and updates custody and user stats, returns the amount of collateral
+// tokens the caller has to transfer to the owner and the amount it has to move from the insurance
+// fund vault to the custody
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn close_position_internal<'info>(
+    perpetuals: &Perpetuals,
+    pool: &mut Account<'info, Pool>,
+    position: &mut Account<'info, Position>,
+    owner: Pubkey,
+    custody: &mut Account<'info, Custody>,
+    custody_oracle_account: &AccountInfo<'info>,
+    custody_secondary_oracle_account: Option<&AccountInfo<'info>>,
+    collateral_custody: &mut Account<'info, Custody>,
+    collateral_custody_oracle_account: &AccountInfo<'info>,
+    collateral_custody_secondary_oracle_account: Option<&AccountInfo<'info>>,
+    user_stats: &mut Account<'info, UserStats>,
+    user_stats_bump: u8,
+    referral: Option<&Referral>,
+    referral_rewards: Option<&mut ReferralRewards>,
+    insurance_fund_balance: u64,
+    params: &ClosePositionParams,
+) -> Result<(u64, u64)> {
     // check permissions
     msg!("Check permissions");
-    let perpetuals = ctx.accounts.perpetuals.as_mut();
-    let custody = ctx.accounts.custody.as_mut();
-    let collateral_custody = ctx.accounts.collateral_custody.as_mut();
     require!(
         perpetuals.permissions.allow_close_position && custody.permissions.allow_close_position,
         PerpetualsError::InstructionNotAllowed
@@ -460,45 +819,55 @@ pub fn close_position(ctx: Context<ClosePosition>, params: &ClosePositionParams)
     if params.price == 0 {
         return Err(ProgramError::InvalidArgument.into());
     }
-    let position = ctx.accounts.position.as_mut();
-    let pool = ctx.accounts.pool.as_mut();
 
     // compute exit price
     let curtime = perpetuals.get_time()?;
 
     let token_price = OraclePrice::new_from_oracle(
-        &ctx.accounts.custody_oracle_account.to_account_info(),
+        &custody_oracle_account.to_account_info(),
+        custody_secondary_oracle_account,
         &custody.oracle,
         curtime,
         false,
     )?;
 
     let token_ema_price = OraclePrice::new_from_oracle(
-        &ctx.accounts.custody_oracle_account.to_account_info(),
+        &custody_oracle_account.to_account_info(),
+        custody_secondary_oracle_account,
         &custody.oracle,
         curtime,
         custody.pricing.use_ema,
     )?;
 
     let collateral_token_price = OraclePrice::new_from_oracle(
-        &ctx.accounts
-            .collateral_custody_oracle_account
-            .to_account_info(),
+        &collateral_custody_oracle_account.to_account_info(),
+        collateral_custody_secondary_oracle_account,
         &collateral_custody.oracle,
         curtime,
         false,
     )?;
 
     let collateral_token_ema_price = OraclePrice::new_from_oracle(
-        &ctx.accounts
-            .collateral_custody_oracle_account
-            .to_account_info(),
+        &collateral_custody_oracle_account.to_account_info(),
+        collateral_custody_secondary_oracle_account,
         &collateral_custody.oracle,
         curtime,
         collateral_custody.pricing.use_ema,
     )?;
 
-    let exit_price = pool.get_exit_price(&token_price, &token_ema_price, position.side, custody)?;
+    // check price bands, closing is allowed while halted
+    custody.update_circuit_breaker(&token_price, curtime)?;
+    if position.side == Side::Long && !custody.is_virtual {
+        collateral_custody.circuit_breaker_state = custody.circuit_breaker_state;
+    }
+
+    let exit_price = pool.get_exit_price(
+        &token_price,
+        &token_ema_price,
+        position.side,
+        custody,
+        position.size_usd,
+    )?;
     msg!("Exit price: {}", exit_price);
 
     if position.side == Side::Long {
@@ -508,24 +877,58 @@ pub fn close_position(ctx: Context<ClosePosition>, params: &ClosePositionParams)
     }
 
     msg!("Settle position");
-    let (transfer_amount, mut fee_amount, profit_usd, loss_usd) = pool.get_close_amount(
+    let volume_usd = user_stats.get_volume_usd(curtime)?;
+    let size = token_ema_price.get_token_amount(position.size_usd, custody.decimals)?;
+    let exit_fee = pool.get_exit_fee(size, custody, volume_usd)?;
+
+    let (profit_usd, loss_usd, exit_fee) = pool.get_pnl_usd_at_price(
         position,
-        &token_price,
+        exit_price,
+        exit_fee,
         &token_ema_price,
         custody,
         &collateral_token_price,
         &collateral_token_ema_price,
         collateral_custody,
         curtime,
-        false,
     )?;
+    let (mut transfer_amount, mut fee_amount, profit_usd, loss_usd) = pool
+        .get_close_amount_from_pnl(
+            position,
+            profit_usd,
+            loss_usd,
+            exit_fee,
+            &collateral_token_price,
+            &collateral_token_ema_price,
+            collateral_custody,
+        )?;
 
-    let fee_amount_usd = token_ema_price.get_asset_amount_usd(fee_amount, custody.decimals)?;
+    let mut fee_amount_usd = token_ema_price.get_asset_amount_usd(fee_amount, custody.decimals)?;
     if position.side == Side::Short || custody.is_virtual {
         fee_amount = collateral_token_ema_price
             .get_token_amount(fee_amount_usd, collateral_custody.decimals)?;
     }
 
+    // referred traders get a discount, the referrer gets a share of the rest,
+    // nothing is shared if the exit fee could not be covered by the position
+    let mut referral_kickback = 0;
+    if Referral::is_applied(
+        referral,
+        referral_rewards.as_deref(),
+        &collateral_custody.key(),
+    )? && (profit_usd > 0 || loss_usd < position.collateral_usd)
+    {
+        let (rebate, kickback) = Pool::get_referral_amounts(fee_amount, custody)?;
+        fee_amount = math::checked_sub(fee_amount, rebate)?;
+        fee_amount_usd = math::checked_sub(
+            fee_amount_usd,
+            Pool::get_fee_amount(custody.fees.referral_rebate, fee_amount_usd)?,
+        )?;
+        transfer_amount = math::checked_add(transfer_amount, rebate)?;
+        referral_kickback = kickback;
+        msg!("Referral rebate: {}", rebate);
+    }
+
     msg!("Net profit: {}, loss: {}", profit_usd, loss_usd);
     msg!("Collected fee: {}", fee_amount);
     msg!("Amount out: {}", transfer_amount);
@@ -540,17 +943,20 @@ pub fn close_position(ctx: Context<ClosePosition>, params: &ClosePositionParams)
         PerpetualsError::CustodyAmountLimit
     );
 
-    // transfer tokens
-    msg!("Transfer tokens");
-    perpetuals.transfer_token(
-        ctx.accounts
-            .collateral_custody_token_account
-            .to_account_info(),
-        ctx.accounts.receiving_account.to_account_info(),
-        ctx.accounts.transfer_authority.to_account_info(),
-        transfer_amount,
-        ctx.accounts.token_program.to_account_info(),
-    )?;
+    // update user stats
+    if user_stats.owner == Pubkey::default() {
+        user_stats.owner = owner;
+        user_stats.pool = pool.key();
+        user_stats.bump = user_stats_bump;
+    }
+    user_stats.add_volume(position.size_usd, curtime)?;
+    user_stats.record_close(
+        position.size_usd,
+        fee_amount_usd,
+        profit_usd,
+        loss_usd,
+        false,
+    );
 
     // update custody stats
     msg!("Update custody stats");
@@ -573,6 +979,22 @@ pub fn close_position(ctx: Context<ClosePosition>, params: &ClosePositionParams)
         position.collateral_amount,
     )?;
 
+    // cover the shortfall of an underwater position from the insurance fund first
+    let insurance_fund_amount = if loss_usd > position.collateral_usd {
+        let bad_debt_usd = math::checked_sub(loss_usd, position.collateral_usd)?;
+        let bad_debt_amount = collateral_token_ema_price
+            .get_token_amount(bad_debt_usd, collateral_custody.decimals)?;
+        let vault_amount = collateral_custody.cover_bad_debt(
+            bad_debt_amount,
+            bad_debt_usd,
+            insurance_fund_balance,
+        )?;
+        msg!("Bad debt: {}, covered by insurance vault: {}", bad_debt_amount, vault_amount);
+        vault_amount
+    } else {
+        0
+    };
+
     let protocol_fee = Pool::get_fee_amount(custody.fees.protocol_share, fee_amount)?;
 
     // Pay protocol_fee from custody if possible, otherwise no protocol_fee
@@ -584,6 +1006,42 @@ pub fn close_position(ctx: Context<ClosePosition>, params: &ClosePositionParams)
             math::checked_sub(collateral_custody.assets.owned, protocol_fee)?;
     }
 
+    let insurance_fee = Pool::get_fee_amount(custody.fees.insurance_share, fee_amount)?;
+    if pool.check_available_amount(insurance_fee, collateral_custody)? {
+        collateral_custody.assets.insurance_fund =
+            math::checked_add(collateral_custody.assets.insurance_fund, insurance_fee)?;
+
+        collateral_custody.assets.owned =
+            math::checked_sub(collateral_custody.assets.owned, insurance_fee)?;
+    }
+
+    // staking fees are only reserved in the LP staking reward custody
+    let staking_fee = Pool::get_fee_amount(custody.fees.staking_share, fee_amount)?;
+    if collateral_custody.is_lp_staking_reward && pool.check_available_amount(staking_fee, collateral_custody)? {
+        collateral_custody.assets.staking_rewards =
+            math::checked_add(collateral_custody.assets.staking_rewards, staking_fee)?;
+
+        collateral_custody.assets.owned =
+            math::checked_sub(collateral_custody.assets.owned, staking_fee)?;
+    }
+
+    if let Some(referral_rewards) = referral_rewards {
+        if pool.check_available_amount(referral_kickback, collateral_custody)? {
+            collateral_custody.assets.referral_rewards =
+                math::checked_add(collateral_custody.assets.referral_rewards, referral_kickback)?;
+            collateral_custody.assets.owned =
+                math::checked_sub(collateral_custody.assets.owned, referral_kickback)?;
+
+            referral_rewards.claimable_amount =
+                math::checked_add(referral_rewards.claimable_amount, referral_kickback)?;
+            referral_rewards.total_earned_amount = referral_rewards
+                .total_earned_amount
+                .wrapping_add(referral_kickback);
+        }
+    }
+
+    collateral_custody.clear_adl_candidate(position.key());
+
     // if custody and collateral_custody accounts are the same, ensure that data is in sync
     if position.side == Side::Long && !custody.is_virtual {
         collateral_custody.volume_stats.close_position_usd = collateral_custody
@@ -640,5 +1098,482 @@ pub fn close_position(ctx: Context<ClosePosition>, params: &ClosePositionParams)
         collateral_custody.update_borrow_rate(curtime)?;
     }
 
-    Ok(())
-}
\ No newline at end of file
+    emit!(ClosePositionEvent {
+        owner,
+        pool: pool.key(),
+        custody: custody.key(),
+        collateral_custody: collateral_custody.key(),
+        position: position.key(),
+        side: position.side,
+        entry_price: position.price,
+        exit_price,
+        size_usd: position.size_usd,
+        collateral_usd: position.collateral_usd,
+        profit_usd,
+        loss_usd,
+        fee_amount,
+        fee_usd: fee_amount_usd,
+        amount_out: transfer_amount,
+        time: curtime,
+    });
+
+    Ok((transfer_amount, insurance_fund_amount))
+}
+#[derive(Accounts)]
+#[instruction(params: LiquidateParams)]
+pub struct Liquidate<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = receiving_account.mint == collateral_custody.mint,
+        constraint = receiving_account.owner == position.owner
+    )]
+    pub receiving_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = rewards_receiving_account.mint == collateral_custody.mint
+    )]
+    pub rewards_receiving_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        mut,
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"position",
+                 position.owner.as_ref(),
+                 pool.key().as_ref(),
+                 custody.key().as_ref(),
+                 &[position.side as u8],
+                 Position::get_index_seed(position.index).as_slice()],
+        bump = position.bump,
+        close = signer
+    )]
+    pub position: Box<Account<'info, Position>>,
+
+    #[account(
+        mut,
+        constraint = position.custody == custody.key()
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the position token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = position.collateral_custody == collateral_custody.key()
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the collateral token
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 collateral_custody.mint.as_ref()],
+        bump = collateral_custody.token_account_bump
+    )]
+    pub collateral_custody_token_account: Box<Account<'info, TokenAccount>>,
+
+    // underwater positions draw on the insurance fund first, see add_insurance_fund
+    #[account(
+        mut,
+        seeds = [b"insurance_fund_token_account",
+                 pool.key().as_ref(),
+                 collateral_custody.mint.as_ref()],
+        bump
+    )]
+    pub insurance_fund_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = UserStats::LEN,
+        seeds = [b"user_stats",
+                 position.owner.as_ref(),
+                 pool.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Box<Account<'info, UserStats>>,
+
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+
+    /// CHECK: secondary oracle account for the position token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    /// CHECK: secondary oracle account for the collateral token, required when configured
+    pub collateral_custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+    // records TWAP samples when provided
+    #[account(
+        mut,
+        seeds = [b"oracle_history",
+                 custody.key().as_ref()],
+        bump = custody_oracle_history.bump
+    )]
+    pub custody_oracle_history: Option<Box<Account<'info, OracleHistory>>>,
+
+    #[account(
+        mut,
+        seeds = [b"oracle_history",
+                 collateral_custody.key().as_ref()],
+        bump = collateral_custody_oracle_history.bump
+    )]
+    pub collateral_custody_oracle_history: Option<Box<Account<'info, OracleHistory>>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct LiquidateParams {}
+
+pub fn liquidate(ctx: Context<Liquidate>, _params: &LiquidateParams) -> Result<()> {
+    // check permissions
+    msg!("Check permissions");
+    let perpetuals = ctx.accounts.perpetuals.as_mut();
+    let custody = ctx.accounts.custody.as_mut();
+    let collateral_custody = ctx.accounts.collateral_custody.as_mut();
+    require!(
+        perpetuals.permissions.allow_close_position && custody.permissions.allow_close_position,
+        PerpetualsError::InstructionNotAllowed
+    );
+
+    let position = ctx.accounts.position.as_mut();
+    let pool = ctx.accounts.pool.as_mut();
+    let insurance_fund_balance = ctx.accounts.insurance_fund_token_account.amount;
+
+    // compute exit price
+    let curtime = perpetuals.get_time()?;
+
+    let token_price = OraclePrice::new_from_oracle(
+        &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &custody.oracle,
+        curtime,
+        false,
+    )?;
+
+    let token_ema_price = OraclePrice::new_from_oracle(
+        &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &custody.oracle,
+        curtime,
+        custody.pricing.use_ema,
+    )?;
+
+    let collateral_token_price = OraclePrice::new_from_oracle(
+        &ctx.accounts
+            .collateral_custody_oracle_account
+            .to_account_info(),
+        ctx.accounts.collateral_custody_secondary_oracle_account.as_ref(),
+        &collateral_custody.oracle,
+        curtime,
+        false,
+    )?;
+
+    let collateral_token_ema_price = OraclePrice::new_from_oracle(
+        &ctx.accounts
+            .collateral_custody_oracle_account
+            .to_account_info(),
+        ctx.accounts.collateral_custody_secondary_oracle_account.as_ref(),
+        &collateral_custody.oracle,
+        curtime,
+        collateral_custody.pricing.use_ema,
+    )?;
+
+    // record samples so the TWAP doesn't depend on the update_oracle_history crank
+    if let Some(oracle_history) = ctx.accounts.custody_oracle_history.as_deref_mut() {
+        oracle_history.update_custody_twap(custody, &token_price, curtime)?;
+    }
+    if position.side == Side::Long && !custody.is_virtual {
+        collateral_custody.twap = custody.twap;
+    } else if let Some(oracle_history) = ctx
+        .accounts
+        .collateral_custody_oracle_history
+        .as_deref_mut()
+    {
+        oracle_history.update_custody_twap(collateral_custody, &collateral_token_price, curtime)?;
+    }
+
+    // custodies with a program-maintained TWAP use it in place of the EMA for liquidations
+    let token_ema_price = custody.get_reference_price(&token_ema_price, curtime)?;
+    let collateral_token_ema_price =
+        collateral_custody.get_reference_price(&collateral_token_ema_price, curtime)?;
+
+    // check price bands, liquidations are allowed while halted
+    custody.update_circuit_breaker(&token_price, curtime)?;
+    if position.side == Side::Long && !custody.is_virtual {
+        collateral_custody.circuit_breaker_state = custody.circuit_breaker_state;
+    }
+
+    // check if position can be liquidated
+    msg!("Check position state");
+    require!(
+        !pool.check_leverage(
+            position,
+            &token_price,
+            &token_ema_price,
+            custody,
+            &collateral_token_price,
+            &collateral_token_ema_price,
+            collateral_custody,
+            curtime,
+            false
+        )?,
+        PerpetualsError::InvalidPositionState
+    );
+
+    msg!("Settle position");
+    let (total_amount_out, mut fee_amount, profit_usd, loss_usd) = pool.get_close_amount(
+        position,
+        &token_price,
+        &token_ema_price,
+        custody,
+        &collateral_token_price,
+        &collateral_token_ema_price,
+        collateral_custody,
+        curtime,
+        true,
+    )?;
+
+    let fee_amount_usd = token_ema_price.get_asset_amount_usd(fee_amount, custody.decimals)?;
+    if position.side == Side::Short || custody.is_virtual {
+        fee_amount = collateral_token_ema_price
+            .get_token_amount(fee_amount_usd, collateral_custody.decimals)?;
+    }
+
+    msg!("Net profit: {}, loss: {}", profit_usd, loss_usd);
+    msg!("Collected fee: {}", fee_amount);
+
+    let reward = Pool::get_fee_amount(custody.fees.liquidation, total_amount_out)?;
+    let user_amount = math::checked_sub(total_amount_out, reward)?;
+
+    msg!("Amount out: {}", user_amount);
+    msg!("Reward: {}", reward);
+
+    // unlock pool funds
+    collateral_custody.unlock_funds(position.locked_amount)?;
+
+    // check pool constraints
+    msg!("Check pool constraints");
+    require!(
+        pool.check_available_amount(total_amount_out, collateral_custody)?,
+        PerpetualsError::CustodyAmountLimit
+    );
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    perpetuals.transfer_token(
+        ctx.accounts
+            .collateral_custody_token_account
+            .to_account_info(),
+        ctx.accounts.receiving_account.to_account_info(),
+        ctx.accounts.transfer_authority.to_account_info(),
+        user_amount,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    perpetuals.transfer_token(
+        ctx.accounts
+            .collateral_custody_token_account
+            .to_account_info(),
+        ctx.accounts.rewards_receiving_account.to_account_info(),
+        ctx.accounts.transfer_authority.to_account_info(),
+        reward,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    // update user stats
+    let user_stats = ctx.accounts.user_stats.as_mut();
+    if user_stats.owner == Pubkey::default() {
+        user_stats.owner = position.owner;
+        user_stats.pool = pool.key();
+        user_stats.bump = ctx.bumps.user_stats;
+    }
+    user_stats.record_close(
+        position.size_usd,
+        fee_amount_usd,
+        profit_usd,
+        loss_usd,
+        true,
+    );
+
+    emit!(LiquidateEvent {
+        owner: position.owner,
+        liquidator: ctx.accounts.signer.key(),
+        pool: pool.key(),
+        custody: custody.key(),
+        collateral_custody: collateral_custody.key(),
+        position: position.key(),
+        side: position.side,
+        entry_price: position.price,
+        size_usd: position.size_usd,
+        collateral_usd: position.collateral_usd,
+        profit_usd,
+        loss_usd,
+        fee_amount,
+        fee_usd: fee_amount_usd,
+        amount_out: user_amount,
+        reward,
+        time: curtime,
+    });
+
+    // update custody stats
+    msg!("Update custody stats");
+    collateral_custody.collected_fees.liquidation_usd = collateral_custody
+        .collected_fees
+        .liquidation_usd
+        .wrapping_add(fee_amount_usd);
+
+    if total_amount_out > position.collateral_amount {
+        let amount_lost = total_amount_out.saturating_sub(position.collateral_amount);
+        collateral_custody.assets.owned =
+            math::checked_sub(collateral_custody.assets.owned, amount_lost)?;
+    } else {
+        let amount_gained = position.collateral_amount.saturating_sub(total_amount_out);
+        collateral_custody.assets.owned =
+            math::checked_add(collateral_custody.assets.owned, amount_gained)?;
+    }
+    collateral_custody.assets.collateral = math::checked_sub(
+        collateral_custody.assets.collateral,
+        position.collateral_amount,
+    )?;
+
+    // cover the shortfall of an underwater position from the insurance fund first
+    let insurance_fund_amount = if loss_usd > position.collateral_usd {
+        let bad_debt_usd = math::checked_sub(loss_usd, position.collateral_usd)?;
+        let bad_debt_amount = collateral_token_ema_price
+            .get_token_amount(bad_debt_usd, collateral_custody.decimals)?;
+        let vault_amount = collateral_custody.cover_bad_debt(
+            bad_debt_amount,
+            bad_debt_usd,
+            insurance_fund_balance,
+        )?;
+        msg!("Bad debt: {}, covered by insurance vault: {}", bad_debt_amount, vault_amount);
+        vault_amount
+    } else {
+        0
+    };
+    if insurance_fund_amount > 0 {
+        perpetuals.transfer_token(
+            ctx.accounts.insurance_fund_token_account.to_account_info(),
+            ctx.accounts
+                .collateral_custody_token_account
+                .to_account_info(),
+            ctx.accounts.transfer_authority.to_account_info(),
+            insurance_fund_amount,
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+    }
+
+    let protocol_fee = Pool::get_fee_amount(custody.fees.protocol_share, fee_amount)?;
+
+    // Pay protocol_fee from custody if possible, otherwise no protocol_fee
+    if pool.check_available_amount(protocol_fee, collateral_custody)? {
+        collateral_custody.assets.protocol_fees =
+            math::checked_add(collateral_custody.assets.protocol_fees, protocol_fee)?;
+
+        collateral_custody.assets.owned =
+            math::checked_sub(collateral_custody.assets.owned, protocol_fee)?;
+    }
+
+    let insurance_fee = Pool::get_fee_amount(custody.fees.insurance_share, fee_amount)?;
+    if pool.check_available_amount(insurance_fee, collateral_custody)? {
+        collateral_custody.assets.insurance_fund =
+            math::checked_add(collateral_custody.assets.insurance_fund, insurance_fee)?;
+
+        collateral_custody.assets.owned =
+            math::checked_sub(collateral_custody.assets.owned, insurance_fee)?;
+    }
+
+    // staking fees are only reserved in the LP staking reward custody
+    let staking_fee = Pool::get_fee_amount(custody.fees.staking_share, fee_amount)?;
+    if collateral_custody.is_lp_staking_reward && pool.check_available_amount(staking_fee, collateral_custody)? {
+        collateral_custody.assets.staking_rewards =
+            math::checked_add(collateral_custody.assets.staking_rewards, staking_fee)?;
+
+        collateral_custody.assets.owned =
+            math::checked_sub(collateral_custody.assets.owned, staking_fee)?;
+    }
+
+    collateral_custody.clear_adl_candidate(position.key());
+
+    // if custody and collateral_custody accounts are the same, ensure that data is in sync
+    if position.side == Side::Long && !custody.is_virtual {
+        collateral_custody.volume_stats.liquidation_usd = collateral_custody
+            .volume_stats
+            .liquidation_usd
+            .wrapping_add(position.size_usd);
+
+        collateral_custody.trade_stats.oi_long_usd = collateral_custody
+            .trade_stats
+            .oi_long_usd
+            .saturating_sub(position.size_usd);
+
+        collateral_custody.trade_stats.profit_usd = collateral_custody
+            .trade_stats
+            .profit_usd
+            .wrapping_add(profit_usd);
+        collateral_custody.trade_stats.loss_usd = collateral_custody
+            .trade_stats
+            .loss_usd
+            .wrapping_add(loss_usd);
+
+        collateral_custody.remove_position(position, curtime, None)?;
+        collateral_custody.update_borrow_rate(curtime)?;
+        *custody = collateral_custody.clone();
+    } else {
+        custody.volume_stats.liquidation_usd = custody
+            .volume_stats
+            .liquidation_usd
+            .wrapping_add(position.size_usd);
+
+        if position.side == Side::Long {
+            custody.trade_stats.oi_long_usd = custody
+                .trade_stats
+                .oi_long_usd
+                .saturating_sub(position.size_usd);
+        } else {
+            custody.trade_stats.oi_short_usd = custody
+                .trade_stats
+                .oi_short_usd
+                .saturating_sub(position.size_usd);
+        }
+
+        custody.trade_stats.profit_usd = custody.trade_stats.profit_usd.wrapping_add(profit_usd);
+        custody.trade_stats.loss_usd = custody.trade_stats.loss_usd.wrapping_add(loss_usd);
+
+        custody.remove_position(position, curtime, Some(collateral_custody))?;
+        collateral_custody.update_borrow_rate(curtime)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,325 @@
+//! SetReferrer, CreateReferral, InitReferralRewards and ClaimReferralRewards instruction handlers
+
+use {
+    crate::{
+        error::PerpetualsError,
+        events::{
+            ClaimReferralRewardsEvent, CreateReferralEvent, InitReferralRewardsEvent,
+            SetReferrerEvent,
+        },
+        math,
+        state::{
+            custody::Custody,
+            multisig::{AdminInstruction, Multisig},
+            perps::Perpetuals,
+            pool::Pool,
+            proposal::Proposal,
+            referral::{Referral, ReferralRewards, Referrer},
+        },
+    },
+    anchor_lang::prelude::*,
+    anchor_spl::token::{Token, TokenAccount},
+};
+
+/**
+ * set referrer: approves or revokes a referrer, only approved referrers can be linked to traders
+ */
+#[derive(Accounts)]
+#[instruction(params: SetReferrerParams)]
+pub struct SetReferrer<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, Multisig>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = Referrer::LEN,
+        seeds = [b"referrer",
+                 params.referrer.as_ref()],
+        bump
+    )]
+    pub referrer: Box<Account<'info, Referrer>>,
+
+    system_program: Program<'info, System>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal",
+                 proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Option<Account<'info, Proposal>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct SetReferrerParams {
+    pub referrer: Pubkey,
+    pub is_approved: bool,
+}
+
+pub fn set_referrer<'info>(
+    ctx: Context<'_, '_, '_, 'info, SetReferrer<'info>>,
+    params: &SetReferrerParams,
+) -> Result<u8> {
+    // validate inputs
+    if params.referrer == Pubkey::default() {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+
+    let mut multisig = ctx.accounts.multisig.load_mut()?;
+
+    let signatures_left = multisig.sign_multisig(
+        &ctx.accounts.admin,
+        &Multisig::get_account_infos(&ctx)[1..],
+        &Multisig::get_instruction_data(AdminInstruction::SetReferrer, params)?,
+        ctx.accounts.proposal.as_deref_mut(),
+    )?;
+    if signatures_left > 0 {
+        msg!(
+            "Instruction has been signed but more signatures are required: {}",
+            signatures_left
+        );
+        return Ok(signatures_left);
+    }
+
+    msg!(
+        "Set referrer: {}, approved: {}",
+        params.referrer,
+        params.is_approved
+    );
+    let referrer = ctx.accounts.referrer.as_mut();
+    referrer.owner = params.referrer;
+    referrer.is_approved = params.is_approved;
+    referrer.bump = ctx.bumps.referrer;
+
+    emit!(SetReferrerEvent {
+        referrer: referrer.owner,
+        is_approved: referrer.is_approved,
+        time: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(0)
+}
+
+/**
+ * create referral: links the signing trader to an approved referrer, can only be done once
+ */
+#[derive(Accounts)]
+#[instruction(params: CreateReferralParams)]
+pub struct CreateReferral<'info> {
+    #[account(mut)]
+    pub trader: Signer<'info>,
+
+    #[account(
+        seeds = [b"referrer",
+                 params.referrer.as_ref()],
+        bump = referrer.bump,
+        constraint = referrer.is_approved @ PerpetualsError::InvalidReferral
+    )]
+    pub referrer: Box<Account<'info, Referrer>>,
+
+    #[account(
+        init,
+        payer = trader,
+        space = Referral::LEN,
+        seeds = [b"referral",
+                 trader.key().as_ref()],
+        bump
+    )]
+    pub referral: Box<Account<'info, Referral>>,
+
+    system_program: Program<'info, System>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct CreateReferralParams {
+    pub referrer: Pubkey,
+}
+
+pub fn create_referral(ctx: Context<CreateReferral>, params: &CreateReferralParams) -> Result<()> {
+    // validate inputs
+    let trader = ctx.accounts.trader.key();
+    if params.referrer == Pubkey::default() || params.referrer == trader {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+
+    let referral = ctx.accounts.referral.as_mut();
+    referral.trader = trader;
+    referral.referrer = params.referrer;
+    referral.bump = ctx.bumps.referral;
+
+    emit!(CreateReferralEvent {
+        trader,
+        referrer: referral.referrer,
+        time: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+/**
+ * init referral rewards: permissionless, creates the referrer balance for a collateral custody
+ */
+#[derive(Accounts)]
+pub struct InitReferralRewards<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        seeds = [b"referrer",
+                 referrer.owner.as_ref()],
+        bump = referrer.bump,
+        constraint = referrer.is_approved @ PerpetualsError::InvalidReferral
+    )]
+    pub referrer: Box<Account<'info, Referrer>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = ReferralRewards::LEN,
+        seeds = [b"referral_rewards",
+                 referrer.owner.as_ref(),
+                 custody.key().as_ref()],
+        bump
+    )]
+    pub referral_rewards: Box<Account<'info, ReferralRewards>>,
+
+    system_program: Program<'info, System>,
+}
+
+pub fn init_referral_rewards(ctx: Context<InitReferralRewards>) -> Result<()> {
+    let referral_rewards = ctx.accounts.referral_rewards.as_mut();
+    referral_rewards.referrer = ctx.accounts.referrer.owner;
+    referral_rewards.custody = ctx.accounts.custody.key();
+    referral_rewards.claimable_amount = 0;
+    referral_rewards.total_earned_amount = 0;
+    referral_rewards.bump = ctx.bumps.referral_rewards;
+
+    emit!(InitReferralRewardsEvent {
+        referrer: referral_rewards.referrer,
+        pool: ctx.accounts.pool.key(),
+        custody: referral_rewards.custody,
+        time: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+/**
+ * claim referral rewards
+ */
+#[derive(Accounts)]
+pub struct ClaimReferralRewards<'info> {
+    pub referrer: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = receiving_account.mint == custody.mint,
+        constraint = receiving_account.owner == referrer.key()
+    )]
+    pub receiving_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: empty PDA, authority for token accounts
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.token_account_bump
+    )]
+    pub custody_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        has_one = referrer,
+        seeds = [b"referral_rewards",
+                 referrer.key().as_ref(),
+                 custody.key().as_ref()],
+        bump = referral_rewards.bump
+    )]
+    pub referral_rewards: Box<Account<'info, ReferralRewards>>,
+
+    token_program: Program<'info, Token>,
+}
+
+pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> Result<()> {
+    let referral_rewards = ctx.accounts.referral_rewards.as_mut();
+    let custody = ctx.accounts.custody.as_mut();
+
+    let amount = referral_rewards.claimable_amount;
+    require!(amount > 0, PerpetualsError::InsufficientAmountReturned);
+    msg!("Amount out: {}", amount);
+
+    // transfer tokens
+    msg!("Transfer tokens");
+    ctx.accounts.perpetuals.transfer_token(
+        ctx.accounts.custody_token_account.to_account_info(),
+        ctx.accounts.receiving_account.to_account_info(),
+        ctx.accounts.transfer_authority.to_account_info(),
+        amount,
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    referral_rewards.claimable_amount = 0;
+    custody.assets.referral_rewards = math::checked_sub(custody.assets.referral_rewards, amount)?;
+
+    emit!(ClaimReferralRewardsEvent {
+        referrer: referral_rewards.referrer,
+        pool: ctx.accounts.pool.key(),
+        custody: referral_rewards.custody,
+        amount,
+        time: ctx.accounts.perpetuals.get_time()?,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,269 @@
+//! TransferPosition instruction handler
+
+use {
+    crate::{
+        error::PerpetualsError,
+        events::TransferPositionEvent,
+        state::{
+            custody::Custody,
+            oracle::OraclePrice,
+            oracle_history::OracleHistory,
+            perps::Perpetuals,
+            pool::Pool,
+            position::{Position, Side},
+            position_counter::PositionCounter,
+        },
+    },
+    anchor_lang::prelude::*,
+};
+
+/**
+ * transfer position: moves a position to the address of a new owner without settling it
+ */
+#[derive(Accounts)]
+#[instruction(params: TransferPositionParams)]
+pub struct TransferPosition<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    /// CHECK: wallet receiving the position
+    pub new_owner: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [b"position",
+                 owner.key().as_ref(),
+                 pool.key().as_ref(),
+                 custody.key().as_ref(),
+                 &[position.side as u8],
+                 Position::get_index_seed(position.index).as_slice()],
+        bump = position.bump,
+        close = owner
+    )]
+    pub position: Box<Account<'info, Position>>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = Position::LEN,
+        seeds = [b"position",
+                 new_owner.key().as_ref(),
+                 pool.key().as_ref(),
+                 custody.key().as_ref(),
+                 &[position.side as u8],
+                 Position::get_index_seed(params.index).as_slice()],
+        bump
+    )]
+    pub new_position: Box<Account<'info, Position>>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = PositionCounter::LEN,
+        seeds = [b"position_counter",
+                 new_owner.key().as_ref()],
+        bump
+    )]
+    pub position_counter: Box<Account<'info, PositionCounter>>,
+
+    #[account(
+        mut,
+        constraint = position.custody == custody.key()
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the position token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = position.collateral_custody == collateral_custody.key()
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the collateral token
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+
+    system_program: Program<'info, System>,
+
+    /// CHECK: secondary oracle account for the position token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    /// CHECK: secondary oracle account for the collateral token, required when configured
+    pub collateral_custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+    // records TWAP samples when provided
+    #[account(
+        mut,
+        seeds = [b"oracle_history",
+                 custody.key().as_ref()],
+        bump = custody_oracle_history.bump
+    )]
+    pub custody_oracle_history: Option<Box<Account<'info, OracleHistory>>>,
+
+    #[account(
+        mut,
+        seeds = [b"oracle_history",
+                 collateral_custody.key().as_ref()],
+        bump = collateral_custody_oracle_history.bump
+    )]
+    pub collateral_custody_oracle_history: Option<Box<Account<'info, OracleHistory>>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct TransferPositionParams {
+    // index of the position at the new owner, see PositionCounter
+    pub index: u32,
+}
+
+pub fn transfer_position(
+    ctx: Context<TransferPosition>,
+    params: &TransferPositionParams,
+) -> Result<()> {
+    // check permissions
+    msg!("Check permissions");
+    let perpetuals = ctx.accounts.perpetuals.as_ref();
+    let custody = ctx.accounts.custody.as_mut();
+    let collateral_custody = ctx.accounts.collateral_custody.as_mut();
+    let position = ctx.accounts.position.as_ref();
+    require!(
+        perpetuals.permissions.allow_transfer_position
+            && custody.permissions.allow_transfer_position,
+        PerpetualsError::InstructionNotAllowed
+    );
+
+    // validate inputs
+    msg!("Validate inputs");
+    require_keys_neq!(ctx.accounts.new_owner.key(), ctx.accounts.owner.key());
+
+    // compute position state
+    let curtime = perpetuals.get_time()?;
+
+    let token_price = OraclePrice::new_from_oracle(
+        &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &custody.oracle,
+        curtime,
+        false,
+    )?;
+
+    let token_ema_price = OraclePrice::new_from_oracle(
+        &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &custody.oracle,
+        curtime,
+        custody.pricing.use_ema,
+    )?;
+
+    let collateral_token_price = OraclePrice::new_from_oracle(
+        &ctx.accounts
+            .collateral_custody_oracle_account
+            .to_account_info(),
+        ctx.accounts
+            .collateral_custody_secondary_oracle_account
+            .as_ref(),
+        &collateral_custody.oracle,
+        curtime,
+        false,
+    )?;
+
+    let collateral_token_ema_price = OraclePrice::new_from_oracle(
+        &ctx.accounts
+            .collateral_custody_oracle_account
+            .to_account_info(),
+        ctx.accounts
+            .collateral_custody_secondary_oracle_account
+            .as_ref(),
+        &collateral_custody.oracle,
+        curtime,
+        collateral_custody.pricing.use_ema,
+    )?;
+
+    // record samples so the TWAP doesn't depend on the update_oracle_history crank
+    if let Some(oracle_history) = ctx.accounts.custody_oracle_history.as_deref_mut() {
+        oracle_history.update_custody_twap(custody, &token_price, curtime)?;
+    }
+    if position.side == Side::Long && !custody.is_virtual {
+        collateral_custody.twap = custody.twap;
+    } else if let Some(oracle_history) = ctx
+        .accounts
+        .collateral_custody_oracle_history
+        .as_deref_mut()
+    {
+        oracle_history.update_custody_twap(collateral_custody, &collateral_token_price, curtime)?;
+    }
+
+    // use the same reference prices as liquidations
+    let token_ema_price = custody.get_reference_price(&token_ema_price, curtime)?;
+    let collateral_token_ema_price =
+        collateral_custody.get_reference_price(&collateral_token_ema_price, curtime)?;
+
+    // positions that can be liquidated can't be transferred
+    msg!("Check position state");
+    require!(
+        ctx.accounts.pool.check_leverage(
+            &ctx.accounts.position,
+            &token_price,
+            &token_ema_price,
+            custody,
+            &collateral_token_price,
+            &collateral_token_ema_price,
+            collateral_custody,
+            curtime,
+            false
+        )?,
+        PerpetualsError::MaxLeverage
+    );
+
+    // assign the recipient index
+    let new_owner = ctx.accounts.new_owner.key();
+    ctx.accounts
+        .position_counter
+        .use_index(new_owner, params.index, ctx.bumps.position_counter)?;
+
+    // copy position to the new address, the old account is closed to the owner
+    msg!("Transfer position");
+    let new_position = ctx.accounts.new_position.as_mut();
+    new_position.set_inner(Position::clone(&ctx.accounts.position));
+    new_position.owner = new_owner;
+    new_position.update_time = curtime;
+    new_position.index = params.index;
+    new_position.bump = ctx.bumps.new_position;
+
+    // the old address no longer holds the position
+    collateral_custody.clear_adl_candidate(ctx.accounts.position.key());
+
+    emit!(TransferPositionEvent {
+        owner: ctx.accounts.owner.key(),
+        new_owner,
+        pool: ctx.accounts.pool.key(),
+        custody: custody.key(),
+        position: ctx.accounts.position.key(),
+        new_position: new_position.key(),
+        side: new_position.side,
+        size_usd: new_position.size_usd,
+        collateral_usd: new_position.collateral_usd,
+        time: curtime,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,345 @@
+//! Read-only quote instructions, results are returned via return data
+
+use {
+    crate::{
+        state::{
+            custody::Custody,
+            oracle::OraclePrice,
+            perps::{NewPositionPricesAndFee, Perpetuals, PriceAndFee},
+            pool::Pool,
+            position::{Position, Side},
+            user_stats::UserStats,
+        },
+    },
+    anchor_lang::prelude::*,
+};
+
+/**
+ * get entry price and fee
+ */
+#[derive(Accounts)]
+pub struct GetEntryPriceAndFee<'info> {
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the position token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 collateral_custody.mint.as_ref()],
+        bump = collateral_custody.bump
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the collateral token
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+
+    // trader stats for the fee tier discount
+    #[account(
+        constraint = user_stats.pool == pool.key()
+    )]
+    pub user_stats: Option<Account<'info, UserStats>>,
+
+    /// CHECK: secondary oracle account for the position token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    /// CHECK: secondary oracle account for the collateral token, required when configured
+    pub collateral_custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct GetEntryPriceAndFeeParams {
+    pub collateral: u64,
+    pub size: u64,
+    pub side: Side,
+}
+
+pub fn get_entry_price_and_fee(
+    ctx: Context<GetEntryPriceAndFee>,
+    params: &GetEntryPriceAndFeeParams,
+) -> Result<NewPositionPricesAndFee> {
+    // validate inputs
+    if params.collateral == 0 || params.size == 0 || params.side == Side::None {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+    let pool = &ctx.accounts.pool;
+    let custody = &ctx.accounts.custody;
+    let collateral_custody = &ctx.accounts.collateral_custody;
+    let use_collateral_custody = params.side == Side::Short || custody.is_virtual;
+
+    // compute position price
+    let curtime = ctx.accounts.perpetuals.get_time()?;
+
+    let token_price = OraclePrice::new_from_oracle(
+        &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &custody.oracle,
+        curtime,
+        false,
+    )?;
+
+    let token_ema_price = OraclePrice::new_from_oracle(
+        &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &custody.oracle,
+        curtime,
+        custody.pricing.use_ema,
+    )?;
+
+    let collateral_token_price = OraclePrice::new_from_oracle(
+        &ctx.accounts
+            .collateral_custody_oracle_account
+            .to_account_info(),
+        ctx.accounts.collateral_custody_secondary_oracle_account.as_ref(),
+        &collateral_custody.oracle,
+        curtime,
+        false,
+    )?;
+
+    let collateral_token_ema_price = OraclePrice::new_from_oracle(
+        &ctx.accounts
+            .collateral_custody_oracle_account
+            .to_account_info(),
+        ctx.accounts.collateral_custody_secondary_oracle_account.as_ref(),
+        &collateral_custody.oracle,
+        curtime,
+        collateral_custody.pricing.use_ema,
+    )?;
+
+    let min_collateral_price = collateral_token_price
+        .get_min_price(&collateral_token_ema_price, collateral_custody.is_stable)?;
+
+    let trade_size_usd = token_price.get_asset_amount_usd(params.size, custody.decimals)?;
+    let price_impact = pool.get_price_impact(params.side, trade_size_usd, true, custody)?;
+    let entry_price = pool.get_entry_price(
+        &token_price,
+        &token_ema_price,
+        params.side,
+        custody,
+        trade_size_usd,
+    )?;
+
+    // compute position parameters
+    let position_oracle_price = OraclePrice {
+        price: entry_price,
+        exponent: -(Perpetuals::PRICE_DECIMALS as i32),
+        conf: 0,
+    };
+    let size_usd = position_oracle_price.get_asset_amount_usd(params.size, custody.decimals)?;
+    let collateral_usd = min_collateral_price
+        .get_asset_amount_usd(params.collateral, collateral_custody.decimals)?;
+
+    let locked_amount = if use_collateral_custody {
+        custody.get_locked_amount(
+            min_collateral_price.get_token_amount(size_usd, collateral_custody.decimals)?,
+            params.side,
+        )?
+    } else {
+        custody.get_locked_amount(params.size, params.side)?
+    };
+
+    let position = Position {
+        side: params.side,
+        price: entry_price,
+        size_usd,
+        collateral_usd,
+        cumulative_interest_snapshot: collateral_custody.get_cumulative_interest(curtime)?,
+        cumulative_dividend_snapshot: custody.get_cumulative_dividend(curtime)?,
+        ..Position::default()
+    };
+
+    let liquidation_price = pool.get_liquidation_price(
+        &position,
+        &token_ema_price,
+        custody,
+        collateral_custody,
+        curtime,
+    )?;
+
+    // compute fee
+    let volume_usd = match &ctx.accounts.user_stats {
+        Some(user_stats) => user_stats.get_volume_usd(curtime)?,
+        None => 0,
+    };
+    let mut fee = pool.get_entry_fee(
+        custody.fees.open_position,
+        params.size,
+        locked_amount,
+        collateral_custody,
+        volume_usd,
+    )?;
+    if use_collateral_custody {
+        let fee_amount_usd = token_ema_price.get_asset_amount_usd(fee, custody.decimals)?;
+        fee = collateral_token_ema_price
+            .get_token_amount(fee_amount_usd, collateral_custody.decimals)?;
+    }
+
+    Ok(NewPositionPricesAndFee {
+        entry_price,
+        liquidation_price,
+        fee,
+        price_impact,
+    })
+}
+
+/**
+ * get exit price and fee
+ */
+#[derive(Accounts)]
+pub struct GetExitPriceAndFee<'info> {
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"position",
+                 position.owner.as_ref(),
+                 pool.key().as_ref(),
+                 custody.key().as_ref(),
+                 &[position.side as u8],
+                 Position::get_index_seed(position.index).as_slice()],
+        bump = position.bump
+    )]
+    pub position: Box<Account<'info, Position>>,
+
+    #[account(
+        constraint = position.custody == custody.key()
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the position token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        constraint = position.collateral_custody == collateral_custody.key()
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the collateral token
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"user_stats",
+                 position.owner.as_ref(),
+                 pool.key().as_ref()],
+        bump = user_stats.bump
+    )]
+    pub user_stats: Option<Account<'info, UserStats>>,
+
+    /// CHECK: secondary oracle account for the position token, required when configured
+    pub custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+
+    /// CHECK: secondary oracle account for the collateral token, required when configured
+    pub collateral_custody_secondary_oracle_account: Option<AccountInfo<'info>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct GetExitPriceAndFeeParams {}
+
+pub fn get_exit_price_and_fee(
+    ctx: Context<GetExitPriceAndFee>,
+    _params: &GetExitPriceAndFeeParams,
+) -> Result<PriceAndFee> {
+    let pool = &ctx.accounts.pool;
+    let position = &ctx.accounts.position;
+    let custody = &ctx.accounts.custody;
+    let collateral_custody = &ctx.accounts.collateral_custody;
+
+    // compute exit price
+    let curtime = ctx.accounts.perpetuals.get_time()?;
+
+    let token_price = OraclePrice::new_from_oracle(
+        &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &custody.oracle,
+        curtime,
+        false,
+    )?;
+
+    let token_ema_price = OraclePrice::new_from_oracle(
+        &ctx.accounts.custody_oracle_account.to_account_info(),
+        ctx.accounts.custody_secondary_oracle_account.as_ref(),
+        &custody.oracle,
+        curtime,
+        custody.pricing.use_ema,
+    )?;
+
+    let collateral_token_ema_price = OraclePrice::new_from_oracle(
+        &ctx.accounts
+            .collateral_custody_oracle_account
+            .to_account_info(),
+        ctx.accounts.collateral_custody_secondary_oracle_account.as_ref(),
+        &collateral_custody.oracle,
+        curtime,
+        collateral_custody.pricing.use_ema,
+    )?;
+
+    let price_impact = pool.get_price_impact(position.side, position.size_usd, false, custody)?;
+    let price = pool.get_exit_price(
+        &token_price,
+        &token_ema_price,
+        position.side,
+        custody,
+        position.size_usd,
+    )?;
+
+    // compute fee
+    let size = token_ema_price.get_token_amount(position.size_usd, custody.decimals)?;
+    let volume_usd = match &ctx.accounts.user_stats {
+        Some(user_stats) => user_stats.get_volume_usd(curtime)?,
+        None => 0,
+    };
+    let mut fee = pool.get_exit_fee(size, custody, volume_usd)?;
+    if position.side == Side::Short || custody.is_virtual {
+        let fee_amount_usd = token_ema_price.get_asset_amount_usd(fee, custody.decimals)?;
+        fee = collateral_token_ema_price
+            .get_token_amount(fee_amount_usd, collateral_custody.decimals)?;
+    }
+
+    Ok(PriceAndFee {
+        price,
+        fee,
+        price_impact,
+    })
+}
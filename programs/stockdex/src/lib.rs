@@ -1,5 +1,6 @@
 use anchor_lang::prelude::*;
 mod error;
+mod events;
 mod math;
 mod state;
 mod instructions;
@@ -7,10 +8,23 @@ mod instructions;
 use {
     anchor_lang::prelude::*,
     instructions::init::*,
+    instructions::multisig::*,
+    instructions::permissions::*,
+    instructions::insurance_fund::*,
     instructions::pools::*,
     instructions::liquidity::*,
     instructions::collateral::*,
     instructions::position::*,
+    instructions::orders::*,
+    instructions::delegation::*,
+    instructions::transfer_position::*,
+    instructions::dividends::*,
+    instructions::circuit_breaker::*,
+    instructions::oracle_history::*,
+    instructions::views::*,
+    instructions::auto_deleverage::*,
+    instructions::referral::*,
+    instructions::lp_staking::*,
     state::perps::{
         AmountAndFee, NewPositionPricesAndFee, PriceAndFee, ProfitAndLoss, SwapAmountAndFees,
     },
@@ -35,10 +49,48 @@ pub mod stockdex {
         instructions::pools::add_pool(ctx, &params)
     }
 
+    pub fn set_fee_tiers<'info>(
+        ctx: Context<'_, '_, '_, 'info, SetFeeTiers<'info>>,
+        params: SetFeeTiersParams,
+    ) -> Result<u8> {
+        instructions::pools::set_fee_tiers(ctx, &params)
+    }
+
+    pub fn set_withdrawal_params<'info>(
+        ctx: Context<'_, '_, '_, 'info, SetWithdrawalParams<'info>>,
+        params: SetWithdrawalParamsParams,
+    ) -> Result<u8> {
+        instructions::pools::set_withdrawal_params(ctx, &params)
+    }
+
     pub fn add_liquidity(ctx: Context<AddLiquidity>, params: AddLiquidityParams) -> Result<()> {
         instructions::liquidity::add_liquidity(ctx, &params)
     }
 
+    pub fn remove_liquidity<'info>(
+        ctx: Context<'_, '_, 'info, 'info, RemoveLiquidity<'info>>,
+        params: RemoveLiquidityParams,
+    ) -> Result<()> {
+        instructions::liquidity::remove_liquidity(ctx, &params)
+    }
+
+    pub fn request_remove_liquidity(
+        ctx: Context<RequestRemoveLiquidity>,
+        params: RequestRemoveLiquidityParams,
+    ) -> Result<()> {
+        instructions::liquidity::request_remove_liquidity(ctx, &params)
+    }
+
+    pub fn execute_remove_liquidity<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteRemoveLiquidity<'info>>,
+    ) -> Result<()> {
+        instructions::liquidity::execute_remove_liquidity(ctx)
+    }
+
+    pub fn cancel_remove_liquidity(ctx: Context<CancelRemoveLiquidity>) -> Result<()> {
+        instructions::liquidity::cancel_remove_liquidity(ctx)
+    }
+
     pub fn add_collateral(ctx: Context<AddCollateral>, params: AddCollateralParams) -> Result<()> {
         instructions::collateral::add_collateral(ctx, &params)
     }
@@ -57,4 +109,216 @@ pub mod stockdex {
     pub fn close_position(ctx: Context<ClosePosition>, params: ClosePositionParams) -> Result<()> {
         instructions::position::close_position(ctx, &params)
     }
+
+    pub fn set_delegation(
+        ctx: Context<SetDelegation>,
+        params: SetDelegationParams,
+    ) -> Result<()> {
+        instructions::delegation::set_delegation(ctx, &params)
+    }
+
+    pub fn revoke_delegation(ctx: Context<RevokeDelegation>) -> Result<()> {
+        instructions::delegation::revoke_delegation(ctx)
+    }
+
+    pub fn request_open_position(
+        ctx: Context<RequestOpenPosition>,
+        params: RequestOpenPositionParams,
+    ) -> Result<()> {
+        instructions::orders::request_open_position(ctx, &params)
+    }
+
+    pub fn execute_open_position(ctx: Context<ExecuteOpenPosition>) -> Result<()> {
+        instructions::orders::execute_open_position(ctx)
+    }
+
+    pub fn request_close_position(
+        ctx: Context<RequestClosePosition>,
+        params: ClosePositionParams,
+    ) -> Result<()> {
+        instructions::orders::request_close_position(ctx, &params)
+    }
+
+    pub fn execute_close_position(ctx: Context<ExecuteClosePosition>) -> Result<()> {
+        instructions::orders::execute_close_position(ctx)
+    }
+
+    pub fn cancel_order(ctx: Context<CancelOrder>) -> Result<()> {
+        instructions::orders::cancel_order(ctx)
+    }
+
+    pub fn transfer_position(
+        ctx: Context<TransferPosition>,
+        params: TransferPositionParams,
+    ) -> Result<()> {
+        instructions::transfer_position::transfer_position(ctx, &params)
+    }
+
+    pub fn liquidate(ctx: Context<Liquidate>, params: LiquidateParams) -> Result<()> {
+        instructions::position::liquidate(ctx, &params)
+    }
+
+    pub fn add_insurance_fund(
+        ctx: Context<AddInsuranceFund>,
+        params: AddInsuranceFundParams,
+    ) -> Result<()> {
+        instructions::insurance_fund::add_insurance_fund(ctx, &params)
+    }
+
+    pub fn withdraw_insurance_fund<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawInsuranceFund<'info>>,
+        params: WithdrawInsuranceFundParams,
+    ) -> Result<u8> {
+        instructions::insurance_fund::withdraw_insurance_fund(ctx, &params)
+    }
+
+    pub fn report_adl_candidate(
+        ctx: Context<ReportAdlCandidate>,
+        params: ReportAdlCandidateParams,
+    ) -> Result<()> {
+        instructions::auto_deleverage::report_adl_candidate(ctx, &params)
+    }
+
+    pub fn auto_deleverage<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AutoDeleverage<'info>>,
+        params: AutoDeleverageParams,
+    ) -> Result<()> {
+        instructions::auto_deleverage::auto_deleverage(ctx, &params)
+    }
+
+    pub fn set_referrer<'info>(
+        ctx: Context<'_, '_, '_, 'info, SetReferrer<'info>>,
+        params: SetReferrerParams,
+    ) -> Result<u8> {
+        instructions::referral::set_referrer(ctx, &params)
+    }
+
+    pub fn create_referral(ctx: Context<CreateReferral>, params: CreateReferralParams) -> Result<()> {
+        instructions::referral::create_referral(ctx, &params)
+    }
+
+    pub fn init_referral_rewards(ctx: Context<InitReferralRewards>) -> Result<()> {
+        instructions::referral::init_referral_rewards(ctx)
+    }
+
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> Result<()> {
+        instructions::referral::claim_referral_rewards(ctx)
+    }
+
+    pub fn init_lp_staking<'info>(
+        ctx: Context<'_, '_, '_, 'info, InitLpStaking<'info>>,
+        params: InitLpStakingParams,
+    ) -> Result<u8> {
+        instructions::lp_staking::init_lp_staking(ctx, &params)
+    }
+
+    pub fn stake_lp(ctx: Context<StakeLp>, params: StakeLpParams) -> Result<()> {
+        instructions::lp_staking::stake_lp(ctx, &params)
+    }
+
+    pub fn unstake_lp(ctx: Context<UnstakeLp>, params: UnstakeLpParams) -> Result<()> {
+        instructions::lp_staking::unstake_lp(ctx, &params)
+    }
+
+    pub fn claim_lp_staking_rewards(ctx: Context<ClaimLpStakingRewards>) -> Result<()> {
+        instructions::lp_staking::claim_lp_staking_rewards(ctx)
+    }
+
+    pub fn distribute_lp_staking_rewards(ctx: Context<DistributeLpStakingRewards>) -> Result<()> {
+        instructions::lp_staking::distribute_lp_staking_rewards(ctx)
+    }
+
+    pub fn record_dividend<'info>(
+        ctx: Context<'_, '_, '_, 'info, RecordDividend<'info>>,
+        params: RecordDividendParams,
+    ) -> Result<u8> {
+        instructions::dividends::record_dividend(ctx, &params)
+    }
+
+    pub fn update_circuit_breaker(ctx: Context<UpdateCircuitBreaker>) -> Result<bool> {
+        instructions::circuit_breaker::update_circuit_breaker(ctx)
+    }
+
+    pub fn clear_circuit_breaker<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClearCircuitBreaker<'info>>,
+        params: ClearCircuitBreakerParams,
+    ) -> Result<u8> {
+        instructions::circuit_breaker::clear_circuit_breaker(ctx, &params)
+    }
+
+    pub fn set_admin_signers<'info>(
+        ctx: Context<'_, '_, '_, 'info, SetAdminSigners<'info>>,
+        params: SetAdminSignersParams,
+    ) -> Result<u8> {
+        instructions::multisig::set_admin_signers(ctx, &params)
+    }
+
+    pub fn upgrade_multisig(ctx: Context<UpgradeMultisig>) -> Result<()> {
+        instructions::multisig::upgrade_multisig(ctx)
+    }
+
+    pub fn set_permissions<'info>(
+        ctx: Context<'_, '_, '_, 'info, SetPermissions<'info>>,
+        params: SetPermissionsParams,
+    ) -> Result<u8> {
+        instructions::permissions::set_permissions(ctx, &params)
+    }
+
+    pub fn set_guardian<'info>(
+        ctx: Context<'_, '_, '_, 'info, SetGuardian<'info>>,
+        params: SetGuardianParams,
+    ) -> Result<u8> {
+        instructions::permissions::set_guardian(ctx, &params)
+    }
+
+    pub fn guardian_pause(ctx: Context<GuardianPause>, params: GuardianPauseParams) -> Result<()> {
+        instructions::permissions::guardian_pause(ctx, &params)
+    }
+
+    pub fn set_timelock<'info>(
+        ctx: Context<'_, '_, '_, 'info, SetTimelock<'info>>,
+        params: SetTimelockParams,
+    ) -> Result<u8> {
+        instructions::multisig::set_timelock(ctx, &params)
+    }
+
+    pub fn cancel_queued_instruction<'info>(
+        ctx: Context<'_, '_, '_, 'info, CancelQueuedInstruction<'info>>,
+        params: CancelQueuedInstructionParams,
+    ) -> Result<u8> {
+        instructions::multisig::cancel_queued_instruction(ctx, &params)
+    }
+
+    pub fn create_proposal(
+        ctx: Context<CreateProposal>,
+        params: CreateProposalParams,
+    ) -> Result<()> {
+        instructions::multisig::create_proposal(ctx, &params)
+    }
+
+    pub fn close_proposal(ctx: Context<CloseProposal>) -> Result<()> {
+        instructions::multisig::close_proposal(ctx)
+    }
+
+    pub fn init_oracle_history(ctx: Context<InitOracleHistory>) -> Result<()> {
+        instructions::oracle_history::init_oracle_history(ctx)
+    }
+
+    pub fn update_oracle_history(ctx: Context<UpdateOracleHistory>) -> Result<()> {
+        instructions::oracle_history::update_oracle_history(ctx)
+    }
+
+    pub fn get_entry_price_and_fee(
+        ctx: Context<GetEntryPriceAndFee>,
+        params: GetEntryPriceAndFeeParams,
+    ) -> Result<NewPositionPricesAndFee> {
+        instructions::views::get_entry_price_and_fee(ctx, &params)
+    }
+
+    pub fn get_exit_price_and_fee(
+        ctx: Context<GetExitPriceAndFee>,
+        params: GetExitPriceAndFeeParams,
+    ) -> Result<PriceAndFee> {
+        instructions::views::get_exit_price_and_fee(ctx, &params)
+    }
 }
@@ -1,6 +1,7 @@
 use {
     crate::{
         error::PerpetualsError,
+        events::CircuitBreakerTripEvent,
         math,
         state::{
             oracle::{OracleParams, OraclePrice, OracleType},
@@ -34,6 +35,14 @@ pub struct Fees {
     pub close_position: u64,
     pub liquidation: u64,
     pub protocol_share: u64,
+    // share of the collected fees that goes to the insurance fund
+    pub insurance_share: u64,
+    // discount on trading fees for referred traders
+    pub referral_rebate: u64,
+    // share of the discounted trading fee credited to the referrer
+    pub referral_kickback: u64,
+    // share of the collected fees distributed to LP stakers, LP staking reward custody only
+    pub staking_share: u64,
     // configs for optimal fee mode
     pub fee_max: u64,
     pub fee_optimal: u64,
@@ -68,13 +77,30 @@ pub struct TradeStats {
     pub oi_short_usd: u64,
 }
 
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct BadDebtStats {
+    // losses of closed positions in excess of their collateral
+    pub total_usd: u64,
+    pub insurance_covered_usd: u64,
+    // part of the bad debt that was absorbed by liquidity providers
+    pub socialized_usd: u64,
+}
+
 #[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
 pub struct Assets {
     // collateral debt
     pub collateral: u64,
     // protocol_fees are part of the collected fees that is reserved for the protocol
     pub protocol_fees: u64,
-    // owned = total_assets - collateral + collected_fees - protocol_fees
+    // insurance_fund is part of the collected fees that is reserved to cover bad debt until it
+    // is swept to the pool insurance fund vault
+    pub insurance_fund: u64,
+    // referral_rewards are fee kickbacks owed to referrers until claimed
+    pub referral_rewards: u64,
+    // staking_rewards are collected fees waiting to be distributed to LP stakers
+    pub staking_rewards: u64,
+    // owned = total_assets - collateral + collected_fees - protocol_fees - insurance_fund
+    //         - referral_rewards - staking_rewards
     pub owned: u64,
     // locked funds for pnl payoff
     pub locked: u64,
@@ -89,6 +115,7 @@ pub struct PricingParams {
     pub trade_spread_long: u64,
     pub trade_spread_short: u64,
     pub swap_spread: u64,
+    pub max_price_impact: u64,
     pub min_initial_leverage: u64,
     pub max_initial_leverage: u64,
     pub max_leverage: u64,
@@ -98,6 +125,28 @@ pub struct PricingParams {
     // USD denominated values always have implied USD_DECIMALS decimals
     pub max_position_locked_usd: u64,
     pub max_total_locked_usd: u64,
+    // skew added by a trade that results in 100% price impact, 0 to disable price impact
+    pub price_impact_depth_usd: u64,
+    // open interest limits per side and for the net long/short imbalance, 0 to disable
+    pub max_long_oi_usd: u64,
+    pub max_short_oi_usd: u64,
+    pub max_skew_usd: u64,
+    // auto-deleveraging is allowed once unlocked liquidity drops below this share of owned assets,
+    // 0 to disable
+    pub adl_threshold: u64,
+    // extra spread as a multiple of the oracle confidence interval, 0 to disable
+    pub conf_spread_mult: u64,
+    // use the program-maintained TWAP instead of the oracle EMA for AUM and liquidations,
+    // the update_oracle_history crank has to refresh it within oracle.max_price_age_sec
+    // or liquidity changes of the pool fail
+    pub use_twap: bool,
+    pub twap_window_sec: u32,
+    // positions are opened and closed through keeper-filled order requests that expire
+    // after order_expiry_sec, only prices published within order_fill_window_sec after the
+    // request can fill them
+    pub use_delayed_orders: bool,
+    pub order_expiry_sec: u32,
+    pub order_fill_window_sec: u32,
 }
 
 #[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
@@ -117,6 +166,41 @@ pub struct BorrowRateState {
     pub last_update: i64,
 }
 
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct CircuitBreakerParams {
+    // max move of the price vs reference price with implied BPS_DECIMALS decimals, 0 to disable
+    pub max_price_move: u64,
+    // reference price is reset after each window
+    pub window_sec: u32,
+    // time the custody stays in reduce-only mode once the breaker is tripped
+    pub cooldown_sec: u32,
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct CircuitBreakerState {
+    // reference price has implied PRICE_DECIMALS decimals
+    pub reference_price: u64,
+    pub reference_time: i64,
+    pub halted_until: i64,
+    pub last_trip_time: i64,
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct TwapState {
+    // last TWAP computed from the custody oracle history
+    pub price: u64,
+    pub exponent: i32,
+    pub update_time: i64,
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct AdlState {
+    // highest scoring position reported since the last auto-deleveraging
+    pub top_position: Pubkey,
+    pub top_score: u128,
+    pub report_time: i64,
+}
+
 #[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
 pub struct PositionStats {
     pub open_positions: u64,
@@ -128,6 +212,17 @@ pub struct PositionStats {
     pub total_quantity: u128,
     pub cumulative_interest_usd: u64,
     pub cumulative_interest_snapshot: u128,
+    pub cumulative_dividend_usd: u64,
+    pub cumulative_dividend_snapshot: u128,
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct DividendState {
+    // dividends have implied USD_DECIMALS decimals per one whole token
+    pub cumulative_dividend: u128,
+    // announced dividend that is settled once ex_date is reached
+    pub pending_dividend: u64,
+    pub pending_ex_date: i64,
 }
 
 #[account]
@@ -145,15 +240,23 @@ pub struct Custody {
     pub permissions: Permissions,
     pub fees: Fees,
     pub borrow_rate: BorrowRateParams,
+    pub circuit_breaker: CircuitBreakerParams,
 
     // dynamic variables
     pub assets: Assets,
     pub collected_fees: FeesStats,
     pub volume_stats: VolumeStats,
     pub trade_stats: TradeStats,
+    pub bad_debt: BadDebtStats,
     pub long_positions: PositionStats,
     pub short_positions: PositionStats,
     pub borrow_rate_state: BorrowRateState,
+    pub dividends: DividendState,
+    pub circuit_breaker_state: CircuitBreakerState,
+    pub twap: TwapState,
+    pub adl_state: AdlState,
+    // set on the pool LP staking reward custody, the only custody that reserves staking fees
+    pub is_lp_staking_reward: bool,
 
     // bumps for address validation
     pub bump: u8,
@@ -222,6 +325,12 @@ impl Fees {
             && self.close_position as u128 <= Perpetuals::BPS_POWER
             && self.liquidation as u128 <= Perpetuals::BPS_POWER
             && self.protocol_share as u128 <= Perpetuals::BPS_POWER
+            && self.referral_rebate as u128 <= Perpetuals::BPS_POWER
+            && (self.protocol_share as u128
+                + self.insurance_share as u128
+                + self.referral_kickback as u128
+                + self.staking_share as u128)
+                <= Perpetuals::BPS_POWER
             && self.fee_max as u128 <= Perpetuals::BPS_POWER
             && self.fee_optimal as u128 <= Perpetuals::BPS_POWER
     }
@@ -229,7 +338,14 @@ impl Fees {
 
 impl OracleParams {
     pub fn validate(&self) -> bool {
-        self.oracle_type == OracleType::None || self.oracle_account != Pubkey::default()
+        (self.oracle_type == OracleType::None || self.oracle_account != Pubkey::default())
+            && ((self.oracle_type != OracleType::PythPull
+                && self.secondary_oracle_type != OracleType::PythPull)
+                || self.feed_id != [0; 32])
+            && (self.secondary_oracle_type == OracleType::None
+                || (self.secondary_oracle_account != Pubkey::default()
+                    && self.secondary_oracle_account != self.oracle_account
+                    && self.max_divergence as u128 <= Perpetuals::BPS_POWER))
     }
 }
 
@@ -241,8 +357,21 @@ impl PricingParams {
             && (self.trade_spread_long as u128) < Perpetuals::BPS_POWER
             && (self.trade_spread_short as u128) < Perpetuals::BPS_POWER
             && (self.swap_spread as u128) < Perpetuals::BPS_POWER
+            && (self.max_price_impact as u128) < Perpetuals::BPS_POWER
+            && (self.price_impact_depth_usd == 0 || self.max_price_impact > 0)
             && (self.max_utilization as u128) <= Perpetuals::BPS_POWER
             && self.max_position_locked_usd <= self.max_total_locked_usd
+            && (self.adl_threshold as u128) <= Perpetuals::BPS_POWER
+            && (!self.use_twap || self.twap_window_sec > 0)
+            && (!self.use_delayed_orders
+                || (self.order_fill_window_sec > 0
+                    && self.order_fill_window_sec <= self.order_expiry_sec))
+            // with both sides capped the skew can't exceed the larger cap, a higher limit
+            // would never bind and is rejected as a misconfiguration
+            && (self.max_skew_usd == 0
+                || self.max_long_oi_usd == 0
+                || self.max_short_oi_usd == 0
+                || self.max_skew_usd <= std::cmp::max(self.max_long_oi_usd, self.max_short_oi_usd))
     }
 }
 
@@ -252,8 +381,17 @@ impl BorrowRateParams {
     }
 }
 
+impl CircuitBreakerParams {
+    pub fn validate(&self) -> bool {
+        self.max_price_move == 0 || (self.window_sec > 0 && self.cooldown_sec > 0)
+    }
+}
+
 impl Custody {
     pub const LEN: usize = 8 + std::mem::size_of::<Custody>();
+    // time for other positions to outrank a reported auto-deleveraging candidate
+    pub const ADL_REPORT_DELAY_SEC: i64 = 30;
+    pub const ADL_REPORT_EXPIRY_SEC: i64 = 600;
 
     pub fn validate(&self) -> bool {
         (!self.is_virtual || !self.is_stable)
@@ -263,6 +401,94 @@ impl Custody {
             && self.pricing.validate()
             && self.fees.validate()
             && self.borrow_rate.validate()
+            && self.circuit_breaker.validate()
+    }
+
+    pub fn is_halted(&self, curtime: i64) -> bool {
+        curtime < self.circuit_breaker_state.halted_until
+    }
+
+    /// Moves the reference price forward and trips the breaker if the price left the band.
+    /// Returns true if the custody is halted (reduce-only).
+    pub fn update_circuit_breaker(
+        &mut self,
+        token_price: &OraclePrice,
+        curtime: i64,
+    ) -> Result<bool> {
+        if self.circuit_breaker.max_price_move == 0 {
+            return Ok(false);
+        }
+
+        let price = token_price
+            .scale_to_exponent(-(Perpetuals::PRICE_DECIMALS as i32))?
+            .price;
+        let state = &mut self.circuit_breaker_state;
+
+        let window_end =
+            math::checked_add(state.reference_time, self.circuit_breaker.window_sec as i64)?;
+        if state.reference_price == 0 || curtime >= window_end {
+            state.reference_price = price;
+            state.reference_time = curtime;
+        } else {
+            let price_diff = if price > state.reference_price {
+                math::checked_sub(price, state.reference_price)?
+            } else {
+                math::checked_sub(state.reference_price, price)?
+            };
+            let price_move = math::checked_div(
+                math::checked_mul(price_diff as u128, Perpetuals::BPS_POWER)?,
+                state.reference_price as u128,
+            )?;
+
+            if price_move > self.circuit_breaker.max_price_move as u128 {
+                msg!(
+                    "Circuit breaker tripped: price {} moved {} bps from reference {}",
+                    price,
+                    price_move,
+                    state.reference_price
+                );
+                state.halted_until =
+                    math::checked_add(curtime, self.circuit_breaker.cooldown_sec as i64)?;
+                state.last_trip_time = curtime;
+
+                emit!(CircuitBreakerTripEvent {
+                    pool: self.pool,
+                    mint: self.mint,
+                    price,
+                    reference_price: state.reference_price,
+                    price_move,
+                    halted_until: state.halted_until,
+                    time: curtime,
+                });
+
+                // restart the band around the new price
+                state.reference_price = price;
+                state.reference_time = curtime;
+            }
+        }
+
+        Ok(self.is_halted(curtime))
+    }
+
+    /// Fails if the custody is already halted, otherwise updates the breaker and returns true if
+    /// it has just tripped. Callers skip the instruction instead of failing in that case, so the
+    /// trip is persisted without waiting for the update_circuit_breaker crank.
+    pub fn check_circuit_breaker(
+        &mut self,
+        token_price: &OraclePrice,
+        curtime: i64,
+    ) -> Result<bool> {
+        require!(
+            !self.is_halted(curtime),
+            PerpetualsError::CircuitBreakerHalted
+        );
+        self.update_circuit_breaker(token_price, curtime)
+    }
+
+    pub fn clear_circuit_breaker(&mut self) {
+        self.circuit_breaker_state.halted_until = 0;
+        self.circuit_breaker_state.reference_price = 0;
+        self.circuit_breaker_state.reference_time = 0;
     }
 
     pub fn lock_funds(&mut self, amount: u64) -> Result<()> {
@@ -292,6 +518,105 @@ impl Custody {
         }
     }
 
+    // Returns the TWAP if enabled for this custody, otherwise the given oracle EMA price
+    pub fn get_reference_price(
+        &self,
+        token_ema_price: &OraclePrice,
+        curtime: i64,
+    ) -> Result<OraclePrice> {
+        if !self.pricing.use_twap {
+            return Ok(*token_ema_price);
+        }
+        if self.is_twap_stale(curtime)? {
+            msg!("Error: Custody TWAP is stale");
+            return err!(PerpetualsError::StaleOraclePrice);
+        }
+        Ok(OraclePrice::new(self.twap.price, self.twap.exponent))
+    }
+
+    pub fn is_twap_stale(&self, curtime: i64) -> Result<bool> {
+        let last_update_age_sec = math::checked_sub(curtime, self.twap.update_time)?;
+        Ok(self.twap.price == 0 || last_update_age_sec > self.oracle.max_price_age_sec as i64)
+    }
+
+    // true if unlocked liquidity is too low to reliably pay out profitable positions
+    pub fn is_adl_allowed(&self) -> Result<bool> {
+        if self.pricing.adl_threshold == 0 {
+            return Ok(false);
+        }
+        if self.assets.owned == 0 {
+            return Ok(self.assets.locked > 0);
+        }
+        let available_ratio = math::checked_div(
+            math::checked_mul(
+                self.assets.owned.saturating_sub(self.assets.locked) as u128,
+                Perpetuals::BPS_POWER,
+            )?,
+            self.assets.owned as u128,
+        )?;
+        Ok(available_ratio < self.pricing.adl_threshold as u128)
+    }
+
+    // share of a position (in BPS) to close to bring unlocked liquidity back to adl_threshold,
+    // payout_amount is what the full close pays on top of the position collateral
+    pub fn get_adl_close_ratio(&self, locked_amount: u64, payout_amount: u64) -> Result<u64> {
+        // (locked - r * locked_amount) <= (1 - threshold) * (owned - r * payout_amount)
+        let target_ratio =
+            math::checked_sub(Perpetuals::BPS_POWER, self.pricing.adl_threshold as u128)?;
+        let required = math::checked_mul(self.assets.locked as u128, Perpetuals::BPS_POWER)?
+            .saturating_sub(math::checked_mul(self.assets.owned as u128, target_ratio)?);
+        let released = math::checked_mul(locked_amount as u128, Perpetuals::BPS_POWER)?
+            .saturating_sub(math::checked_mul(payout_amount as u128, target_ratio)?);
+        if released == 0 {
+            return Ok(Perpetuals::BPS_POWER as u64);
+        }
+        let close_ratio = math::checked_ceil_div(
+            math::checked_mul(required, Perpetuals::BPS_POWER)?,
+            released,
+        )?;
+        math::checked_as_u64(std::cmp::min(close_ratio, Perpetuals::BPS_POWER))
+    }
+
+    // records the position as the auto-deleveraging candidate if it outranks the current one
+    pub fn report_adl_candidate(
+        &mut self,
+        position: Pubkey,
+        score: u128,
+        curtime: i64,
+    ) -> Result<()> {
+        let state = &mut self.adl_state;
+        let expired = curtime > math::checked_add(state.report_time, Self::ADL_REPORT_EXPIRY_SEC)?;
+        if state.top_position == position && !expired {
+            state.top_score = score;
+        } else if state.top_position == Pubkey::default() || expired || score > state.top_score {
+            state.top_position = position;
+            state.top_score = score;
+            state.report_time = curtime;
+        } else {
+            return err!(PerpetualsError::AutoDeleverageRank);
+        }
+        Ok(())
+    }
+
+    // the candidate can be deleveraged once others had time to report a higher score
+    pub fn check_adl_candidate(&self, position: Pubkey, curtime: i64) -> Result<()> {
+        let state = &self.adl_state;
+        require!(
+            state.top_position == position
+                && curtime >= math::checked_add(state.report_time, Self::ADL_REPORT_DELAY_SEC)?
+                && curtime <= math::checked_add(state.report_time, Self::ADL_REPORT_EXPIRY_SEC)?,
+            PerpetualsError::AutoDeleverageRank
+        );
+        Ok(())
+    }
+
+    // drops the candidate once its position is closed or moved, so a stale key can't block reports
+    pub fn clear_adl_candidate(&mut self, position: Pubkey) {
+        if self.adl_state.top_position == position {
+            self.adl_state = AdlState::default();
+        }
+    }
+
     pub fn unlock_funds(&mut self, amount: u64) -> Result<()> {
         require!(!self.is_virtual, PerpetualsError::InvalidCollateralCustody);
 
@@ -304,6 +629,47 @@ impl Custody {
         Ok(())
     }
 
+    /// Records the shortfall of an underwater position and covers as much of it as possible,
+    /// from the reserved insurance fees first and then from the pool insurance fund vault holding
+    /// insurance_fund_balance tokens. Returns the amount the caller has to move from the vault.
+    pub fn cover_bad_debt(
+        &mut self,
+        bad_debt_amount: u64,
+        bad_debt_usd: u64,
+        insurance_fund_balance: u64,
+    ) -> Result<u64> {
+        let reserved_amount = std::cmp::min(bad_debt_amount, self.assets.insurance_fund);
+        let vault_amount = std::cmp::min(
+            math::checked_sub(bad_debt_amount, reserved_amount)?,
+            insurance_fund_balance,
+        );
+        let covered_amount = math::checked_add(reserved_amount, vault_amount)?;
+        let covered_usd = if covered_amount == bad_debt_amount {
+            bad_debt_usd
+        } else {
+            math::checked_as_u64(math::checked_div(
+                math::checked_mul(bad_debt_usd as u128, covered_amount as u128)?,
+                bad_debt_amount as u128,
+            )?)?
+        };
+
+        self.assets.insurance_fund =
+            math::checked_sub(self.assets.insurance_fund, reserved_amount)?;
+        self.assets.owned = math::checked_add(self.assets.owned, covered_amount)?;
+
+        self.bad_debt.total_usd = self.bad_debt.total_usd.wrapping_add(bad_debt_usd);
+        self.bad_debt.insurance_covered_usd = self
+            .bad_debt
+            .insurance_covered_usd
+            .wrapping_add(covered_usd);
+        self.bad_debt.socialized_usd = self
+            .bad_debt
+            .socialized_usd
+            .wrapping_add(math::checked_sub(bad_debt_usd, covered_usd)?);
+
+        Ok(vault_amount)
+    }
+
     pub fn get_locked_amount(&self, size: u64, side: Side) -> Result<u64> {
         let max_payoff_mult = if side == Side::Short {
             std::cmp::min(Perpetuals::BPS_POWER, self.pricing.max_payoff_mult as u128)
@@ -316,6 +682,49 @@ impl Custody {
         )?)
     }
 
+    // checks open interest limits for a trade that adds size_usd to the given side
+    pub fn check_open_interest(&self, side: Side, size_usd: u64) -> Result<()> {
+        let (oi_long_usd, oi_short_usd) = if side == Side::Long {
+            (
+                math::checked_add(self.trade_stats.oi_long_usd, size_usd)?,
+                self.trade_stats.oi_short_usd,
+            )
+        } else {
+            (
+                self.trade_stats.oi_long_usd,
+                math::checked_add(self.trade_stats.oi_short_usd, size_usd)?,
+            )
+        };
+
+        if self.pricing.max_long_oi_usd > 0 && side == Side::Long {
+            require!(
+                oi_long_usd <= self.pricing.max_long_oi_usd,
+                PerpetualsError::MaxOpenInterest
+            );
+        }
+        if self.pricing.max_short_oi_usd > 0 && side == Side::Short {
+            require!(
+                oi_short_usd <= self.pricing.max_short_oi_usd,
+                PerpetualsError::MaxOpenInterest
+            );
+        }
+
+        // trades that shrink the imbalance are always allowed
+        if self.pricing.max_skew_usd > 0 {
+            let cur_skew_usd = self
+                .trade_stats
+                .oi_long_usd
+                .abs_diff(self.trade_stats.oi_short_usd);
+            let new_skew_usd = oi_long_usd.abs_diff(oi_short_usd);
+            require!(
+                new_skew_usd <= self.pricing.max_skew_usd || new_skew_usd <= cur_skew_usd,
+                PerpetualsError::MaxSkew
+            );
+        }
+
+        Ok(())
+    }
+
     pub fn get_interest_amount_usd(&self, position: &Position, curtime: i64) -> Result<u64> {
         if position.borrow_size_usd == 0 || self.is_virtual {
             return Ok(0);
@@ -353,6 +762,69 @@ impl Custody {
         }
     }
 
+    pub fn get_cumulative_dividend(&self, curtime: i64) -> Result<u128> {
+        if self.dividends.pending_ex_date > 0 && curtime >= self.dividends.pending_ex_date {
+            math::checked_add(
+                self.dividends.cumulative_dividend,
+                self.dividends.pending_dividend as u128,
+            )
+        } else {
+            Ok(self.dividends.cumulative_dividend)
+        }
+    }
+
+    // Positive for longs (received), charged to shorts (paid)
+    pub fn get_dividend_amount_usd(&self, position: &Position, curtime: i64) -> Result<u64> {
+        if position.size_usd == 0 || position.price == 0 || self.is_stable {
+            return Ok(0);
+        }
+
+        let cumulative_dividend = self.get_cumulative_dividend(curtime)?;
+
+        let position_dividend = if cumulative_dividend > position.cumulative_dividend_snapshot {
+            math::checked_sub(cumulative_dividend, position.cumulative_dividend_snapshot)?
+        } else {
+            return Ok(0);
+        };
+
+        let position_price = math::scale_to_exponent(
+            position.price,
+            -(Perpetuals::PRICE_DECIMALS as i32),
+            -(Perpetuals::USD_DECIMALS as i32),
+        )?;
+
+        // dividend_usd = dividend_per_token * size_usd / entry_price
+        math::checked_as_u64(math::checked_div(
+            math::checked_mul(position_dividend, position.size_usd as u128)?,
+            position_price as u128,
+        )?)
+    }
+
+    pub fn record_dividend(
+        &mut self,
+        dividend_per_share: u64,
+        ex_date: i64,
+        curtime: i64,
+    ) -> Result<()> {
+        require!(!self.is_stable, PerpetualsError::InvalidCustodyState);
+
+        // settle previously announced dividend if it went ex already
+        if self.dividends.pending_ex_date > 0 && curtime >= self.dividends.pending_ex_date {
+            self.dividends.cumulative_dividend = self.get_cumulative_dividend(curtime)?;
+        }
+
+        // dividend must be announced before ex-date, pending one can be replaced until then
+        if ex_date <= curtime {
+            msg!("Error: Ex-date {} is not in the future", ex_date);
+            return Err(ProgramError::InvalidArgument.into());
+        }
+
+        self.dividends.pending_dividend = dividend_per_share;
+        self.dividends.pending_ex_date = ex_date;
+
+        Ok(())
+    }
+
     pub fn update_borrow_rate(&mut self, curtime: i64) -> Result<()> {
         // if current_utilization < optimal_utilization:
         //   rate = base_rate + (current_utilization / optimal_utilization) * slope1
@@ -430,8 +902,19 @@ impl Custody {
                 },
                 size_usd: stats.size_usd,
                 borrow_size_usd: stats.borrow_size_usd,
-                unrealized_loss_usd: stats.cumulative_interest_usd,
+                // dividends settled so far are received by longs and paid by shorts
+                unrealized_profit_usd: if side == Side::Long {
+                    stats.cumulative_dividend_usd
+                } else {
+                    0
+                },
+                unrealized_loss_usd: if side == Side::Long {
+                    stats.cumulative_interest_usd
+                } else {
+                    math::checked_add(stats.cumulative_interest_usd, stats.cumulative_dividend_usd)?
+                },
                 cumulative_interest_snapshot: stats.cumulative_interest_snapshot,
+                cumulative_dividend_snapshot: stats.cumulative_dividend_snapshot,
                 locked_amount: stats.locked_amount,
                 ..Position::default()
             })
@@ -447,9 +930,10 @@ impl Custody {
         curtime: i64,
         collateral_custody: Option<&mut Custody>,
     ) -> Result<()> {
-        // compute accumulated interest
+        // compute accumulated interest and dividends
         let collective_position = self.get_collective_position(position.side)?;
         let interest_usd = self.get_interest_amount_usd(&collective_position, curtime)?;
+        let dividend_usd = self.get_dividend_amount_usd(&collective_position, curtime)?;
 
         // update positions
         let stats = if position.side == Side::Long {
@@ -462,6 +946,10 @@ impl Custody {
         stats.size_usd = math::checked_add(stats.size_usd, position.size_usd)?;
         stats.locked_amount = math::checked_add(stats.locked_amount, position.locked_amount)?;
 
+        stats.cumulative_dividend_usd =
+            math::checked_add(stats.cumulative_dividend_usd, dividend_usd)?;
+        stats.cumulative_dividend_snapshot = position.cumulative_dividend_snapshot;
+
         // update borrowed size and cumulative interest only if trading token custody is the collateral custody
         if collateral_custody.is_none() {
             stats.cumulative_interest_usd =
@@ -534,11 +1022,14 @@ impl Custody {
         curtime: i64,
         collateral_custody: Option<&mut Custody>,
     ) -> Result<()> {
-        // compute accumulated interest
+        // compute accumulated interest and dividends
         let collective_position = self.get_collective_position(position.side)?;
         let interest_usd = self.get_interest_amount_usd(&collective_position, curtime)?;
         let cumulative_interest_snapshot = self.get_cumulative_interest(curtime)?;
         let position_interest_usd = self.get_interest_amount_usd(position, curtime)?;
+        let dividend_usd = self.get_dividend_amount_usd(&collective_position, curtime)?;
+        let cumulative_dividend_snapshot = self.get_cumulative_dividend(curtime)?;
+        let position_dividend_usd = self.get_dividend_amount_usd(position, curtime)?;
 
         // update stats
         let stats = if position.side == Side::Long {
@@ -568,6 +1059,13 @@ impl Custody {
         stats.size_usd = math::checked_sub(stats.size_usd, position.size_usd)?;
         stats.locked_amount = math::checked_sub(stats.locked_amount, position.locked_amount)?;
 
+        stats.cumulative_dividend_usd =
+            math::checked_add(stats.cumulative_dividend_usd, dividend_usd)?;
+        stats.cumulative_dividend_usd = stats
+            .cumulative_dividend_usd
+            .saturating_sub(position_dividend_usd);
+        stats.cumulative_dividend_snapshot = cumulative_dividend_snapshot;
+
         let position_price = math::scale_to_exponent(
             position.price,
             -(Perpetuals::PRICE_DECIMALS as i32),
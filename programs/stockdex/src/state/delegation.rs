@@ -0,0 +1,67 @@
+use {crate::error::PerpetualsError, anchor_lang::prelude::*};
+
+pub enum DelegatedAction {
+    OpenPosition,
+    ClosePosition,
+    AdjustCollateral,
+}
+
+// trading rights granted by a position owner to a delegate key
+#[account]
+#[derive(Default, Debug)]
+pub struct Delegation {
+    pub owner: Pubkey,
+    pub delegate: Pubkey,
+    pub can_open: bool,
+    pub can_close: bool,
+    pub can_adjust_collateral: bool,
+    // max size of a position opened by the delegate, 0 for no limit
+    pub max_size_usd: u64,
+    // 0 for no expiry
+    pub expiry_time: i64,
+
+    pub bump: u8,
+}
+
+impl Delegation {
+    pub const LEN: usize = 8 + std::mem::size_of::<Delegation>();
+
+    // Accepts the owner or a delegate with the right for the given action
+    pub fn check_authority(
+        authority: &Pubkey,
+        owner: &Pubkey,
+        delegation: Option<&Delegation>,
+        action: DelegatedAction,
+        curtime: i64,
+    ) -> Result<()> {
+        if authority == owner {
+            return Ok(());
+        }
+        let Some(delegation) = delegation else {
+            return err!(PerpetualsError::InvalidDelegation);
+        };
+        require!(
+            delegation.owner == *owner
+                && delegation.delegate == *authority
+                && (delegation.expiry_time == 0 || curtime < delegation.expiry_time),
+            PerpetualsError::InvalidDelegation
+        );
+
+        let allowed = match action {
+            DelegatedAction::OpenPosition => delegation.can_open,
+            DelegatedAction::ClosePosition => delegation.can_close,
+            DelegatedAction::AdjustCollateral => delegation.can_adjust_collateral,
+        };
+        require!(allowed, PerpetualsError::InvalidDelegation);
+
+        Ok(())
+    }
+
+    pub fn check_size(&self, size_usd: u64) -> Result<()> {
+        require!(
+            self.max_size_usd == 0 || size_usd <= self.max_size_usd,
+            PerpetualsError::InvalidDelegation
+        );
+        Ok(())
+    }
+}
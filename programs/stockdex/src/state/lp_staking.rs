@@ -0,0 +1,78 @@
+use {crate::math, anchor_lang::prelude::*};
+
+#[account]
+#[derive(Default, Debug)]
+pub struct LpStaking {
+    pub pool: Pubkey,
+    // stable custody whose fee share is distributed to stakers
+    pub reward_custody: Pubkey,
+    pub total_staked: u64,
+    // cumulative rewards per staked lp token with implied REWARD_PER_SHARE_DECIMALS decimals
+    pub reward_per_share: u128,
+    pub total_distributed: u64,
+    pub last_distribution_time: i64,
+
+    // bumps for address validation
+    pub bump: u8,
+    pub stake_token_account_bump: u8,
+    pub reward_token_account_bump: u8,
+}
+
+#[account]
+#[derive(Default, Debug)]
+pub struct LpStake {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub amount: u64,
+    // LpStaking::reward_per_share at the last settlement
+    pub reward_per_share_snapshot: u128,
+    pub pending_rewards: u64,
+    // time of the last stake, unstaking is locked for MIN_STAKE_PERIOD_SEC after it
+    pub stake_time: i64,
+
+    pub bump: u8,
+}
+
+impl LpStaking {
+    pub const LEN: usize = 8 + std::mem::size_of::<LpStaking>();
+    pub const REWARD_PER_SHARE_DECIMALS: u8 = 12;
+    pub const REWARD_PER_SHARE_POWER: u128 = 10u64.pow(Self::REWARD_PER_SHARE_DECIMALS as u32) as u128;
+    // every stake locks the whole staked amount for this long
+    pub const MIN_STAKE_PERIOD_SEC: i64 = 86_400;
+
+    pub fn add_rewards(&mut self, amount: u64, curtime: i64) -> Result<()> {
+        self.reward_per_share = math::checked_add(
+            self.reward_per_share,
+            math::checked_div(
+                math::checked_mul(amount as u128, Self::REWARD_PER_SHARE_POWER)?,
+                self.total_staked as u128,
+            )?,
+        )?;
+        self.total_distributed = self.total_distributed.wrapping_add(amount);
+        self.last_distribution_time = curtime;
+        Ok(())
+    }
+}
+
+impl LpStake {
+    pub const LEN: usize = 8 + std::mem::size_of::<LpStake>();
+
+    // moves rewards accrued since the last snapshot to pending_rewards
+    pub fn settle_rewards(&mut self, lp_staking: &LpStaking) -> Result<()> {
+        if self.reward_per_share_snapshot < lp_staking.reward_per_share {
+            let accrued = math::checked_as_u64(math::checked_div(
+                math::checked_mul(
+                    math::checked_sub(
+                        lp_staking.reward_per_share,
+                        self.reward_per_share_snapshot,
+                    )?,
+                    self.amount as u128,
+                )?,
+                LpStaking::REWARD_PER_SHARE_POWER,
+            )?)?;
+            self.pending_rewards = math::checked_add(self.pending_rewards, accrued)?;
+        }
+        self.reward_per_share_snapshot = lp_staking.reward_per_share;
+        Ok(())
+    }
+}
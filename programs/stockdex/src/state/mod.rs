@@ -4,10 +4,28 @@ pub mod pool;
 pub mod custody;
 pub mod oracle;
 pub mod multisig;
+pub mod referral;
+pub mod user_stats;
+pub mod lp_staking;
+pub mod withdrawal_request;
+pub mod oracle_history;
+pub mod order_request;
+pub mod delegation;
+pub mod position_counter;
+pub mod proposal;
 
 pub use perps::*;
 pub use position::*;
 pub use pool::*;
 pub use custody::*;
 pub use oracle::*;
-pub use multisig::*;
\ No newline at end of file
+pub use multisig::*;
+pub use referral::*;
+pub use user_stats::*;
+pub use lp_staking::*;
+pub use withdrawal_request::*;
+pub use oracle_history::*;
+pub use order_request::*;
+pub use delegation::*;
+pub use position_counter::*;
+pub use proposal::*;
@@ -1,24 +1,56 @@
 //! Multisig state and routines
 
 use {
-    crate::{error::PerpetualsError, math},
-    ahash::AHasher,
-    anchor_lang::{prelude::*, Bumps},
-    std::hash::Hasher,
+    crate::{
+        error::PerpetualsError, events::QueueAdminInstructionEvent, math, state::proposal::Proposal,
+    },
+    anchor_lang::{prelude::*, solana_program::hash::hashv, Bumps},
 };
 
 #[repr(C, packed)]
 #[account(zero_copy)]
 #[derive(Default)]
 pub struct Multisig {
+    pub num_signers: u8,
+    // sum of the weights of the signers that signed the pending instruction
+    pub signed_weight: u8,
+    // sum of the weights required to execute an instruction
+    pub min_weight: u8,
+    pub instruction_accounts_len: u8,
+    pub instruction_data_len: u16,
+    pub instruction_hash: [u8; 32],
+    pub signers: [Pubkey; 16], // Multisig::MAX_SIGNERS
+    pub signed: [u8; 16],      // Multisig::MAX_SIGNERS
+    pub weights: [u8; 16],     // Multisig::MAX_SIGNERS
+    // incremented on each change of the signer set, proposals are bound to the version
+    pub signers_version: u32,
+    // delay between reaching quorum and execution, 0 executes immediately
+    pub timelock_sec: u32,
+    pub queued_instruction_hash: [u8; 32],
+    pub queued_instruction_accounts_len: u8,
+    pub queued_instruction_data_len: u16,
+    // earliest execution time of the queued instruction, 0 if nothing is queued
+    pub queued_eta: i64,
+    // bumped on each execution of an instruction signed in the multisig, part of the hash
+    pub nonce: u64,
+    // id of the next proposal, ids are never reused so a closed proposal can't be recreated
+    pub proposal_count: u64,
+    pub bump: u8,
+}
+
+// layout of up to 6 unweighted signers, upgraded in place by upgrade_multisig
+#[repr(C, packed)]
+#[zero_copy]
+#[derive(Default)]
+pub struct DeprecatedMultisig {
     pub num_signers: u8,
     pub num_signed: u8,
     pub min_signatures: u8,
     pub instruction_accounts_len: u8,
     pub instruction_data_len: u16,
     pub instruction_hash: u64,
-    pub signers: [Pubkey; 6], // Multisig::MAX_SIGNERS
-    pub signed: [u8; 6],      // Multisig::MAX_SIGNERS
+    pub signers: [Pubkey; 6],
+    pub signed: [u8; 6],
     pub bump: u8,
 }
 
@@ -36,27 +68,37 @@ pub enum AdminInstruction {
     SetCustomOraclePrice,
     SetTestTime,
     UpgradeCustody,
+    RecordDividend,
+    ClearCircuitBreaker,
+    SetFeeTiers,
+    InitLpStaking,
+    SetWithdrawalParams,
+    SetTimelock,
+    CancelQueuedInstruction,
+    SetGuardian,
+    WithdrawInsuranceFund,
+    SetReferrer,
 }
 
 impl Multisig {
-    pub const MAX_SIGNERS: usize = 6;
+    pub const MAX_SIGNERS: usize = 16;
+    pub const MAX_TIMELOCK_SEC: u32 = 30 * 86_400;
     pub const LEN: usize = 8 + std::mem::size_of::<Multisig>();
 
-    /// Returns instruction accounts and data hash.
-    /// Hash is not cryptographic and is meant to perform a fast check that admins are signing
-    /// the same instruction.
+    /// Returns SHA-256 hash of the program id, nonce, instruction accounts and data.
+    /// Instruction data starts with the admin instruction discriminator.
     pub fn get_instruction_hash(
         instruction_accounts: &[AccountInfo],
         instruction_data: &[u8],
-    ) -> u64 {
-        let mut hasher = AHasher::default();
+        nonce: u64,
+    ) -> [u8; 32] {
+        let nonce_bytes = nonce.to_le_bytes();
+        let mut values: Vec<&[u8]> = vec![crate::ID.as_ref(), &nonce_bytes];
         for account in instruction_accounts {
-            hasher.write(account.key.as_ref());
-        }
-        if !instruction_data.is_empty() {
-            hasher.write(instruction_data);
+            values.push(account.key.as_ref());
         }
-        hasher.finish()
+        values.push(instruction_data);
+        hashv(&values).to_bytes()
     }
 
     /// Returns all accounts for the given context
@@ -68,28 +110,38 @@ impl Multisig {
         infos
     }
 
-    /// Returns serialized instruction data
+    /// Returns admin instruction discriminator followed by serialized params
     pub fn get_instruction_data<T: AnchorSerialize>(
         instruction_type: AdminInstruction,
         params: &T,
     ) -> Result<Vec<u8>> {
-        let mut res = vec![];
+        let mut res = vec![instruction_type as u8];
         AnchorSerialize::serialize(&params, &mut res)?;
-        res.push(instruction_type as u8);
         Ok(res)
     }
 
-    /// Initializes multisig PDA with a new set of signers
-    pub fn set_signers(&mut self, admin_signers: &[AccountInfo], min_signatures: u8) -> Result<()> {
-        if admin_signers.is_empty() || min_signatures == 0 {
+    /// Initializes multisig PDA with a new set of weighted signers
+    pub fn set_signers(
+        &mut self,
+        admin_signers: &[AccountInfo],
+        weights: &[u8],
+        min_weight: u8,
+    ) -> Result<()> {
+        if admin_signers.is_empty() || min_weight == 0 {
             msg!("Error: At least one signer is required");
             return Err(ProgramError::MissingRequiredSignature.into());
         }
-        if (min_signatures as usize) > admin_signers.len() {
+        if weights.len() != admin_signers.len() || weights.contains(&0) {
+            msg!("Error: Each signer requires a non-zero weight");
+            return Err(ProgramError::InvalidArgument.into());
+        }
+        let total_weight = weights
+            .iter()
+            .try_fold(0u8, |total, weight| total.checked_add(*weight));
+        if total_weight.is_none_or(|total_weight| min_weight > total_weight) {
             msg!(
-                "Error: Number of min signatures ({}) exceeded number of signers ({})",
-                min_signatures,
-                admin_signers.len(),
+                "Error: Min weight ({}) exceeded total weight of signers or total weight overflows",
+                min_weight,
             );
             return Err(ProgramError::InvalidArgument.into());
         }
@@ -104,6 +156,7 @@ impl Multisig {
 
         let mut signers: [Pubkey; Multisig::MAX_SIGNERS] = Default::default();
         let mut signed: [u8; Multisig::MAX_SIGNERS] = Default::default();
+        let mut signer_weights: [u8; Multisig::MAX_SIGNERS] = Default::default();
 
         for idx in 0..admin_signers.len() {
             if signers.contains(admin_signers[idx].key) {
@@ -112,30 +165,161 @@ impl Multisig {
             }
             signers[idx] = *admin_signers[idx].key;
             signed[idx] = 0;
+            signer_weights[idx] = weights[idx];
         }
 
         *self = Multisig {
             num_signers: admin_signers.len() as u8,
-            num_signed: 0,
-            min_signatures,
+            signed_weight: 0,
+            min_weight,
             instruction_accounts_len: 0,
             instruction_data_len: 0,
-            instruction_hash: 0,
+            instruction_hash: [0; 32],
             signers,
             signed,
+            weights: signer_weights,
+            signers_version: math::checked_add(self.signers_version, 1)?,
+            timelock_sec: self.timelock_sec,
+            queued_instruction_hash: [0; 32],
+            queued_instruction_accounts_len: 0,
+            queued_instruction_data_len: 0,
+            queued_eta: 0,
+            nonce: self.nonce,
+            proposal_count: self.proposal_count,
             bump: self.bump,
         };
 
         Ok(())
     }
 
-    /// Signs multisig and returns Ok(0) if there are enough signatures to continue or Ok(signatures_left) otherwise.
+    /// Signs multisig and returns Ok(0) if the signed weight reached min_weight or Ok(weight_left) otherwise.
+    /// With a timelock, reaching quorum queues the instruction and returns Ok(1), it is executed
+    /// when submitted again by any signer after the timelock has passed.
+    /// Signatures are collected in the proposal if provided, otherwise in the multisig itself,
+    /// which only holds one pending instruction.
     /// If Err() is returned then signature was not recognized and transaction must be aborted.
     pub fn sign_multisig(
         &mut self,
         signer_account: &AccountInfo,
         instruction_accounts: &[AccountInfo],
         instruction_data: &[u8],
+        proposal: Option<&mut Proposal>,
+    ) -> Result<u8> {
+        if let Some(proposal) = proposal {
+            self.sign_proposal(
+                proposal,
+                signer_account,
+                instruction_accounts,
+                instruction_data,
+                true,
+            )
+        } else {
+            self.sign_multisig_internal(signer_account, instruction_accounts, instruction_data, true)
+        }
+    }
+
+    /// Same as sign_multisig but executes on quorum regardless of the timelock, used to cancel
+    /// queued instructions.
+    pub fn sign_multisig_without_timelock(
+        &mut self,
+        signer_account: &AccountInfo,
+        instruction_accounts: &[AccountInfo],
+        instruction_data: &[u8],
+        proposal: Option<&mut Proposal>,
+    ) -> Result<u8> {
+        if let Some(proposal) = proposal {
+            self.sign_proposal(
+                proposal,
+                signer_account,
+                instruction_accounts,
+                instruction_data,
+                false,
+            )
+        } else {
+            self.sign_multisig_internal(signer_account, instruction_accounts, instruction_data, false)
+        }
+    }
+
+    fn sign_proposal(
+        &self,
+        proposal: &mut Proposal,
+        signer_account: &AccountInfo,
+        instruction_accounts: &[AccountInfo],
+        instruction_data: &[u8],
+        use_timelock: bool,
+    ) -> Result<u8> {
+        // return early if not a signer
+        if !signer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature.into());
+        }
+        let signer_idx = self.get_signer_index(signer_account.key)?;
+
+        let curtime = Clock::get()?.unix_timestamp;
+        proposal.check_pending(curtime)?;
+        if proposal.is_bound() && proposal.signers_version != self.signers_version {
+            return err!(PerpetualsError::ProposalSignersChanged);
+        }
+
+        // the first signature binds the proposal to the instruction, proposal ids are assigned
+        // from Multisig::proposal_count and executed once so the id is used as the nonce
+        let instruction_hash =
+            Multisig::get_instruction_hash(instruction_accounts, instruction_data, proposal.id);
+        if !proposal.is_bound() {
+            proposal.signers_version = self.signers_version;
+            proposal.instruction_hash = instruction_hash;
+            proposal.instruction_accounts_len = instruction_accounts.len() as u8;
+            proposal.instruction_data_len = instruction_data.len() as u16;
+        } else if instruction_hash != proposal.instruction_hash
+            || instruction_accounts.len() != proposal.instruction_accounts_len as usize
+            || instruction_data.len() != proposal.instruction_data_len as usize
+        {
+            return err!(PerpetualsError::ProposalInstructionMismatch);
+        }
+
+        // execute the queued instruction once the timelock has passed
+        if proposal.queued_eta != 0 {
+            if curtime < proposal.queued_eta {
+                msg!("Instruction is queued until {}", proposal.queued_eta);
+                return err!(PerpetualsError::MultisigTimelockNotExpired);
+            }
+            proposal.executed = true;
+            return Ok(0);
+        }
+
+        if proposal.signed[signer_idx] == 1 {
+            return err!(PerpetualsError::MultisigAlreadySigned);
+        }
+        proposal.signed[signer_idx] = 1;
+        proposal.signed_weight =
+            math::checked_add(proposal.signed_weight, self.weights[signer_idx])?;
+
+        if proposal.signed_weight < self.min_weight {
+            return math::checked_sub(self.min_weight, proposal.signed_weight);
+        }
+
+        // quorum reached
+        if use_timelock && self.timelock_sec > 0 {
+            proposal.queued_eta = math::checked_add(curtime, self.timelock_sec as i64)?;
+            msg!("Instruction has been queued until {}", proposal.queued_eta);
+            emit!(QueueAdminInstructionEvent {
+                instruction_hash,
+                proposal_id: Some(proposal.id),
+                queued_eta: proposal.queued_eta,
+                time: curtime,
+            });
+            return Ok(1);
+        }
+        proposal.executed = true;
+
+        Ok(0)
+    }
+
+    fn sign_multisig_internal(
+        &mut self,
+        signer_account: &AccountInfo,
+        instruction_accounts: &[AccountInfo],
+        instruction_data: &[u8],
+        use_timelock: bool,
     ) -> Result<u8> {
         // return early if not a signer
         if !signer_account.is_signer {
@@ -149,44 +333,114 @@ impl Multisig {
             return err!(PerpetualsError::MultisigAccountNotAuthorized);
         };
 
+        let use_timelock = use_timelock && self.timelock_sec > 0;
+
         // if single signer return Ok to continue
-        if self.num_signers <= 1 {
+        if self.num_signers <= 1 && !use_timelock {
+            self.nonce = math::checked_add(self.nonce, 1)?;
             return Ok(0);
         }
 
         let instruction_hash =
-            Multisig::get_instruction_hash(instruction_accounts, instruction_data);
+            Multisig::get_instruction_hash(instruction_accounts, instruction_data, self.nonce);
+
+        // execute the queued instruction once the timelock has passed
+        if use_timelock
+            && self.queued_eta != 0
+            && instruction_hash == self.queued_instruction_hash
+            && instruction_accounts.len() == self.queued_instruction_accounts_len as usize
+            && instruction_data.len() == self.queued_instruction_data_len as usize
+        {
+            let curtime = Clock::get()?.unix_timestamp;
+            let queued_eta = self.queued_eta;
+            if curtime < queued_eta {
+                msg!("Instruction is queued until {}", queued_eta);
+                return err!(PerpetualsError::MultisigTimelockNotExpired);
+            }
+            self.clear_queue();
+            self.nonce = math::checked_add(self.nonce, 1)?;
+            return Ok(0);
+        }
+
+        let signatures_left = self.count_signature(
+            signer_idx,
+            instruction_hash,
+            instruction_accounts.len(),
+            instruction_data.len(),
+        )?;
+        if signatures_left > 0 {
+            return Ok(signatures_left);
+        }
+        if !use_timelock {
+            self.nonce = math::checked_add(self.nonce, 1)?;
+            return Ok(0);
+        }
+
+        // quorum reached, queue the instruction and reset signatures for the next one
+        require!(self.queued_eta == 0, PerpetualsError::MultisigInstructionQueued);
+        let curtime = Clock::get()?.unix_timestamp;
+        let queued_eta = math::checked_add(curtime, self.timelock_sec as i64)?;
+        self.queued_instruction_hash = instruction_hash;
+        self.queued_instruction_accounts_len = instruction_accounts.len() as u8;
+        self.queued_instruction_data_len = instruction_data.len() as u16;
+        self.queued_eta = queued_eta;
+        self.signed_weight = 0;
+        self.instruction_hash = [0; 32];
+        self.signed.fill(0);
+        msg!("Instruction has been queued until {}", queued_eta);
+        emit!(QueueAdminInstructionEvent {
+            instruction_hash,
+            proposal_id: None,
+            queued_eta,
+            time: curtime,
+        });
+
+        Ok(1)
+    }
+
+    /// Counts the signature in and returns the weight left
+    fn count_signature(
+        &mut self,
+        signer_idx: usize,
+        instruction_hash: [u8; 32],
+        instruction_accounts_len: usize,
+        instruction_data_len: usize,
+    ) -> Result<u8> {
         if instruction_hash != self.instruction_hash
-            || instruction_accounts.len() != self.instruction_accounts_len as usize
-            || instruction_data.len() != self.instruction_data_len as usize
+            || instruction_accounts_len != self.instruction_accounts_len as usize
+            || instruction_data_len != self.instruction_data_len as usize
         {
             // if this is a new instruction reset the data
-            self.num_signed = 1;
-            self.instruction_accounts_len = instruction_accounts.len() as u8;
-            self.instruction_data_len = instruction_data.len() as u16;
+            self.signed_weight = self.weights[signer_idx];
+            self.instruction_accounts_len = instruction_accounts_len as u8;
+            self.instruction_data_len = instruction_data_len as u16;
             self.instruction_hash = instruction_hash;
             self.signed.fill(0);
             self.signed[signer_idx] = 1;
             //multisig.pack(*multisig_account.try_borrow_mut_data()?)?;
 
-            math::checked_sub(self.min_signatures, 1)
+            Ok(self.min_weight.saturating_sub(self.signed_weight))
         } else if self.signed[signer_idx] == 1 {
             err!(PerpetualsError::MultisigAlreadySigned)
-        } else if self.num_signed < self.min_signatures {
+        } else if self.signed_weight < self.min_weight {
             // count the signature in
-            self.num_signed = math::checked_add(self.num_signed, 1)?;
+            self.signed_weight = math::checked_add(self.signed_weight, self.weights[signer_idx])?;
             self.signed[signer_idx] = 1;
 
-            if self.num_signed == self.min_signatures {
-                Ok(0)
-            } else {
-                math::checked_sub(self.min_signatures, self.num_signed)
-            }
+            Ok(self.min_weight.saturating_sub(self.signed_weight))
         } else {
             err!(PerpetualsError::MultisigAlreadyExecuted)
         }
     }
 
+    /// Drops the queued instruction
+    pub fn clear_queue(&mut self) {
+        self.queued_instruction_hash = [0; 32];
+        self.queued_instruction_accounts_len = 0;
+        self.queued_instruction_data_len = 0;
+        self.queued_eta = 0;
+    }
+
     /// Removes admin signature from the multisig
     pub fn unsign_multisig(&mut self, signer_account: &AccountInfo) -> Result<()> {
         // return early if not a signer
@@ -195,7 +449,7 @@ impl Multisig {
         }
 
         // if single signer return
-        if self.num_signers <= 1 || self.num_signed == 0 {
+        if self.num_signers <= 1 || self.signed_weight == 0 {
             return Ok(());
         }
 
@@ -212,7 +466,7 @@ impl Multisig {
         }
 
         // remove signature
-        self.num_signed = math::checked_sub(self.num_signed, 1)?;
+        self.signed_weight = math::checked_sub(self.signed_weight, self.weights[signer_idx])?;
         self.signed[signer_idx] = 0;
 
         Ok(())
@@ -228,8 +482,422 @@ impl Multisig {
         err!(PerpetualsError::MultisigAccountNotAuthorized)
     }
 
+    /// Converts the deprecated layout, each signer gets a weight of 1.
+    /// Signatures of the pending instruction are dropped as they were collected for the old
+    /// instruction hash, timelock, nonce and proposal counter start from their defaults.
+    pub fn from_deprecated(deprecated: &DeprecatedMultisig) -> Multisig {
+        let mut multisig = Multisig {
+            num_signers: deprecated.num_signers,
+            min_weight: deprecated.min_signatures,
+            bump: deprecated.bump,
+            ..Default::default()
+        };
+        let deprecated_signers = deprecated.signers;
+        for (idx, signer) in deprecated_signers
+            .iter()
+            .take(deprecated.num_signers as usize)
+            .enumerate()
+        {
+            multisig.signers[idx] = *signer;
+            multisig.weights[idx] = 1;
+        }
+        multisig
+    }
+
     /// Checks if provided account is one of multisig signers
     pub fn is_signer(&self, key: &Pubkey) -> Result<bool> {
         Ok(self.get_signer_index(key).is_ok())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        anchor_lang::solana_program::program_stubs::{set_syscall_stubs, SyscallStubs},
+        std::{cell::Cell, sync::Once},
+    };
+
+    thread_local! {
+        static CURTIME: Cell<i64> = const { Cell::new(0) };
+    }
+
+    // Clock::get() is a syscall, the stub returns the time set by the calling test
+    struct ClockStub;
+
+    impl SyscallStubs for ClockStub {
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            let clock = Clock {
+                unix_timestamp: CURTIME.with(Cell::get),
+                ..Default::default()
+            };
+            unsafe { var_addr.cast::<Clock>().write(clock) };
+            0
+        }
+    }
+
+    fn set_time(curtime: i64) {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            set_syscall_stubs(Box::new(ClockStub));
+        });
+        CURTIME.with(|time| time.set(curtime));
+    }
+
+    fn get_signer(key: Pubkey, is_signer: bool) -> AccountInfo<'static> {
+        AccountInfo::new(
+            Box::leak(Box::new(key)),
+            is_signer,
+            false,
+            Box::leak(Box::new(0)),
+            Box::leak(Vec::new().into_boxed_slice()),
+            &anchor_lang::system_program::ID,
+            false,
+            0,
+        )
+    }
+
+    fn get_multisig(
+        weights: &[u8],
+        min_weight: u8,
+        timelock_sec: u32,
+    ) -> (Multisig, Vec<AccountInfo<'static>>) {
+        let signers: Vec<AccountInfo> = weights
+            .iter()
+            .map(|_| get_signer(Pubkey::new_unique(), true))
+            .collect();
+        let mut multisig = Multisig {
+            timelock_sec,
+            ..Default::default()
+        };
+        multisig.set_signers(&signers, weights, min_weight).unwrap();
+        (multisig, signers)
+    }
+
+    fn get_proposal(id: u64, expiry_time: i64) -> Proposal {
+        Proposal {
+            id,
+            expiry_time,
+            ..Default::default()
+        }
+    }
+
+    fn sign(multisig: &mut Multisig, signer: &AccountInfo, data: &[u8]) -> Result<u8> {
+        multisig.sign_multisig(signer, &[], data, None)
+    }
+
+    fn sign_proposal(
+        multisig: &mut Multisig,
+        signer: &AccountInfo,
+        data: &[u8],
+        proposal: &mut Proposal,
+    ) -> Result<u8> {
+        multisig.sign_multisig(signer, &[], data, Some(proposal))
+    }
+
+    fn assert_error<T: std::fmt::Debug>(result: Result<T>, expected: PerpetualsError) {
+        match result {
+            Err(Error::AnchorError(err)) => {
+                assert_eq!(err.error_code_number, u32::from(expected))
+            }
+            other => panic!("expected {:?}, got {:?}", expected, other),
+        }
+    }
+
+    const DATA: [u8; 3] = [AdminInstruction::SetFeeTiers as u8, 1, 2];
+    const OTHER_DATA: [u8; 3] = [AdminInstruction::SetFeeTiers as u8, 3, 4];
+
+    #[test]
+    fn test_set_signers() {
+        let (multisig, signers) = get_multisig(&[2, 1, 1], 3, 0);
+        assert_eq!({ multisig.num_signers }, 3);
+        assert_eq!({ multisig.signers_version }, 1);
+        assert_eq!({ multisig.weights }[..4], [2, 1, 1, 0]);
+
+        let mut multisig = Multisig::default();
+        assert!(multisig.set_signers(&signers, &[2, 1, 1], 5).is_err());
+        assert!(multisig.set_signers(&signers, &[2, 0, 1], 1).is_err());
+        assert!(multisig.set_signers(&signers, &[255, 1, 1], 1).is_err());
+        assert!(multisig.set_signers(&signers[..2], &[2, 1, 1], 1).is_err());
+        let duplicate = [signers[0].clone(), signers[0].clone()];
+        assert!(multisig.set_signers(&duplicate, &[1, 1], 1).is_err());
+        assert_eq!({ multisig.signers_version }, 0);
+    }
+
+    #[test]
+    fn test_weighted_quorum() {
+        let (mut multisig, signers) = get_multisig(&[2, 1, 1], 3, 0);
+
+        assert_eq!(sign(&mut multisig, &signers[1], &DATA).unwrap(), 2);
+        assert_error(
+            sign(&mut multisig, &signers[1], &DATA),
+            PerpetualsError::MultisigAlreadySigned,
+        );
+        assert_eq!({ multisig.nonce }, 0);
+
+        // signatures are only counted for the pending instruction
+        assert_eq!(sign(&mut multisig, &signers[2], &OTHER_DATA).unwrap(), 2);
+        assert_eq!(sign(&mut multisig, &signers[0], &OTHER_DATA).unwrap(), 0);
+        assert_eq!({ multisig.nonce }, 1);
+
+        // the nonce is part of the hash, the same instruction is signed again from scratch
+        assert_eq!(sign(&mut multisig, &signers[0], &OTHER_DATA).unwrap(), 1);
+        assert_eq!({ multisig.nonce }, 1);
+    }
+
+    #[test]
+    fn test_unauthorized_signer() {
+        let (mut multisig, signers) = get_multisig(&[1, 1], 2, 0);
+
+        let not_signed = get_signer(*signers[0].key, false);
+        assert!(matches!(
+            sign(&mut multisig, &not_signed, &DATA),
+            Err(Error::ProgramError(_))
+        ));
+        assert_error(
+            sign(
+                &mut multisig,
+                &get_signer(Pubkey::new_unique(), true),
+                &DATA,
+            ),
+            PerpetualsError::MultisigAccountNotAuthorized,
+        );
+    }
+
+    #[test]
+    fn test_single_signer() {
+        let (mut multisig, signers) = get_multisig(&[1], 1, 0);
+
+        assert_eq!(sign(&mut multisig, &signers[0], &DATA).unwrap(), 0);
+        assert_eq!(sign(&mut multisig, &signers[0], &DATA).unwrap(), 0);
+        assert_eq!({ multisig.nonce }, 2);
+    }
+
+    #[test]
+    fn test_timelock() {
+        let (mut multisig, signers) = get_multisig(&[1, 1], 2, 100);
+        set_time(1_000);
+
+        assert_eq!(sign(&mut multisig, &signers[0], &DATA).unwrap(), 1);
+        assert_eq!(sign(&mut multisig, &signers[1], &DATA).unwrap(), 1);
+        assert_eq!({ multisig.queued_eta }, 1_100);
+        assert_eq!({ multisig.signed_weight }, 0);
+        assert_eq!({ multisig.nonce }, 0);
+
+        // only one instruction can be queued
+        assert_eq!(sign(&mut multisig, &signers[0], &OTHER_DATA).unwrap(), 1);
+        assert_error(
+            sign(&mut multisig, &signers[1], &OTHER_DATA),
+            PerpetualsError::MultisigInstructionQueued,
+        );
+
+        set_time(1_099);
+        assert_error(
+            sign(&mut multisig, &signers[0], &DATA),
+            PerpetualsError::MultisigTimelockNotExpired,
+        );
+
+        // any signer executes the queued instruction after the eta
+        set_time(1_100);
+        assert_eq!(sign(&mut multisig, &signers[1], &DATA).unwrap(), 0);
+        assert_eq!({ multisig.queued_eta }, 0);
+        assert_eq!({ multisig.nonce }, 1);
+    }
+
+    #[test]
+    fn test_timelock_single_signer() {
+        let (mut multisig, signers) = get_multisig(&[1], 1, 100);
+        set_time(1_000);
+
+        assert_eq!(sign(&mut multisig, &signers[0], &DATA).unwrap(), 1);
+        assert_eq!({ multisig.nonce }, 0);
+
+        set_time(1_100);
+        assert_eq!(sign(&mut multisig, &signers[0], &DATA).unwrap(), 0);
+        assert_eq!({ multisig.nonce }, 1);
+    }
+
+    #[test]
+    fn test_cancel_queued_instruction() {
+        let (mut multisig, signers) = get_multisig(&[1, 1], 2, 100);
+        set_time(1_000);
+
+        sign(&mut multisig, &signers[0], &DATA).unwrap();
+        assert_eq!(sign(&mut multisig, &signers[1], &DATA).unwrap(), 1);
+
+        // the cancel instruction executes on quorum without waiting for the timelock
+        let cancel_data = [AdminInstruction::CancelQueuedInstruction as u8];
+        assert_eq!(
+            multisig
+                .sign_multisig_without_timelock(&signers[0], &[], &cancel_data, None)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            multisig
+                .sign_multisig_without_timelock(&signers[1], &[], &cancel_data, None)
+                .unwrap(),
+            0
+        );
+        multisig.clear_queue();
+        assert_eq!({ multisig.nonce }, 1);
+
+        // the cancelled instruction has to be signed again
+        set_time(1_100);
+        assert_eq!(sign(&mut multisig, &signers[0], &DATA).unwrap(), 1);
+        assert_eq!({ multisig.queued_eta }, 0);
+    }
+
+    #[test]
+    fn test_proposal() {
+        let (mut multisig, signers) = get_multisig(&[2, 1, 1], 3, 0);
+        let mut proposal = get_proposal(5, 2_000);
+        set_time(1_000);
+
+        assert_eq!(
+            sign_proposal(&mut multisig, &signers[1], &DATA, &mut proposal).unwrap(),
+            2
+        );
+        assert!(proposal.is_bound());
+        assert_eq!(proposal.signers_version, 1);
+        assert_error(
+            sign_proposal(&mut multisig, &signers[1], &DATA, &mut proposal),
+            PerpetualsError::MultisigAlreadySigned,
+        );
+        assert_error(
+            sign_proposal(&mut multisig, &signers[0], &OTHER_DATA, &mut proposal),
+            PerpetualsError::ProposalInstructionMismatch,
+        );
+
+        assert_eq!(
+            sign_proposal(&mut multisig, &signers[0], &DATA, &mut proposal).unwrap(),
+            0
+        );
+        assert!(proposal.executed);
+        assert_error(
+            sign_proposal(&mut multisig, &signers[2], &DATA, &mut proposal),
+            PerpetualsError::MultisigAlreadyExecuted,
+        );
+
+        // proposals don't use the multisig nonce or pending signatures
+        assert_eq!({ multisig.nonce }, 0);
+        assert_eq!({ multisig.signed_weight }, 0);
+    }
+
+    #[test]
+    fn test_proposal_timelock() {
+        let (mut multisig, signers) = get_multisig(&[1, 1], 2, 100);
+        let mut proposal = get_proposal(0, 2_000);
+        set_time(1_000);
+
+        sign_proposal(&mut multisig, &signers[0], &DATA, &mut proposal).unwrap();
+        assert_eq!(
+            sign_proposal(&mut multisig, &signers[1], &DATA, &mut proposal).unwrap(),
+            1
+        );
+        assert_eq!(proposal.queued_eta, 1_100);
+
+        set_time(1_099);
+        assert_error(
+            sign_proposal(&mut multisig, &signers[0], &DATA, &mut proposal),
+            PerpetualsError::MultisigTimelockNotExpired,
+        );
+
+        set_time(1_100);
+        assert_eq!(
+            sign_proposal(&mut multisig, &signers[0], &DATA, &mut proposal).unwrap(),
+            0
+        );
+        assert!(proposal.executed);
+
+        // cancelling a proposal executes without the timelock
+        let mut proposal = get_proposal(1, 2_000);
+        let cancel_data = [AdminInstruction::CancelQueuedInstruction as u8];
+        multisig
+            .sign_multisig_without_timelock(&signers[0], &[], &cancel_data, Some(&mut proposal))
+            .unwrap();
+        assert_eq!(
+            multisig
+                .sign_multisig_without_timelock(&signers[1], &[], &cancel_data, Some(&mut proposal))
+                .unwrap(),
+            0
+        );
+        assert_eq!(proposal.queued_eta, 0);
+        assert!(proposal.executed);
+    }
+
+    #[test]
+    fn test_proposal_signers_changed() {
+        let (mut multisig, signers) = get_multisig(&[1, 1], 2, 0);
+        let mut proposal = get_proposal(0, 2_000);
+        set_time(1_000);
+
+        sign_proposal(&mut multisig, &signers[0], &DATA, &mut proposal).unwrap();
+        multisig.set_signers(&signers, &[1, 1], 2).unwrap();
+        assert_error(
+            sign_proposal(&mut multisig, &signers[1], &DATA, &mut proposal),
+            PerpetualsError::ProposalSignersChanged,
+        );
+
+        // proposals are bound on the first signature
+        let mut proposal = get_proposal(1, 2_000);
+        sign_proposal(&mut multisig, &signers[0], &DATA, &mut proposal).unwrap();
+        assert_eq!(proposal.signers_version, 2);
+    }
+
+    #[test]
+    fn test_proposal_expired() {
+        let (mut multisig, signers) = get_multisig(&[1, 1], 2, 0);
+        let mut proposal = get_proposal(0, 1_100);
+        set_time(1_000);
+
+        sign_proposal(&mut multisig, &signers[0], &DATA, &mut proposal).unwrap();
+        set_time(1_100);
+        assert_error(
+            sign_proposal(&mut multisig, &signers[1], &DATA, &mut proposal),
+            PerpetualsError::ProposalExpired,
+        );
+
+        let mut proposal = get_proposal(1, 2_000);
+        proposal.cancel(1_100);
+        assert_error(
+            sign_proposal(&mut multisig, &signers[0], &DATA, &mut proposal),
+            PerpetualsError::ProposalExpired,
+        );
+    }
+
+    #[test]
+    fn test_from_deprecated() {
+        let mut deprecated = DeprecatedMultisig {
+            num_signers: 2,
+            num_signed: 1,
+            min_signatures: 2,
+            instruction_accounts_len: 0,
+            instruction_data_len: 3,
+            instruction_hash: 12_345,
+            bump: 254,
+            ..Default::default()
+        };
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        deprecated.signers[..2].copy_from_slice(&keys);
+        deprecated.signed[0] = 1;
+
+        let mut multisig = Multisig::from_deprecated(&deprecated);
+        assert_eq!({ multisig.num_signers }, 2);
+        assert_eq!({ multisig.min_weight }, 2);
+        assert_eq!({ multisig.bump }, 254);
+        assert_eq!(
+            { multisig.signers }[..3],
+            [keys[0], keys[1], Pubkey::default()]
+        );
+        assert_eq!({ multisig.weights }[..3], [1, 1, 0]);
+        assert_eq!({ multisig.signed }, [0; Multisig::MAX_SIGNERS]);
+        assert_eq!({ multisig.signed_weight }, 0);
+        assert_eq!({ multisig.instruction_data_len }, 0);
+        assert_eq!({ multisig.nonce }, 0);
+
+        // the converted signers sign with a weight of 1
+        let signers = [get_signer(keys[0], true), get_signer(keys[1], true)];
+        assert_eq!(sign(&mut multisig, &signers[0], &DATA).unwrap(), 1);
+        assert_eq!(sign(&mut multisig, &signers[1], &DATA).unwrap(), 0);
+    }
+}
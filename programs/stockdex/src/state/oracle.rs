@@ -8,11 +8,16 @@ const ORACLE_EXPONENT_SCALE: i32 = -9;
 const ORACLE_PRICE_SCALE: u64 = 1_000_000_000;
 const ORACLE_MAX_PRICE: u64 = (1 << 28) - 1;
 
+// Pyth pull oracle receiver program, owner of PriceUpdateV2 accounts
+pub const PYTH_RECEIVER_PROGRAM_ID: Pubkey =
+    pubkey!("rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ");
+
 #[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Debug)]
 pub enum OracleType {
     None,
     Custom,
     Pyth,
+    PythPull,
 }
 
 impl Default for OracleType {
@@ -25,6 +30,8 @@ impl Default for OracleType {
 pub struct OraclePrice {
     pub price: u64,
     pub exponent: i32,
+    // confidence interval reported by the oracle, same exponent as price
+    pub conf: u64,
 }
 
 #[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
@@ -36,6 +43,15 @@ pub struct OracleParams {
     pub oracle_authority: Pubkey,
     pub max_price_error: u64,
     pub max_price_age_sec: u32,
+
+    // Optional fallback source, used when the primary price is stale. When both prices are
+    // fresh they must not differ by more than max_divergence (in BPS).
+    pub secondary_oracle_account: Pubkey,
+    pub secondary_oracle_type: OracleType,
+    pub max_divergence: u64,
+
+    // Pyth pull oracle feed id, checked against PriceUpdateV2 accounts
+    pub feed_id: [u8; 32],
 }
 
 #[account]
@@ -48,6 +64,52 @@ pub struct CustomOracle {
     pub publish_time: i64,
 }
 
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Debug)]
+pub enum VerificationLevel {
+    Partial { num_signatures: u8 },
+    Full,
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Debug)]
+pub struct PriceFeedMessage {
+    pub feed_id: [u8; 32],
+    pub price: i64,
+    pub conf: u64,
+    pub exponent: i32,
+    pub publish_time: i64,
+    pub prev_publish_time: i64,
+    pub ema_price: i64,
+    pub ema_conf: u64,
+}
+
+// Layout of the Pyth receiver PriceUpdateV2 account, parsed locally to avoid
+// depending on the receiver sdk
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Debug)]
+pub struct PriceUpdateV2 {
+    pub write_authority: Pubkey,
+    pub verification_level: VerificationLevel,
+    pub price_message: PriceFeedMessage,
+    pub posted_slot: u64,
+}
+
+impl PriceUpdateV2 {
+    pub const DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+
+    pub fn try_from_account_info(account_info: &AccountInfo) -> Result<Self> {
+        require_keys_eq!(
+            *account_info.owner,
+            PYTH_RECEIVER_PROGRAM_ID,
+            PerpetualsError::InvalidOracleAccount
+        );
+        let data = account_info.try_borrow_data()?;
+        require!(
+            data.len() > 8 && data[..8] == Self::DISCRIMINATOR,
+            PerpetualsError::InvalidOracleAccount
+        );
+        Self::deserialize(&mut &data[8..]).map_err(|_| PerpetualsError::InvalidOracleAccount.into())
+    }
+}
+
 impl CustomOracle {
     pub const LEN: usize = 8 + std::mem::size_of::<CustomOracle>();
 
@@ -82,23 +144,78 @@ impl PartialOrd for OraclePrice {
 #[allow(dead_code)]
 impl OraclePrice {
     pub fn new(price: u64, exponent: i32) -> Self {
-        Self { price, exponent }
+        Self {
+            price,
+            exponent,
+            conf: 0,
+        }
     }
 
     pub fn new_from_token(amount_and_decimals: (u64, u8)) -> Self {
         Self {
             price: amount_and_decimals.0,
             exponent: -(amount_and_decimals.1 as i32),
+            conf: 0,
         }
     }
 
     pub fn new_from_oracle<'info>(
         oracle_account: &AccountInfo<'info>,
+        secondary_oracle_account: Option<&AccountInfo<'info>>,
         oracle_params: &OracleParams,
         current_time: i64,
         use_ema: bool,
     ) -> Result<Self> {
-        match oracle_params.oracle_type {
+        let primary_price = Self::get_price(
+            oracle_account,
+            oracle_params.oracle_type,
+            oracle_params,
+            current_time,
+            use_ema,
+        );
+        if oracle_params.secondary_oracle_type == OracleType::None {
+            return primary_price;
+        }
+
+        let Some(secondary_oracle_account) = secondary_oracle_account else {
+            msg!("Error: Secondary oracle account is missing");
+            return err!(PerpetualsError::InvalidOracleAccount);
+        };
+        require_keys_eq!(
+            secondary_oracle_account.key(),
+            oracle_params.secondary_oracle_account,
+            PerpetualsError::InvalidOracleAccount
+        );
+        let secondary_price = Self::get_price(
+            secondary_oracle_account,
+            oracle_params.secondary_oracle_type,
+            oracle_params,
+            current_time,
+            use_ema,
+        );
+
+        match (primary_price, secondary_price) {
+            (Ok(primary_price), Ok(secondary_price)) => {
+                primary_price.check_divergence(&secondary_price, oracle_params.max_divergence)?;
+                Ok(primary_price)
+            }
+            (Ok(primary_price), Err(_)) => Ok(primary_price),
+            (Err(err), Ok(secondary_price)) if Self::is_stale_price_error(&err) => {
+                msg!("Primary oracle price is stale, using secondary oracle");
+                Ok(secondary_price)
+            }
+            (Err(err), _) => Err(err),
+        }
+    }
+
+    fn get_price<'info>(
+        oracle_account: &AccountInfo<'info>,
+        oracle_type: OracleType,
+        oracle_params: &OracleParams,
+        current_time: i64,
+        use_ema: bool,
+    ) -> Result<Self> {
+        match oracle_type {
             OracleType::Custom => Self::get_custom_price(
                 oracle_account,
                 oracle_params.max_price_error,
@@ -113,10 +230,70 @@ impl OraclePrice {
                 current_time,
                 use_ema,
             ),
+            OracleType::PythPull => Self::get_pyth_pull_price(
+                oracle_account,
+                &oracle_params.feed_id,
+                oracle_params.max_price_error,
+                oracle_params.max_price_age_sec,
+                current_time,
+                use_ema,
+            ),
             _ => err!(PerpetualsError::UnsupportedOracle),
         }
     }
 
+    // Returns publish time of the primary oracle price
+    pub fn get_publish_time(
+        oracle_account: &AccountInfo,
+        oracle_params: &OracleParams,
+    ) -> Result<i64> {
+        require!(
+            !Perpetuals::is_empty_account(oracle_account)?,
+            PerpetualsError::InvalidOracleAccount
+        );
+        match oracle_params.oracle_type {
+            OracleType::Custom => {
+                let data = &oracle_account.try_borrow_data()?;
+                let oracle_acc = CustomOracle::try_deserialize(&mut data.as_ref())?;
+                Ok(oracle_acc.publish_time)
+            }
+            OracleType::Pyth => {
+                let price_feed = pyth_sdk_solana::load_price_feed_from_account_info(oracle_account)
+                    .map_err(|_| PerpetualsError::InvalidOracleAccount)?;
+                Ok(price_feed.get_price_unchecked().publish_time)
+            }
+            OracleType::PythPull => Ok(PriceUpdateV2::try_from_account_info(oracle_account)?
+                .price_message
+                .publish_time),
+            _ => err!(PerpetualsError::UnsupportedOracle),
+        }
+    }
+
+    fn is_stale_price_error(err: &Error) -> bool {
+        matches!(err, Error::AnchorError(anchor_error)
+            if anchor_error.error_code_number == u32::from(PerpetualsError::StaleOraclePrice))
+    }
+
+    // Rejects prices from two fresh sources that differ by more than max_divergence (in BPS
+    // of this price)
+    pub fn check_divergence(&self, other: &OraclePrice, max_divergence: u64) -> Result<()> {
+        let other = other.scale_to_exponent(self.exponent)?;
+        let diff = if self.price > other.price {
+            math::checked_sub(self.price, other.price)?
+        } else {
+            math::checked_sub(other.price, self.price)?
+        };
+        let divergence = math::checked_div(
+            math::checked_mul(diff as u128, Perpetuals::BPS_POWER)?,
+            self.price as u128,
+        )?;
+        if divergence > max_divergence as u128 {
+            msg!("Error: Oracle prices diverge by {} bps", divergence);
+            return err!(PerpetualsError::OraclePriceDivergence);
+        }
+        Ok(())
+    }
+
     // Converts token amount to USD with implied USD_DECIMALS decimals using oracle price
     pub fn get_asset_amount_usd(&self, token_amount: u64, token_decimals: u8) -> Result<u64> {
         if token_amount == 0 || self.price == 0 {
@@ -148,16 +325,19 @@ impl OraclePrice {
     /// Returns price with mantissa normalized to be less than ORACLE_MAX_PRICE
     pub fn normalize(&self) -> Result<OraclePrice> {
         let mut p = self.price;
+        let mut c = self.conf;
         let mut e = self.exponent;
 
         while p > ORACLE_MAX_PRICE {
             p = math::checked_div(p, 10)?;
+            c = math::checked_div(c, 10)?;
             e = math::checked_add(e, 1)?;
         }
 
         Ok(OraclePrice {
             price: p,
             exponent: e,
+            conf: c,
         })
     }
 
@@ -174,6 +354,7 @@ impl OraclePrice {
                 math::checked_add(base.exponent, ORACLE_EXPONENT_SCALE)?,
                 other.exponent,
             )?,
+            conf: 0,
         })
     }
 
@@ -181,6 +362,7 @@ impl OraclePrice {
         Ok(OraclePrice {
             price: math::checked_mul(self.price, other.price)?,
             exponent: math::checked_add(self.exponent, other.exponent)?,
+            conf: 0,
         })
     }
 
@@ -193,15 +375,29 @@ impl OraclePrice {
             Ok(OraclePrice {
                 price: math::checked_div(self.price, math::checked_pow(10, delta as usize)?)?,
                 exponent: target_exponent,
+                conf: math::checked_div(self.conf, math::checked_pow(10, delta as usize)?)?,
             })
         } else {
             Ok(OraclePrice {
                 price: math::checked_mul(self.price, math::checked_pow(10, (-delta) as usize)?)?,
                 exponent: target_exponent,
+                conf: math::checked_mul(self.conf, math::checked_pow(10, (-delta) as usize)?)?,
             })
         }
     }
 
+    // Returns confidence interval as a share of price with implied BPS_DECIMALS decimals,
+    // multiplied by conf_mult (also BPS_DECIMALS)
+    pub fn get_conf_spread(&self, conf_mult: u64) -> Result<u64> {
+        if conf_mult == 0 || self.price == 0 {
+            return Ok(0);
+        }
+        math::checked_as_u64(math::checked_div(
+            math::checked_mul(self.conf as u128, conf_mult as u128)?,
+            self.price as u128,
+        )?)
+    }
+
     pub fn checked_as_f64(&self) -> Result<f64> {
         math::checked_float_mul(
             math::checked_as_f64(self.price)?,
@@ -219,6 +415,7 @@ impl OraclePrice {
                     return Ok(OraclePrice {
                         price: 1000000u64,
                         exponent: -6,
+                        conf: 0,
                     });
                 }
             }
@@ -227,6 +424,7 @@ impl OraclePrice {
                 Ok(OraclePrice {
                     price: one_usd,
                     exponent: min_price.exponent,
+                    conf: min_price.conf,
                 })
             } else {
                 Ok(*min_price)
@@ -277,6 +475,7 @@ impl OraclePrice {
             // price is i64 and > 0 per check above
             price,
             exponent: oracle_acc.expo,
+            conf: oracle_acc.conf,
         })
     }
 
@@ -319,6 +518,276 @@ impl OraclePrice {
             // price is i64 and > 0 per check above
             price: pyth_price.price as u64,
             exponent: pyth_price.expo,
+            conf: pyth_price.conf,
         })
     }
-}
\ No newline at end of file
+
+    fn get_pyth_pull_price<'info>(
+        price_update_info: &AccountInfo<'info>,
+        feed_id: &[u8; 32],
+        max_price_error: u64,
+        max_price_age_sec: u32,
+        current_time: i64,
+        use_ema: bool,
+    ) -> Result<OraclePrice> {
+        require!(
+            !Perpetuals::is_empty_account(price_update_info)?,
+            PerpetualsError::InvalidOracleAccount
+        );
+        let price_update = PriceUpdateV2::try_from_account_info(price_update_info)?;
+
+        if price_update.verification_level != VerificationLevel::Full {
+            msg!("Error: Pyth price update is not fully verified");
+            return err!(PerpetualsError::InvalidOracleAccount);
+        }
+
+        let message = price_update.price_message;
+        if message.feed_id != *feed_id {
+            msg!("Error: Pyth price update feed id mismatch");
+            return err!(PerpetualsError::InvalidOracleAccount);
+        }
+
+        let last_update_age_sec = math::checked_sub(current_time, message.publish_time)?;
+        if last_update_age_sec > max_price_age_sec as i64 {
+            msg!("Error: Pyth pull oracle price is stale");
+            return err!(PerpetualsError::StaleOraclePrice);
+        }
+
+        let (price, conf) = if use_ema {
+            (message.ema_price, message.ema_conf)
+        } else {
+            (message.price, message.conf)
+        };
+
+        if price <= 0
+            || math::checked_div(
+                math::checked_mul(conf as u128, Perpetuals::BPS_POWER)?,
+                price as u128,
+            )? > max_price_error as u128
+        {
+            msg!("Error: Pyth pull oracle price is out of bounds");
+            return err!(PerpetualsError::InvalidOraclePrice);
+        }
+
+        Ok(OraclePrice {
+            // price is i64 and > 0 per check above
+            price: price as u64,
+            exponent: message.exponent,
+            conf,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FEED_ID: [u8; 32] = [7; 32];
+    const MAX_PRICE_ERROR: u64 = 100;
+    const MAX_PRICE_AGE_SEC: u32 = 60;
+    const PUBLISH_TIME: i64 = 1_700_000_000;
+
+    fn get_price_update() -> PriceUpdateV2 {
+        PriceUpdateV2 {
+            write_authority: Pubkey::new_unique(),
+            verification_level: VerificationLevel::Full,
+            price_message: PriceFeedMessage {
+                feed_id: FEED_ID,
+                price: 150_000_000,
+                conf: 100_000,
+                exponent: -8,
+                publish_time: PUBLISH_TIME,
+                prev_publish_time: PUBLISH_TIME - 1,
+                ema_price: 148_000_000,
+                ema_conf: 120_000,
+            },
+            posted_slot: 1,
+        }
+    }
+
+    fn get_account_data(price_update: &PriceUpdateV2) -> Vec<u8> {
+        let mut data = PriceUpdateV2::DISCRIMINATOR.to_vec();
+        price_update.serialize(&mut data).unwrap();
+        data
+    }
+
+    fn get_pull_price(
+        price_update: &PriceUpdateV2,
+        current_time: i64,
+        use_ema: bool,
+    ) -> Result<OraclePrice> {
+        let key = Pubkey::new_unique();
+        let mut lamports = 1_000_000;
+        let mut data = get_account_data(price_update);
+        let account_info = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &PYTH_RECEIVER_PROGRAM_ID,
+            false,
+            0,
+        );
+        OraclePrice::get_pyth_pull_price(
+            &account_info,
+            &FEED_ID,
+            MAX_PRICE_ERROR,
+            MAX_PRICE_AGE_SEC,
+            current_time,
+            use_ema,
+        )
+    }
+
+    fn assert_error(result: Result<OraclePrice>, expected: PerpetualsError) {
+        match result {
+            Err(Error::AnchorError(err)) => {
+                assert_eq!(err.error_code_number, u32::from(expected))
+            }
+            other => panic!("expected {:?}, got {:?}", expected, other),
+        }
+    }
+
+    #[test]
+    fn test_try_from_account_info() {
+        let price_update = get_price_update();
+        let key = Pubkey::new_unique();
+        let mut lamports = 1_000_000;
+        let mut data = get_account_data(&price_update);
+        let account_info = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &PYTH_RECEIVER_PROGRAM_ID,
+            false,
+            0,
+        );
+        assert_eq!(
+            PriceUpdateV2::try_from_account_info(&account_info).unwrap(),
+            price_update
+        );
+    }
+
+    #[test]
+    fn test_try_from_account_info_invalid_account() {
+        let price_update = get_price_update();
+        let key = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let mut lamports = 1_000_000;
+        let mut data = get_account_data(&price_update);
+        let account_info = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        assert!(PriceUpdateV2::try_from_account_info(&account_info).is_err());
+
+        let mut lamports = 1_000_000;
+        let mut data = get_account_data(&price_update);
+        data[0] ^= 1;
+        let account_info = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &PYTH_RECEIVER_PROGRAM_ID,
+            false,
+            0,
+        );
+        assert!(PriceUpdateV2::try_from_account_info(&account_info).is_err());
+    }
+
+    #[test]
+    fn test_get_pyth_pull_price() {
+        let price_update = get_price_update();
+        assert_eq!(
+            get_pull_price(&price_update, PUBLISH_TIME + 10, false).unwrap(),
+            OraclePrice {
+                price: 150_000_000,
+                exponent: -8,
+                conf: 100_000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_get_pyth_pull_price_ema() {
+        let mut price_update = get_price_update();
+        assert_eq!(
+            get_pull_price(&price_update, PUBLISH_TIME, true).unwrap(),
+            OraclePrice {
+                price: 148_000_000,
+                exponent: -8,
+                conf: 120_000,
+            }
+        );
+
+        // the confidence check applies to the price that is used
+        price_update.price_message.ema_conf = 10_000_000;
+        assert!(get_pull_price(&price_update, PUBLISH_TIME, false).is_ok());
+        assert_error(
+            get_pull_price(&price_update, PUBLISH_TIME, true),
+            PerpetualsError::InvalidOraclePrice,
+        );
+    }
+
+    #[test]
+    fn test_get_pyth_pull_price_feed_id_mismatch() {
+        let mut price_update = get_price_update();
+        price_update.price_message.feed_id = [8; 32];
+        assert_error(
+            get_pull_price(&price_update, PUBLISH_TIME, false),
+            PerpetualsError::InvalidOracleAccount,
+        );
+    }
+
+    #[test]
+    fn test_get_pyth_pull_price_partial_verification() {
+        let mut price_update = get_price_update();
+        price_update.verification_level = VerificationLevel::Partial { num_signatures: 5 };
+        assert_error(
+            get_pull_price(&price_update, PUBLISH_TIME, false),
+            PerpetualsError::InvalidOracleAccount,
+        );
+    }
+
+    #[test]
+    fn test_get_pyth_pull_price_stale() {
+        let price_update = get_price_update();
+        let max_age_time = PUBLISH_TIME + MAX_PRICE_AGE_SEC as i64;
+        assert!(get_pull_price(&price_update, max_age_time, false).is_ok());
+        assert_error(
+            get_pull_price(&price_update, max_age_time + 1, false),
+            PerpetualsError::StaleOraclePrice,
+        );
+    }
+
+    #[test]
+    fn test_get_pyth_pull_price_max_price_error() {
+        let mut price_update = get_price_update();
+        // conf at exactly max_price_error is accepted
+        price_update.price_message.conf = 1_500_000;
+        assert!(get_pull_price(&price_update, PUBLISH_TIME, false).is_ok());
+
+        price_update.price_message.conf = 1_515_000;
+        assert_error(
+            get_pull_price(&price_update, PUBLISH_TIME, false),
+            PerpetualsError::InvalidOraclePrice,
+        );
+
+        price_update.price_message.conf = 0;
+        price_update.price_message.price = 0;
+        assert_error(
+            get_pull_price(&price_update, PUBLISH_TIME, false),
+            PerpetualsError::InvalidOraclePrice,
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use {
+    crate::{
+        error::PerpetualsError,
+        math,
+        state::{custody::Custody, oracle::OraclePrice},
+    },
+    anchor_lang::prelude::*,
+};
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct PriceSample {
+    pub price: u64,
+    pub exponent: i32,
+    pub time: i64,
+}
+
+// ring buffer of oracle prices used to compute the custody TWAP
+#[account]
+#[derive(Default, Debug)]
+pub struct OracleHistory {
+    pub custody: Pubkey,
+    pub samples: [PriceSample; OracleHistory::MAX_SAMPLES],
+    // index of the next sample to write
+    pub head: u32,
+    pub count: u32,
+
+    pub bump: u8,
+}
+
+impl OracleHistory {
+    pub const MAX_SAMPLES: usize = 32;
+    pub const LEN: usize = 8 + std::mem::size_of::<OracleHistory>();
+
+    pub fn get_last_sample(&self) -> Option<&PriceSample> {
+        if self.count == 0 {
+            return None;
+        }
+        let idx = (self.head as usize + Self::MAX_SAMPLES - 1) % Self::MAX_SAMPLES;
+        Some(&self.samples[idx])
+    }
+
+    // records a sample, spaced so that the buffer always covers the whole TWAP window
+    pub fn add_sample(
+        &mut self,
+        price: &OraclePrice,
+        window_sec: u32,
+        curtime: i64,
+    ) -> Result<bool> {
+        if let Some(last_sample) = self.get_last_sample() {
+            let min_spacing = std::cmp::max(window_sec as i64 / Self::MAX_SAMPLES as i64, 1);
+            if curtime < math::checked_add(last_sample.time, min_spacing)? {
+                return Ok(false);
+            }
+        }
+
+        self.samples[self.head as usize] = PriceSample {
+            price: price.price,
+            exponent: price.exponent,
+            time: curtime,
+        };
+        self.head = ((self.head as usize + 1) % Self::MAX_SAMPLES) as u32;
+        if (self.count as usize) < Self::MAX_SAMPLES {
+            self.count = math::checked_add(self.count, 1)?;
+        }
+
+        Ok(true)
+    }
+
+    // records the oracle price and refreshes the custody TWAP
+    pub fn update_custody_twap(
+        &mut self,
+        custody: &mut Custody,
+        token_price: &OraclePrice,
+        curtime: i64,
+    ) -> Result<()> {
+        let window_sec = custody.pricing.twap_window_sec;
+        self.add_sample(token_price, window_sec, curtime)?;
+
+        let twap = self.get_twap(window_sec, curtime)?;
+        msg!("TWAP: {}", twap.price);
+
+        custody.twap.price = twap.price;
+        custody.twap.exponent = twap.exponent;
+        custody.twap.update_time = curtime;
+
+        Ok(())
+    }
+
+    // time-weighted average of the recorded prices over the last window_sec seconds,
+    // each sample is weighted by the time until the next sample (or curtime)
+    pub fn get_twap(&self, window_sec: u32, curtime: i64) -> Result<OraclePrice> {
+        let Some(last_sample) = self.get_last_sample() else {
+            return err!(PerpetualsError::InvalidOracleState);
+        };
+        let exponent = last_sample.exponent;
+        let window_start = math::checked_sub(curtime, window_sec as i64)?;
+
+        let mut weighted_sum: u128 = 0;
+        let mut total_time: u128 = 0;
+        let mut end_time = curtime;
+        for i in 0..self.count as usize {
+            let idx = (self.head as usize + Self::MAX_SAMPLES - 1 - i) % Self::MAX_SAMPLES;
+            let sample = &self.samples[idx];
+            let start_time = std::cmp::max(sample.time, window_start);
+            if end_time > start_time {
+                let duration = math::checked_sub(end_time, start_time)? as u128;
+                let price = OraclePrice::new(sample.price, sample.exponent)
+                    .scale_to_exponent(exponent)?
+                    .price;
+                weighted_sum =
+                    math::checked_add(weighted_sum, math::checked_mul(price as u128, duration)?)?;
+                total_time = math::checked_add(total_time, duration)?;
+            }
+            if sample.time <= window_start {
+                break;
+            }
+            end_time = sample.time;
+        }
+
+        if total_time == 0 {
+            return Ok(OraclePrice::new(last_sample.price, exponent));
+        }
+
+        Ok(OraclePrice::new(
+            math::checked_as_u64(math::checked_div(weighted_sum, total_time)?)?,
+            exponent,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn add_sample(
+        oracle_history: &mut OracleHistory,
+        price: u64,
+        window_sec: u32,
+        time: i64,
+    ) -> bool {
+        oracle_history
+            .add_sample(&OraclePrice::new(price, -8), window_sec, time)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_get_twap_empty() {
+        let oracle_history = OracleHistory::default();
+        assert!(oracle_history.get_last_sample().is_none());
+        assert!(oracle_history.get_twap(100, 1_000).is_err());
+    }
+
+    #[test]
+    fn test_add_sample_spacing() {
+        let mut oracle_history = OracleHistory::default();
+
+        // window / MAX_SAMPLES = 10 seconds between samples
+        assert!(add_sample(&mut oracle_history, 100, 320, 1_000));
+        assert!(!add_sample(&mut oracle_history, 200, 320, 1_005));
+        assert!(!add_sample(&mut oracle_history, 200, 320, 1_009));
+        assert!(add_sample(&mut oracle_history, 300, 320, 1_010));
+        assert_eq!(oracle_history.count, 2);
+        assert_eq!(oracle_history.get_last_sample().unwrap().price, 300);
+
+        // short windows still need one second between samples
+        assert!(!add_sample(&mut oracle_history, 400, 10, 1_010));
+        assert!(add_sample(&mut oracle_history, 400, 10, 1_011));
+        assert_eq!(oracle_history.count, 3);
+    }
+
+    #[test]
+    fn test_buffer_wrap() {
+        let mut oracle_history = OracleHistory::default();
+        let num_samples = OracleHistory::MAX_SAMPLES as i64 + 8;
+        for time in 1..=num_samples {
+            assert!(add_sample(&mut oracle_history, time as u64, 32, time));
+        }
+
+        assert_eq!(oracle_history.count as usize, OracleHistory::MAX_SAMPLES);
+        assert_eq!(oracle_history.head, 8);
+        assert_eq!(oracle_history.get_last_sample().unwrap().time, num_samples);
+
+        // the 8 oldest samples were overwritten, samples 9..=39 are weighted one second each
+        // and the last one has no weight at curtime
+        let twap = oracle_history.get_twap(1_000, num_samples).unwrap();
+        assert_eq!(twap, OraclePrice::new((9..=39).sum::<u64>() / 31, -8));
+    }
+
+    #[test]
+    fn test_twap_window_clipping() {
+        let mut oracle_history = OracleHistory::default();
+        assert!(add_sample(&mut oracle_history, 100, 100, 0));
+        assert!(add_sample(&mut oracle_history, 200, 100, 100));
+
+        // the first sample is only weighted from the window start at 50
+        let twap = oracle_history.get_twap(100, 150).unwrap();
+        assert_eq!(twap, OraclePrice::new(150, -8));
+
+        // samples older than the window have no weight
+        let twap = oracle_history.get_twap(40, 150).unwrap();
+        assert_eq!(twap, OraclePrice::new(200, -8));
+    }
+
+    #[test]
+    fn test_twap_zero_total_time() {
+        let mut oracle_history = OracleHistory::default();
+        assert!(add_sample(&mut oracle_history, 100, 100, 1_000));
+
+        let twap = oracle_history.get_twap(100, 1_000).unwrap();
+        assert_eq!(twap, OraclePrice::new(100, -8));
+    }
+
+    #[test]
+    fn test_twap_exponent() {
+        let mut oracle_history = OracleHistory::default();
+        oracle_history
+            .add_sample(&OraclePrice::new(1_000_000, -6), 100, 0)
+            .unwrap();
+        oracle_history
+            .add_sample(&OraclePrice::new(300_000_000, -8), 100, 50)
+            .unwrap();
+
+        // older samples are scaled to the exponent of the last one
+        let twap = oracle_history.get_twap(100, 100).unwrap();
+        assert_eq!(twap, OraclePrice::new(200_000_000, -8));
+    }
+}
@@ -0,0 +1,39 @@
+use {crate::state::position::Side, anchor_lang::prelude::*};
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub enum OrderType {
+    Open,
+    Close,
+    #[default]
+    None,
+}
+
+// pending order for a custody in delayed order mode, filled by a keeper with an oracle price
+// published within the custody order_fill_window_sec after request_time
+#[account]
+#[derive(Default, Debug)]
+pub struct OrderRequest {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    pub collateral_custody: Pubkey,
+    pub position: Pubkey,
+    pub order_type: OrderType,
+    pub side: Side,
+    // position index, see PositionCounter
+    pub index: u32,
+    // worst acceptable entry or exit price with implied PRICE_DECIMALS decimals
+    pub price: u64,
+    pub collateral: u64,
+    pub size: u64,
+    // collateral tokens held in the order escrow, collateral plus the fee budget
+    pub escrow_amount: u64,
+    pub request_time: i64,
+    pub expiry_time: i64,
+
+    pub bump: u8,
+}
+
+impl OrderRequest {
+    pub const LEN: usize = 8 + std::mem::size_of::<OrderRequest>();
+}
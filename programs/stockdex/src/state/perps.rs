@@ -5,6 +5,7 @@ use {anchor_lang::{prelude::*}, anchor_spl::token::{Burn, MintTo, Transfer}};
 pub struct PriceAndFee {
     pub price: u64,
     pub fee: u64,
+    pub price_impact: u64,
 }
 
 #[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
@@ -18,6 +19,7 @@ pub struct NewPositionPricesAndFee {
     pub entry_price: u64,
     pub liquidation_price: u64,
     pub fee: u64,
+    pub price_impact: u64,
 }
 
 #[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
@@ -43,6 +45,7 @@ pub struct Permissions {
     pub allow_pnl_withdrawal: bool,
     pub allow_collateral_withdrawal: bool,
     pub allow_size_change: bool,
+    pub allow_transfer_position: bool,
 }
 
 #[account]
@@ -50,6 +53,8 @@ pub struct Permissions {
 pub struct Perpetuals {
     pub permissions: Permissions,
     pub pools: Vec<Pubkey>,
+    // can only disable permissions, Pubkey::default() if not set
+    pub guardian: Pubkey,
 
     pub transfer_authority_bump: u8,
     pub perpetuals_bump: u8,
@@ -19,6 +19,8 @@ pub enum AumCalcMode {
     Max,
     Last,
     EMA,
+    // program-maintained TWAP for custodies that enable it, EMA otherwise
+    Twap,
 }
 
 #[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
@@ -28,6 +30,31 @@ pub struct TokenRatios {
     pub max: u64,
 }
 
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct FeeTier {
+    // 30-day traded notional required for the tier
+    pub min_volume_usd: u64,
+    // discount on open and close position fees with implied BPS_DECIMALS decimals
+    pub discount: u64,
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct WithdrawalParams {
+    // min delay after a deposit and between a withdrawal request and its execution,
+    // 0 allows single-step remove_liquidity
+    pub cooldown_sec: u32,
+    pub epoch_sec: u32,
+    // max withdrawals per epoch as a share of AUM with implied BPS_DECIMALS decimals, 0 to disable
+    pub max_epoch_outflow: u64,
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct WithdrawalState {
+    pub epoch_start_time: i64,
+    pub epoch_start_aum_usd: u128,
+    pub epoch_outflow_usd: u64,
+}
+
 #[account]
 #[derive(Default, Debug)]
 pub struct Pool {
@@ -35,6 +62,10 @@ pub struct Pool {
     pub custodies: Vec<Pubkey>,
     pub ratios: Vec<TokenRatios>,
     pub aum_usd: u128,
+    // sorted by min_volume_usd
+    pub fee_tiers: Vec<FeeTier>,
+    pub withdrawal: WithdrawalParams,
+    pub withdrawal_state: WithdrawalState,
 
     pub bump: u8,
     pub lp_token_bump: u8,
@@ -51,12 +82,26 @@ impl TokenRatios {
     }
 }
 
+impl FeeTier {
+    pub fn validate(&self) -> bool {
+        (self.discount as u128) <= Perpetuals::BPS_POWER
+    }
+}
+
+impl WithdrawalParams {
+    pub fn validate(&self) -> bool {
+        (self.max_epoch_outflow as u128) <= Perpetuals::BPS_POWER
+            && (self.max_epoch_outflow == 0 || self.epoch_sec > 0)
+    }
+}
+
 /// Token Pool
 /// All returned prices are scaled to PRICE_DECIMALS.
 /// All returned amounts are scaled to corresponding custody decimals.
 ///
 impl Pool {
     pub const LEN: usize = 8 + 64 + std::mem::size_of::<Pool>();
+    pub const MAX_FEE_TIERS: usize = 16;
 
     pub fn validate(&self) -> bool {
         for ratio in &self.ratios {
@@ -77,6 +122,21 @@ impl Pool {
             return false;
         }
 
+        // check fee tiers are strictly increasing
+        if self.fee_tiers.len() > Self::MAX_FEE_TIERS
+            || self.fee_tiers.iter().any(|tier| !tier.validate())
+            || self
+                .fee_tiers
+                .windows(2)
+                .any(|tiers| tiers[0].min_volume_usd >= tiers[1].min_volume_usd)
+        {
+            return false;
+        }
+
+        if !self.withdrawal.validate() {
+            return false;
+        }
+
         // check custodies are unique
         for i in 1..self.custodies.len() {
             if self.custodies[i..].contains(&self.custodies[i - 1]) {
@@ -100,16 +160,21 @@ impl Pool {
         token_ema_price: &OraclePrice,
         side: Side,
         custody: &Custody,
+        size_usd: u64,
     ) -> Result<u64> {
+        let spread = if side == Side::Long {
+            custody.pricing.trade_spread_long
+        } else {
+            custody.pricing.trade_spread_short
+        };
+        let price_impact = self.get_price_impact(side, size_usd, true, custody)?;
+        let conf_spread = token_price.get_conf_spread(custody.pricing.conf_spread_mult)?;
+
         let price = self.get_price(
             token_price,
             token_ema_price,
             side,
-            if side == Side::Long {
-                custody.pricing.trade_spread_long
-            } else {
-                custody.pricing.trade_spread_short
-            },
+            math::checked_add(math::checked_add(spread, price_impact)?, conf_spread)?,
         )?;
         require_gt!(price.price, 0, PerpetualsError::MaxPriceSlippage);
 
@@ -118,12 +183,22 @@ impl Pool {
             .price)
     }
 
+    // discount of the highest fee tier reached by the trader's 30-day volume
+    pub fn get_fee_discount(&self, volume_usd: u64) -> u64 {
+        self.fee_tiers
+            .iter()
+            .rev()
+            .find(|tier| volume_usd >= tier.min_volume_usd)
+            .map_or(0, |tier| tier.discount)
+    }
+
     pub fn get_entry_fee(
         &self,
         base_fee: u64,
         size: u64,
         locked_amount: u64,
         collateral_custody: &Custody,
+        volume_usd: u64,
     ) -> Result<u64> {
         // The "optimal" algorithm is always used to compute the fee for entering a position.
         // entry_fee = custody.fees.open_position * utilization_fee * size
@@ -170,7 +245,8 @@ impl Pool {
             )?)?;
         }
 
-        Ok(size_fee)
+        let discount = Self::get_fee_amount(self.get_fee_discount(volume_usd), size_fee)?;
+        math::checked_sub(size_fee, discount)
     }
 
     pub fn get_exit_price(
@@ -179,7 +255,16 @@ impl Pool {
         token_ema_price: &OraclePrice,
         side: Side,
         custody: &Custody,
+        size_usd: u64,
     ) -> Result<u64> {
+        let spread = if side == Side::Long {
+            custody.pricing.trade_spread_short
+        } else {
+            custody.pricing.trade_spread_long
+        };
+        let price_impact = self.get_price_impact(side, size_usd, false, custody)?;
+        let conf_spread = token_price.get_conf_spread(custody.pricing.conf_spread_mult)?;
+
         let price = self.get_price(
             token_price,
             token_ema_price,
@@ -188,11 +273,7 @@ impl Pool {
             } else {
                 Side::Long
             },
-            if side == Side::Long {
-                custody.pricing.trade_spread_short
-            } else {
-                custody.pricing.trade_spread_long
-            },
+            math::checked_add(math::checked_add(spread, price_impact)?, conf_spread)?,
         )?;
 
         Ok(price
@@ -200,8 +281,51 @@ impl Pool {
             .price)
     }
 
-    pub fn get_exit_fee(&self, size: u64, custody: &Custody) -> Result<u64> {
-        Self::get_fee_amount(custody.fees.close_position, size)
+    // Returns additional spread with implied BPS_DECIMALS decimals charged for the skew a trade
+    // adds to the custody open interest. Trades that reduce the skew have no price impact.
+    pub fn get_price_impact(
+        &self,
+        side: Side,
+        size_usd: u64,
+        is_entry: bool,
+        custody: &Custody,
+    ) -> Result<u64> {
+        if custody.pricing.price_impact_depth_usd == 0 || size_usd == 0 {
+            return Ok(0);
+        }
+
+        let oi_long_usd = custody.trade_stats.oi_long_usd;
+        let oi_short_usd = custody.trade_stats.oi_short_usd;
+        let (new_oi_long_usd, new_oi_short_usd) = match (side, is_entry) {
+            (Side::Long, true) => (math::checked_add(oi_long_usd, size_usd)?, oi_short_usd),
+            (Side::Long, false) => (oi_long_usd.saturating_sub(size_usd), oi_short_usd),
+            (Side::Short, true) => (oi_long_usd, math::checked_add(oi_short_usd, size_usd)?),
+            (Side::Short, false) => (oi_long_usd, oi_short_usd.saturating_sub(size_usd)),
+            (Side::None, _) => return Ok(0),
+        };
+
+        let cur_skew_usd = oi_long_usd.abs_diff(oi_short_usd);
+        let new_skew_usd = new_oi_long_usd.abs_diff(new_oi_short_usd);
+        if new_skew_usd <= cur_skew_usd {
+            return Ok(0);
+        }
+
+        // price_impact = added_skew / depth
+        let price_impact = math::checked_as_u64(math::checked_div(
+            math::checked_mul(
+                math::checked_sub(new_skew_usd, cur_skew_usd)? as u128,
+                Perpetuals::BPS_POWER,
+            )?,
+            custody.pricing.price_impact_depth_usd as u128,
+        )?)?;
+
+        Ok(std::cmp::min(price_impact, custody.pricing.max_price_impact))
+    }
+
+    pub fn get_exit_fee(&self, size: u64, custody: &Custody, volume_usd: u64) -> Result<u64> {
+        let size_fee = Self::get_fee_amount(custody.fees.close_position, size)?;
+        let discount = Self::get_fee_amount(self.get_fee_discount(volume_usd), size_fee)?;
+        math::checked_sub(size_fee, discount)
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -229,6 +353,29 @@ impl Pool {
             liquidation,
         )?;
 
+        self.get_close_amount_from_pnl(
+            position,
+            profit_usd,
+            loss_usd,
+            fee_amount,
+            collateral_token_price,
+            collateral_token_ema_price,
+            collateral_custody,
+        )
+    }
+
+    // returns (close_amount, fee_amount, profit_usd, loss_usd) for already computed pnl
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_close_amount_from_pnl(
+        &self,
+        position: &Position,
+        profit_usd: u64,
+        loss_usd: u64,
+        fee_amount: u64,
+        collateral_token_price: &OraclePrice,
+        collateral_token_ema_price: &OraclePrice,
+        collateral_custody: &Custody,
+    ) -> Result<(u64, u64, u64, u64)> {
         let available_amount_usd = if profit_usd > 0 {
             math::checked_add(position.collateral_usd, profit_usd)?
         } else if loss_usd < position.collateral_usd {
@@ -396,6 +543,81 @@ impl Pool {
         Self::get_fee_amount(custody.fees.liquidation, size)
     }
 
+    // returns (trader_rebate, referrer_kickback) carved out of the fee of a referred trader
+    pub fn get_referral_amounts(fee_amount: u64, custody: &Custody) -> Result<(u64, u64)> {
+        let rebate = Self::get_fee_amount(custody.fees.referral_rebate, fee_amount)?;
+        let kickback = Self::get_fee_amount(
+            custody.fees.referral_kickback,
+            math::checked_sub(fee_amount, rebate)?,
+        )?;
+        Ok((rebate, kickback))
+    }
+
+    // returns (remove_amount, fee_amount, remove_amount_usd) for burning lp_amount_in lp tokens
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_remove_liquidity_amount(
+        &self,
+        token_id: usize,
+        lp_amount_in: u64,
+        lp_supply: u64,
+        pool_amount_usd: u128,
+        custody: &Custody,
+        token_price: &OraclePrice,
+        token_ema_price: &OraclePrice,
+    ) -> Result<(u64, u64, u64)> {
+        let remove_amount_usd = math::checked_as_u64(math::checked_div(
+            math::checked_mul(pool_amount_usd, lp_amount_in as u128)?,
+            lp_supply as u128,
+        )?)?;
+
+        let max_price = if token_price > token_ema_price {
+            token_price
+        } else {
+            token_ema_price
+        };
+        let remove_amount = max_price.get_token_amount(remove_amount_usd, custody.decimals)?;
+
+        let fee_amount =
+            self.get_remove_liquidity_fee(token_id, remove_amount, custody, token_ema_price)?;
+
+        Ok((remove_amount, fee_amount, remove_amount_usd))
+    }
+
+    // tracks withdrawals against the per-epoch outflow cap
+    pub fn record_withdrawal(
+        &mut self,
+        amount_usd: u64,
+        pool_amount_usd: u128,
+        curtime: i64,
+    ) -> Result<()> {
+        if self.withdrawal.max_epoch_outflow == 0 {
+            return Ok(());
+        }
+
+        let state = &mut self.withdrawal_state;
+        let epoch_end = math::checked_add(state.epoch_start_time, self.withdrawal.epoch_sec as i64)?;
+        if curtime >= epoch_end {
+            state.epoch_start_time = curtime;
+            state.epoch_start_aum_usd = pool_amount_usd;
+            state.epoch_outflow_usd = 0;
+        }
+
+        let max_outflow_usd = math::checked_div(
+            math::checked_mul(
+                state.epoch_start_aum_usd,
+                self.withdrawal.max_epoch_outflow as u128,
+            )?,
+            Perpetuals::BPS_POWER,
+        )?;
+        state.epoch_outflow_usd = math::checked_add(state.epoch_outflow_usd, amount_usd)?;
+        require!(
+            state.epoch_outflow_usd as u128 <= max_outflow_usd,
+            PerpetualsError::WithdrawalLimit
+        );
+
+        Ok(())
+    }
+
     pub fn check_token_ratio(
         &self,
         token_id: usize,
@@ -510,13 +732,15 @@ impl Pool {
         }
 
         let size = token_ema_price.get_token_amount(position.size_usd, custody.decimals)?;
-        let exit_fee_tokens = self.get_exit_fee(size, custody)?;
+        let exit_fee_tokens = self.get_exit_fee(size, custody, 0)?;
         let exit_fee_usd =
             token_ema_price.get_asset_amount_usd(exit_fee_tokens, custody.decimals)?;
         let interest_usd = collateral_custody.get_interest_amount_usd(position, curtime)?;
+        let (dividend_profit_usd, dividend_loss_usd) =
+            Self::get_dividend_pnl_usd(position, custody, curtime)?;
         let unrealized_loss_usd = math::checked_add(
             math::checked_add(exit_fee_usd, interest_usd)?,
-            position.unrealized_loss_usd,
+            math::checked_add(position.unrealized_loss_usd, dividend_loss_usd)?,
         )?;
 
         let max_loss_usd = math::checked_as_u64(math::checked_div(
@@ -525,8 +749,10 @@ impl Pool {
         )?)?;
         let max_loss_usd = math::checked_add(max_loss_usd, unrealized_loss_usd)?;
 
-        let margin_usd =
-            math::checked_add(position.collateral_usd, position.unrealized_profit_usd)?;
+        let margin_usd = math::checked_add(
+            position.collateral_usd,
+            math::checked_add(position.unrealized_profit_usd, dividend_profit_usd)?,
+        )?;
 
         let max_price_diff = if max_loss_usd >= margin_usd {
             math::checked_sub(max_loss_usd, margin_usd)?
@@ -588,22 +814,60 @@ impl Pool {
             return Ok((0, 0, 0));
         }
 
+        // valuation excludes price impact, closing a whole collective position at once would
+        // distort AUM and margin checks, impact is only charged on actual exits
         let exit_price =
-            self.get_exit_price(token_price, token_ema_price, position.side, custody)?;
+            self.get_exit_price(token_price, token_ema_price, position.side, custody, 0)?;
 
         let size = token_ema_price.get_token_amount(position.size_usd, custody.decimals)?;
 
+        // fee tier discounts are not known here, margin and AUM checks use the full fee
         let exit_fee = if liquidation {
             self.get_liquidation_fee(size, custody)?
         } else {
-            self.get_exit_fee(size, custody)?
+            self.get_exit_fee(size, custody, 0)?
         };
 
+        self.get_pnl_usd_at_price(
+            position,
+            exit_price,
+            exit_fee,
+            token_ema_price,
+            custody,
+            collateral_token_price,
+            collateral_token_ema_price,
+            collateral_custody,
+            curtime,
+        )
+    }
+
+    // returns (profit_usd, loss_usd, fee_amount) for the given exit price and fee
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_pnl_usd_at_price(
+        &self,
+        position: &Position,
+        exit_price: u64,
+        exit_fee: u64,
+        token_ema_price: &OraclePrice,
+        custody: &Custody,
+        collateral_token_price: &OraclePrice,
+        collateral_token_ema_price: &OraclePrice,
+        collateral_custody: &Custody,
+        curtime: i64,
+    ) -> Result<(u64, u64, u64)> {
+        if position.size_usd == 0 || position.price == 0 {
+            return Ok((0, 0, 0));
+        }
+
         let exit_fee_usd = token_ema_price.get_asset_amount_usd(exit_fee, custody.decimals)?;
         let interest_usd = collateral_custody.get_interest_amount_usd(position, curtime)?;
+        let (dividend_profit_usd, dividend_loss_usd) =
+            Self::get_dividend_pnl_usd(position, custody, curtime)?;
+        let unrealized_profit_usd =
+            math::checked_add(position.unrealized_profit_usd, dividend_profit_usd)?;
         let unrealized_loss_usd = math::checked_add(
             math::checked_add(exit_fee_usd, interest_usd)?,
-            position.unrealized_loss_usd,
+            math::checked_add(position.unrealized_loss_usd, dividend_loss_usd)?,
         )?;
 
         let (price_diff_profit, price_diff_loss) = if position.side == Side::Long {
@@ -631,7 +895,7 @@ impl Pool {
             )?)?;
 
             let potential_profit_usd =
-                math::checked_add(potential_profit_usd, position.unrealized_profit_usd)?;
+                math::checked_add(potential_profit_usd, unrealized_profit_usd)?;
 
             if potential_profit_usd >= unrealized_loss_usd {
                 let cur_profit_usd = math::checked_sub(potential_profit_usd, unrealized_loss_usd)?;
@@ -642,6 +906,7 @@ impl Pool {
                     OraclePrice {
                         price: 10u64.pow(Perpetuals::USD_DECIMALS as u32),
                         exponent: -(Perpetuals::USD_DECIMALS as i32),
+                        conf: 0,
                     }
                 } else {
                     collateral_token_price
@@ -673,19 +938,20 @@ impl Pool {
 
             let potential_loss_usd = math::checked_add(potential_loss_usd, unrealized_loss_usd)?;
 
-            if potential_loss_usd >= position.unrealized_profit_usd {
+            if potential_loss_usd >= unrealized_profit_usd {
                 Ok((
                     0u64,
-                    math::checked_sub(potential_loss_usd, position.unrealized_profit_usd)?,
+                    math::checked_sub(potential_loss_usd, unrealized_profit_usd)?,
                     exit_fee,
                 ))
             } else {
                 let cur_profit_usd =
-                    math::checked_sub(position.unrealized_profit_usd, potential_loss_usd)?;
+                    math::checked_sub(unrealized_profit_usd, potential_loss_usd)?;
                 let min_collateral_price = if collateral_custody.is_virtual {
                     OraclePrice {
                         price: 10u64.pow(Perpetuals::USD_DECIMALS as u32),
                         exponent: -(Perpetuals::USD_DECIMALS as i32),
+                        conf: 0,
                     }
                 } else {
                     collateral_token_price
@@ -725,8 +991,13 @@ impl Pool {
 
             require_keys_eq!(accounts[oracle_idx].key(), custody.oracle.oracle_account);
 
+            // secondary oracles are optional and follow the primary ones
+            let secondary_oracle_idx = oracle_idx + self.custodies.len();
+            let secondary_oracle_account = accounts.get(secondary_oracle_idx);
+
             let token_price = OraclePrice::new_from_oracle(
                 &accounts[oracle_idx],
+                secondary_oracle_account,
                 &custody.oracle,
                 curtime,
                 false,
@@ -734,6 +1005,7 @@ impl Pool {
 
             let token_ema_price = OraclePrice::new_from_oracle(
                 &accounts[oracle_idx],
+                secondary_oracle_account,
                 &custody.oracle,
                 curtime,
                 custody.pricing.use_ema,
@@ -742,6 +1014,9 @@ impl Pool {
             let aum_token_price = match aum_calc_mode {
                 AumCalcMode::Last => token_price,
                 AumCalcMode::EMA => token_ema_price,
+                // custodies other than the one being traded can't record samples here, a stale
+                // TWAP fails instead of trusting the oracle EMA, see update_oracle_history
+                AumCalcMode::Twap => custody.get_reference_price(&token_ema_price, curtime)?,
                 AumCalcMode::Min => {
                     if token_price < token_ema_price {
                         token_price
@@ -812,6 +1087,37 @@ impl Pool {
         Ok(pool_amount_usd)
     }
 
+    // auto-deleveraging rank, profit ratio multiplied by leverage (both in BPS)
+    pub fn get_adl_score(position: &Position, profit_usd: u64) -> Result<u128> {
+        if profit_usd == 0 || position.collateral_usd == 0 {
+            return Ok(0);
+        }
+        let profit_ratio = math::checked_div(
+            math::checked_mul(profit_usd as u128, Perpetuals::BPS_POWER)?,
+            position.collateral_usd as u128,
+        )?;
+        let leverage = math::checked_div(
+            math::checked_mul(position.size_usd as u128, Perpetuals::BPS_POWER)?,
+            position.collateral_usd as u128,
+        )?;
+        math::checked_div(math::checked_mul(profit_ratio, leverage)?, Perpetuals::BPS_POWER)
+    }
+
+    // returns (dividend_profit_usd, dividend_loss_usd)
+    pub fn get_dividend_pnl_usd(
+        position: &Position,
+        custody: &Custody,
+        curtime: i64,
+    ) -> Result<(u64, u64)> {
+        // longs receive dividends and shorts pay them, same as holders and borrowers of the stock
+        let dividend_usd = custody.get_dividend_amount_usd(position, curtime)?;
+        if position.side == Side::Long {
+            Ok((dividend_usd, 0))
+        } else {
+            Ok((0, dividend_usd))
+        }
+    }
+
     pub fn get_fee_amount(fee: u64, amount: u64) -> Result<u64> {
         if fee == 0 || amount == 0 {
             return Ok(0);
@@ -918,6 +1224,7 @@ impl Pool {
                     )?,
                 )?,
                 exponent: max_price.exponent,
+                conf: max_price.conf,
             })
         } else {
             let min_price = if token_price < token_ema_price {
@@ -943,6 +1250,7 @@ impl Pool {
             Ok(OraclePrice {
                 price,
                 exponent: min_price.exponent,
+                conf: min_price.conf,
             })
         }
     }
@@ -49,6 +49,8 @@ pub struct Position {
     pub cumulative_interest_snapshot: u128, // interest/funding snapshot 
     pub locked_amount: u64, // net amount locked for this posn
     pub collateral_amount: u64, // actual collateral amount
+    pub cumulative_dividend_snapshot: u128, // dividend index at open, see Custody::dividends
+    pub index: u32, // 0 for the legacy position per side, see PositionCounter
 
     pub bump: u8,
 }
@@ -56,6 +58,15 @@ pub struct Position {
 impl Position {
     pub const LEN: usize = 8 + std::mem::size_of::<Position>();
 
+    // index 0 keeps the original [b"position", owner, pool, custody, side] address
+    pub fn get_index_seed(index: u32) -> Vec<u8> {
+        if index == 0 {
+            Vec::new()
+        } else {
+            index.to_le_bytes().to_vec()
+        }
+    }
+
     pub fn get_initial_leverage(&self) -> Result<u64> {
         math::checked_as_u64(math::checked_div(
             math::checked_mul(self.size_usd as u128, Perpetuals::BPS_POWER)?,
@@ -0,0 +1,33 @@
+use {
+    crate::{error::PerpetualsError, math},
+    anchor_lang::prelude::*,
+};
+
+// numbers the additional positions of an owner, index 0 is the legacy position per side
+#[account]
+#[derive(Default, Debug)]
+pub struct PositionCounter {
+    pub owner: Pubkey,
+    // index to be used by the next position with a non-zero index
+    pub next_index: u32,
+
+    pub bump: u8,
+}
+
+impl PositionCounter {
+    pub const LEN: usize = 8 + std::mem::size_of::<PositionCounter>();
+
+    // initializes the counter on first use and consumes the next index for non-zero indices
+    pub fn use_index(&mut self, owner: Pubkey, index: u32, bump: u8) -> Result<()> {
+        if self.owner == Pubkey::default() {
+            self.owner = owner;
+            self.next_index = 1;
+            self.bump = bump;
+        }
+        if index != 0 {
+            require_eq!(index, self.next_index, PerpetualsError::InvalidPositionIndex);
+            self.next_index = math::checked_add(self.next_index, 1)?;
+        }
+        Ok(())
+    }
+}
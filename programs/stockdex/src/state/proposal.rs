@@ -0,0 +1,47 @@
+use {
+    crate::{error::PerpetualsError, state::multisig::Multisig},
+    anchor_lang::prelude::*,
+};
+
+// pending admin instruction with its own signatures, bound to the first instruction signed with it
+#[account]
+#[derive(Default, Debug)]
+pub struct Proposal {
+    pub id: u64,
+    pub proposer: Pubkey,
+    pub instruction_hash: [u8; 32],
+    pub instruction_accounts_len: u8,
+    pub instruction_data_len: u16,
+    pub signed_weight: u8,
+    pub signed: [u8; Multisig::MAX_SIGNERS],
+    // signer set the proposal was bound to, see Multisig::signers_version
+    pub signers_version: u32,
+    pub creation_time: i64,
+    pub expiry_time: i64,
+    // earliest execution time once quorum is reached with a timelock, 0 if not queued
+    pub queued_eta: i64,
+    pub executed: bool,
+
+    pub bump: u8,
+}
+
+impl Proposal {
+    pub const LEN: usize = 8 + std::mem::size_of::<Proposal>();
+    pub const MAX_EXPIRY_SEC: u32 = 30 * 86_400;
+
+    pub fn is_bound(&self) -> bool {
+        self.signed_weight > 0 || self.queued_eta != 0
+    }
+
+    pub fn check_pending(&self, curtime: i64) -> Result<()> {
+        require!(!self.executed, PerpetualsError::MultisigAlreadyExecuted);
+        require!(curtime < self.expiry_time, PerpetualsError::ProposalExpired);
+        Ok(())
+    }
+
+    // expires the proposal so it can no longer be signed or executed
+    pub fn cancel(&mut self, curtime: i64) {
+        self.expiry_time = std::cmp::min(self.expiry_time, curtime);
+        self.queued_eta = 0;
+    }
+}
@@ -0,0 +1,65 @@
+use {crate::error::PerpetualsError, anchor_lang::prelude::*};
+
+// links a trader to the partner that onboarded them, set once by the trader
+#[account]
+#[derive(Default, Debug)]
+pub struct Referral {
+    pub trader: Pubkey,
+    pub referrer: Pubkey,
+
+    pub bump: u8,
+}
+
+// partner allowed to refer traders, approved by the admins so traders can't refer themselves
+#[account]
+#[derive(Default, Debug)]
+pub struct Referrer {
+    pub owner: Pubkey,
+    pub is_approved: bool,
+
+    pub bump: u8,
+}
+
+// claimable referrer payouts, kept per collateral custody since kickbacks are paid in fee tokens
+#[account]
+#[derive(Default, Debug)]
+pub struct ReferralRewards {
+    pub referrer: Pubkey,
+    pub custody: Pubkey,
+    pub claimable_amount: u64,
+    pub total_earned_amount: u64,
+
+    pub bump: u8,
+}
+
+impl Referral {
+    pub const LEN: usize = 8 + std::mem::size_of::<Referral>();
+
+    // referral accounts are optional but must be passed together and belong to the same referrer
+    pub fn is_applied(
+        referral: Option<&Referral>,
+        referral_rewards: Option<&ReferralRewards>,
+        collateral_custody: &Pubkey,
+    ) -> Result<bool> {
+        match (referral, referral_rewards) {
+            (None, None) => Ok(false),
+            (Some(referral), Some(referral_rewards)) => {
+                require!(
+                    referral_rewards.referrer == referral.referrer
+                        && referral_rewards.custody == *collateral_custody,
+                    PerpetualsError::InvalidReferral
+                );
+                Ok(true)
+            }
+            _ => err!(PerpetualsError::InvalidReferral),
+        }
+    }
+}
+
+impl Referrer {
+    pub const LEN: usize = 8 + std::mem::size_of::<Referrer>();
+}
+
+impl ReferralRewards {
+    pub const LEN: usize = 8 + std::mem::size_of::<ReferralRewards>();
+}
@@ -0,0 +1,191 @@
+use {crate::math, anchor_lang::prelude::*};
+
+#[account]
+#[derive(Default, Debug)]
+pub struct UserStats {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+
+    // traded notional per day, the bucket of a day is day % VOLUME_WINDOW_DAYS
+    pub daily_volume_usd: [u64; UserStats::VOLUME_WINDOW_DAYS],
+    // day (unix time / SECONDS_PER_DAY) of the most recent bucket
+    pub last_volume_day: i64,
+    // withdrawals are subject to the pool cooldown after each deposit
+    pub last_add_liquidity_time: i64,
+
+    // lifetime trading totals, liquidations count as closes
+    pub total_volume_usd: u64,
+    pub total_fees_usd: u64,
+    pub total_profit_usd: u64,
+    pub total_loss_usd: u64,
+    pub position_count: u64,
+    pub liquidation_count: u64,
+
+    pub bump: u8,
+}
+
+impl UserStats {
+    pub const LEN: usize = 8 + std::mem::size_of::<UserStats>();
+    pub const VOLUME_WINDOW_DAYS: usize = 30;
+    pub const SECONDS_PER_DAY: i64 = 86_400;
+
+    // rolling 30-day traded notional, including today
+    pub fn get_volume_usd(&self, curtime: i64) -> Result<u64> {
+        let today = math::checked_div(curtime, Self::SECONDS_PER_DAY)?;
+        let window = Self::VOLUME_WINDOW_DAYS as i64;
+        // buckets only hold the days up to last_volume_day, older days were overwritten
+        let last_day = std::cmp::max(self.last_volume_day, today);
+        let first_day = std::cmp::max(
+            math::checked_add(math::checked_sub(last_day, window)?, 1)?,
+            0,
+        );
+
+        let mut volume_usd = 0u64;
+        let mut day = first_day;
+        while day <= std::cmp::min(self.last_volume_day, today) {
+            volume_usd = volume_usd.saturating_add(self.daily_volume_usd[(day % window) as usize]);
+            day = math::checked_add(day, 1)?;
+        }
+
+        Ok(volume_usd)
+    }
+
+    pub fn add_volume(&mut self, size_usd: u64, curtime: i64) -> Result<()> {
+        let today = math::checked_div(curtime, Self::SECONDS_PER_DAY)?;
+        let window = Self::VOLUME_WINDOW_DAYS as i64;
+
+        // clear buckets of the days that passed since the last update
+        if today > self.last_volume_day {
+            let mut day = math::checked_add(self.last_volume_day, 1)?;
+            let last_day = std::cmp::min(today, math::checked_add(self.last_volume_day, window)?);
+            while day <= last_day {
+                self.daily_volume_usd[(day % window) as usize] = 0;
+                day = math::checked_add(day, 1)?;
+            }
+            self.last_volume_day = today;
+        }
+
+        let bucket = (today % window) as usize;
+        self.daily_volume_usd[bucket] = self.daily_volume_usd[bucket].saturating_add(size_usd);
+
+        Ok(())
+    }
+
+    pub fn record_open(&mut self, size_usd: u64, fee_usd: u64) {
+        self.total_volume_usd = self.total_volume_usd.saturating_add(size_usd);
+        self.total_fees_usd = self.total_fees_usd.saturating_add(fee_usd);
+        self.position_count = self.position_count.saturating_add(1);
+    }
+
+    pub fn record_close(
+        &mut self,
+        size_usd: u64,
+        fee_usd: u64,
+        profit_usd: u64,
+        loss_usd: u64,
+        liquidated: bool,
+    ) {
+        self.total_volume_usd = self.total_volume_usd.saturating_add(size_usd);
+        self.total_fees_usd = self.total_fees_usd.saturating_add(fee_usd);
+        self.total_profit_usd = self.total_profit_usd.saturating_add(profit_usd);
+        self.total_loss_usd = self.total_loss_usd.saturating_add(loss_usd);
+        if liquidated {
+            self.liquidation_count = self.liquidation_count.saturating_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DAY: i64 = UserStats::SECONDS_PER_DAY;
+    const START_DAY: i64 = 19_000;
+
+    fn get_time(day: i64) -> i64 {
+        (START_DAY + day) * DAY + DAY / 2
+    }
+
+    #[test]
+    fn test_add_volume() {
+        let mut user_stats = UserStats::default();
+        user_stats.add_volume(100, get_time(0)).unwrap();
+        user_stats.add_volume(50, get_time(0) + DAY / 4).unwrap();
+        user_stats.add_volume(25, get_time(1)).unwrap();
+
+        assert_eq!(user_stats.last_volume_day, START_DAY + 1);
+        assert_eq!(user_stats.get_volume_usd(get_time(1)).unwrap(), 175);
+    }
+
+    #[test]
+    fn test_volume_window_rollover() {
+        let mut user_stats = UserStats::default();
+        user_stats.add_volume(100, get_time(0)).unwrap();
+        user_stats.add_volume(10, get_time(5)).unwrap();
+
+        // day 29 is the last day with day 0 in the window
+        assert_eq!(user_stats.get_volume_usd(get_time(29)).unwrap(), 110);
+        assert_eq!(user_stats.get_volume_usd(get_time(30)).unwrap(), 10);
+
+        // day 30 reuses the bucket of day 0, which has to be cleared first
+        user_stats.add_volume(1, get_time(30)).unwrap();
+        assert_eq!(user_stats.get_volume_usd(get_time(30)).unwrap(), 11);
+        assert_eq!(user_stats.get_volume_usd(get_time(35)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_volume_skipped_days() {
+        let mut user_stats = UserStats::default();
+        for day in 0..UserStats::VOLUME_WINDOW_DAYS as i64 {
+            user_stats.add_volume(1, get_time(day)).unwrap();
+        }
+        assert_eq!(user_stats.get_volume_usd(get_time(29)).unwrap(), 30);
+
+        // skipping days clears their stale buckets
+        user_stats.add_volume(100, get_time(40)).unwrap();
+        assert_eq!(user_stats.get_volume_usd(get_time(40)).unwrap(), 119);
+    }
+
+    #[test]
+    fn test_volume_gap_longer_than_window() {
+        let mut user_stats = UserStats::default();
+        for day in 0..3 {
+            user_stats.add_volume(1_000, get_time(day)).unwrap();
+        }
+
+        user_stats.add_volume(7, get_time(100)).unwrap();
+        assert_eq!(user_stats.get_volume_usd(get_time(100)).unwrap(), 7);
+        assert_eq!(user_stats.daily_volume_usd.iter().sum::<u64>(), 7);
+
+        // nothing traded within the window
+        assert_eq!(user_stats.get_volume_usd(get_time(200)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_volume_before_last_update() {
+        let mut user_stats = UserStats::default();
+        user_stats.add_volume(100, get_time(10)).unwrap();
+        user_stats.add_volume(10, get_time(11)).unwrap();
+
+        // buckets after the queried day are not counted, and neither are the days before
+        // the window of last_volume_day as their buckets were reused
+        assert_eq!(user_stats.get_volume_usd(get_time(10)).unwrap(), 100);
+        assert_eq!(user_stats.get_volume_usd(get_time(11)).unwrap(), 110);
+    }
+
+    #[test]
+    fn test_record_open_and_close() {
+        let mut user_stats = UserStats {
+            total_volume_usd: u64::MAX - 1,
+            ..UserStats::default()
+        };
+        user_stats.record_open(10, 1);
+        user_stats.record_close(10, 1, 5, 0, true);
+
+        assert_eq!(user_stats.total_volume_usd, u64::MAX);
+        assert_eq!(user_stats.total_fees_usd, 2);
+        assert_eq!(user_stats.total_profit_usd, 5);
+        assert_eq!(user_stats.position_count, 1);
+        assert_eq!(user_stats.liquidation_count, 1);
+    }
+}
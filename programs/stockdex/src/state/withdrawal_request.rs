@@ -0,0 +1,20 @@
+use anchor_lang::prelude::*;
+
+// pending two-step liquidity removal, lp tokens are held in the pool withdrawal escrow
+#[account]
+#[derive(Default, Debug)]
+pub struct WithdrawalRequest {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    // custody of the token to withdraw
+    pub custody: Pubkey,
+    pub lp_amount: u64,
+    pub min_amount_out: u64,
+    pub request_time: i64,
+
+    pub bump: u8,
+}
+
+impl WithdrawalRequest {
+    pub const LEN: usize = 8 + std::mem::size_of::<WithdrawalRequest>();
+}